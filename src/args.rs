@@ -1,11 +1,15 @@
 use std::path::PathBuf;
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[command(name = "nya")]
 #[command(version = "preview-1")]
 #[command(about = "Nya is framework that lets you build your own platform, anywhere you want.", long_about = None)]
 pub struct Cli {
+  /// Only print step summaries. Output from the nodes still goes to ~/.nya/logs/
+  #[arg(short, long, global = true)]
+  pub quiet: bool,
+
   #[command(subcommand)]
   pub command: Commands
 }
@@ -38,6 +42,84 @@ pub enum Commands {
 
     #[arg(short, long)]
     location: Option<PathBuf>,
+
+    /// Only ship this pack. Repeat to ship several
+    #[arg(short, long = "pack")]
+    packs: Vec<String>,
+
+    /// Ship packs even when nothing changed since their last ship
+    #[arg(long)]
+    force: bool,
+
+    /// Ship into a preview environment for the current git branch
+    #[arg(long)]
+    preview: bool,
+  },
+
+  /// Preview environments shipped with `nya ship --preview`
+  Preview {
+    #[command(subcommand)]
+    command: PreviewCommands,
+  },
+
+  /// List the Helm revisions of a pack with their image tags
+  History {
+    pack: String,
+
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    #[arg(short, long)]
+    location: Option<PathBuf>,
+  },
+
+  /// Roll a pack back to an earlier Helm revision
+  Rollback {
+    pack: String,
+
+    /// Revision to roll back to, from `nya history`. Defaults to the one before the live revision
+    #[arg(long)]
+    to: Option<u32>,
+
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    #[arg(short, long)]
+    location: Option<PathBuf>,
+  },
+
+  /// Run a command or script on base nodes, e.g. `nya exec --all -- uptime`
+  #[command(group(ArgGroup::new("target").required(true).args(["node", "role", "all"])))]
+  Exec {
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Run on the node with this host
+    #[arg(long)]
+    node: Option<String>,
+
+    /// Run on every node with this role: control_plane or node
+    #[arg(long)]
+    role: Option<String>,
+
+    /// Run on every node
+    #[arg(long)]
+    all: bool,
+
+    /// Run a local script instead, by path or by name from ~/.nya/scripts/. Trailing arguments are passed to it
+    #[arg(long)]
+    script: Option<PathBuf>,
+
+    /// Run as root
+    #[arg(long)]
+    sudo: bool,
+
+    /// How many nodes to run on at once
+    #[arg(long, default_value_t = 10)]
+    concurrency: usize,
+
+    #[arg(last = true)]
+    command: Vec<String>,
   },
 }
 
@@ -46,10 +128,85 @@ pub enum BaseCommands {
   Build {
     #[arg(short, long)]
     config: Option<PathBuf>,
+
+    /// Install from an offline bundle created with `nya base bundle`
+    #[arg(short, long)]
+    bundle: Option<PathBuf>,
+  },
+  #[command(name = "cloud-init")]
+  CloudInit {
+    /// Host of the node, as it appears in the base config
+    host: String,
+
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Write the user-data here instead of printing it
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Hostname to give the machine
+    #[arg(long)]
+    hostname: Option<String>,
+
+    /// Preinstall Docker
+    #[arg(long)]
+    docker: bool,
+  },
+  /// Upgrade OS packages on every node, one node at a time
+  Patch {
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+  },
+  /// Collect CPU, memory, disk and allocatable resources from every node
+  Inventory {
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+  },
+  /// Snapshot the k3s datastore, registry, DNS zones and CA
+  Backup {
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Directory, file or s3:// URL to write the archive to
+    #[arg(short, long)]
+    output: Option<String>,
+  },
+  /// Rebuild a destroyed control plane from a backup
+  Restore {
+    /// Backup archive, as a local path or s3:// URL
+    archive: String,
+
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+  },
+  Bundle {
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Where to write the bundle archive
+    #[arg(short, long)]
+    output: Option<PathBuf>,
   },
   Destroy{
     #[arg(short, long)]
     config: Option<PathBuf>,
+
+    /// Only remove these components (comma separated)
+    #[arg(long, value_delimiter = ',')]
+    only: Vec<String>,
+
+    /// Keep these components (comma separated)
+    #[arg(long, value_delimiter = ',')]
+    keep: Vec<String>,
+
+    /// Only tear down the machine with this host
+    #[arg(long)]
+    node: Option<String>,
+
+    /// Skip the confirmation prompt
+    #[arg(short, long)]
+    yes: bool,
   },
 }
 
@@ -59,6 +216,42 @@ pub enum CapsuleCommands {
     #[arg(short, long)]
     config: Option<PathBuf>,
   },
+  /// Uninstall the capsule's releases and remove its namespace
+  Delete {
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    #[arg(short, long)]
+    location: Option<PathBuf>,
+
+    /// Skip the confirmation prompt
+    #[arg(short, long)]
+    yes: bool,
+  },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PreviewCommands {
+  /// List the capsule's previews and whether their branches still exist
+  List {
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    #[arg(short, long)]
+    location: Option<PathBuf>,
+  },
+  /// Delete the previews of branches that no longer exist
+  Prune {
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    #[arg(short, long)]
+    location: Option<PathBuf>,
+
+    /// Skip the confirmation prompt
+    #[arg(short, long)]
+    yes: bool,
+  },
 }
 
 #[derive(Subcommand, Debug)]
@@ -67,4 +260,30 @@ pub enum PackCommands {
     #[arg(short, long)]
     capsule: Option<PathBuf>,
   },
-}
\ No newline at end of file
+  /// Build and deploy a single pack, leaving the others alone
+  Deploy {
+    name: String,
+
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// The capsule's location, like `nya ship --location`
+    #[arg(short = 'l', long)]
+    capsule: Option<PathBuf>,
+
+    /// Ship the pack even when nothing changed since its last ship
+    #[arg(long)]
+    force: bool,
+  },
+}
+
+#[cfg(test)]
+mod args_tests {
+  use clap::CommandFactory;
+  use crate::args::Cli;
+
+  #[test]
+  fn cli_definition_is_valid() {
+    Cli::command().debug_assert();
+  }
+}
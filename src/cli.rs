@@ -1,5 +1,8 @@
 pub mod base;
 pub mod init;
 pub mod capsule;
+pub mod exec;
 pub mod pack;
+pub mod preview;
+pub mod release;
 pub mod ship;
\ No newline at end of file
@@ -1,8 +1,10 @@
 use std::path::PathBuf;
+use colored::*;
 use crate::core::runtime::Nya;
+use crate::ops::{base::destroy::DESTROY_COMPONENTS, types::{CloudInitOptions, DestroyOptions}};
 use crate::utils::{verify_base_config, ConfigStatus};
 
-pub async fn build(config: Option<PathBuf>) {
+pub async fn build(config: Option<PathBuf>, bundle: Option<PathBuf>) {
   let input_path = verify_base_config(config);
   let path = match input_path {
     ConfigStatus::Exists(path) => path,
@@ -11,10 +13,79 @@ pub async fn build(config: Option<PathBuf>) {
       return;
     },
   };
-  Nya::run("base:build", path, None).await;
+
+  let mut args: Vec<(&str, serde_json::Value)> = Vec::new();
+  if let Some(bundle) = bundle {
+    let bundle_path = match bundle.canonicalize() {
+      Ok(p) => p,
+      Err(e) => {
+        println!("{}", format!("Bundle {} could not be read: {}", bundle.display(), e).red());
+        return;
+      }
+    };
+    args.push(("bundle_path", serde_json::json!(bundle_path.display().to_string())));
+  }
+  Nya::run_with("base:build", path, None, args).await;
+}
+
+pub async fn bundle(config: Option<PathBuf>, output: Option<PathBuf>) {
+  let valid_path = verify_base_config(config);
+  let path = match valid_path {
+    ConfigStatus::Exists(path) => path,
+    ConfigStatus::Missing(result) => {
+      println!("No config found at {}. Please create a config file to proceed.", result.0.display());
+      return;
+    }
+  };
+
+  let mut args: Vec<(&str, serde_json::Value)> = Vec::new();
+  if let Some(output) = output {
+    args.push(("bundle_output", serde_json::json!(output.display().to_string())));
+  }
+  Nya::run_with("base:bundle", path, None, args).await;
+}
+
+pub async fn destroy(config: Option<PathBuf>, only: Vec<String>, keep: Vec<String>, node: Option<String>, yes: bool) {
+  let valid_path = verify_base_config(config);
+  let path = match valid_path {
+    ConfigStatus::Exists(path) => path,
+    ConfigStatus::Missing(result) => {
+      println!("No config found at {}. Please create a config file to proceed.", result.0.display());
+      return;
+    }
+  };
+
+  let unknown: Vec<&String> = only.iter().chain(keep.iter())
+    .filter(|c| !DESTROY_COMPONENTS.contains(&c.as_str()))
+    .collect();
+  if !unknown.is_empty() {
+    let unknown: Vec<&str> = unknown.iter().map(|c| c.as_str()).collect();
+    println!("{}", format!("Unknown component(s): {}", unknown.join(", ")).red());
+    println!("Valid components are: {}", DESTROY_COMPONENTS.join(", "));
+    return;
+  }
+
+  let options = DestroyOptions { only, keep, node, yes };
+  let options_value = serde_json::to_value(options).unwrap();
+  Nya::run_with("base:destroy", path, None, vec![("destroy_options", options_value)]).await;
+}
+
+pub async fn cloud_init(host: String, config: Option<PathBuf>, output: Option<PathBuf>, hostname: Option<String>, docker: bool) {
+  let valid_path = verify_base_config(config);
+  let path = match valid_path {
+    ConfigStatus::Exists(path) => path,
+    ConfigStatus::Missing(result) => {
+      println!("No config found at {}. Please create a config file to proceed.", result.0.display());
+      return;
+    }
+  };
+
+  let options = CloudInitOptions { host, output, hostname, docker };
+  let options_value = serde_json::to_value(options).unwrap();
+  Nya::run_with("base:cloud-init", path, None, vec![("cloud_init_options", options_value)]).await;
 }
 
-pub async fn destroy(config: Option<PathBuf>) {
+pub async fn patch(config: Option<PathBuf>) {
   let valid_path = verify_base_config(config);
   let path = match valid_path {
     ConfigStatus::Exists(path) => path,
@@ -23,5 +94,58 @@ pub async fn destroy(config: Option<PathBuf>) {
       return;
     }
   };
-  Nya::run("base:destroy", path, None).await;
-}
\ No newline at end of file
+  Nya::run("base:patch", path, None).await;
+}
+
+pub async fn inventory(config: Option<PathBuf>) {
+  let valid_path = verify_base_config(config);
+  let path = match valid_path {
+    ConfigStatus::Exists(path) => path,
+    ConfigStatus::Missing(result) => {
+      println!("No config found at {}. Please create a config file to proceed.", result.0.display());
+      return;
+    }
+  };
+  Nya::run("base:inventory", path, None).await;
+}
+
+pub async fn backup(config: Option<PathBuf>, output: Option<String>) {
+  let valid_path = verify_base_config(config);
+  let path = match valid_path {
+    ConfigStatus::Exists(path) => path,
+    ConfigStatus::Missing(result) => {
+      println!("No config found at {}. Please create a config file to proceed.", result.0.display());
+      return;
+    }
+  };
+
+  let mut args: Vec<(&str, serde_json::Value)> = Vec::new();
+  if let Some(output) = output {
+    args.push(("backup_output", serde_json::json!(output)));
+  }
+  Nya::run_with("base:backup", path, None, args).await;
+}
+
+pub async fn restore(archive: String, config: Option<PathBuf>) {
+  let valid_path = verify_base_config(config);
+  let path = match valid_path {
+    ConfigStatus::Exists(path) => path,
+    ConfigStatus::Missing(result) => {
+      println!("No config found at {}. Please create a config file to proceed.", result.0.display());
+      return;
+    }
+  };
+
+  let archive = if archive.starts_with("s3://") {
+    archive
+  } else {
+    match PathBuf::from(&archive).canonicalize() {
+      Ok(p) => p.display().to_string(),
+      Err(e) => {
+        println!("{}", format!("Backup {} could not be read: {}", archive, e).red());
+        return;
+      }
+    }
+  };
+  Nya::run_with("base:restore", path, None, vec![("restore_archive", serde_json::json!(archive))]).await;
+}
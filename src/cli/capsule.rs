@@ -3,8 +3,8 @@ use colored::*;
 use inquire::Text;
 use serde::Serialize;
 use serde_json::Value;
-use crate::{cli::pack::Pack, defaults, utils};
-use crate::utils::ConfigStatus;
+use crate::{cli::pack::Pack, core::runtime::Nya, defaults, ops::types::CapsuleDeleteOptions, utils};
+use crate::utils::{ConfigStatus, verify_base_config, verify_capsule};
 
 #[derive(Serialize, Debug)]
 pub struct CapsuleData {
@@ -100,13 +100,31 @@ pub fn check(path: Option<PathBuf>){
   }
 }
 
+pub async fn delete(config: Option<PathBuf>, location: Option<PathBuf>, yes: bool) {
+  let nya_base_config_path = match verify_base_config(config) {
+    ConfigStatus::Exists(path) => path,
+    ConfigStatus::Missing(result) => {
+      println!("No config found at {}. Please create a config file to proceed.", result.0.display());
+      return;
+    }
+  };
+  let nya_capsule_path = match verify_capsule(location) {
+    ConfigStatus::Exists(path) => path,
+    ConfigStatus::Missing(result) => {
+      println!("{}{}", "No capsule was found at ".red(), result.0.display().to_string().red());
+      return;
+    }
+  };
+  let options = serde_json::to_value(CapsuleDeleteOptions { yes }).unwrap();
+  Nya::run_with("capsule:delete", nya_base_config_path, Some(nya_capsule_path), vec![("capsule_delete_options", options)]).await;
+}
+
 fn get_capsule_path(path: PathBuf) -> PathBuf {
   if path.display().to_string().contains(".nya/nya.json") {
     return path;
   }
 
-  let full_path = path.join(defaults::CAPSULE_DEFAULT_FILE_DIR_AND_NAME);
-  full_path
+  path.join(defaults::CAPSULE_DEFAULT_FILE_DIR_AND_NAME)
 }
 
 pub fn read_capsule_file(path: PathBuf) -> Option<Value> {
@@ -0,0 +1,26 @@
+use std::path::PathBuf;
+use colored::*;
+use crate::core::runtime::Nya;
+use crate::ops::types::ExecOptions;
+use crate::utils::{verify_base_config, ConfigStatus};
+
+#[allow(clippy::too_many_arguments)]
+pub async fn run(config: Option<PathBuf>, node: Option<String>, role: Option<String>, all: bool, script: Option<PathBuf>, sudo: bool, concurrency: usize, command: Vec<String>) {
+  let valid_path = verify_base_config(config);
+  let path = match valid_path {
+    ConfigStatus::Exists(path) => path,
+    ConfigStatus::Missing(result) => {
+      println!("No config found at {}. Please create a config file to proceed.", result.0.display());
+      return;
+    }
+  };
+
+  if script.is_none() && command.is_empty() {
+    println!("{}", "Nothing to run. Pass a command after `--` or a script with --script.".red());
+    return;
+  }
+
+  let options = ExecOptions { node, role, all, command, script, sudo, concurrency };
+  let options_value = serde_json::to_value(options).unwrap();
+  Nya::run_with("exec", path, None, vec![("exec_options", options_value)]).await;
+}
@@ -13,11 +13,10 @@ pub fn run(user_input: Option<PathBuf>) {
       println!("Otherwise, remove the existing file first.");
     },
     ConfigStatus::Missing((path, _)) => {
-      if let Some(parent) = path.parent() {
-        if let Err(e) = fs::create_dir_all(parent) {
-          println!("Failed to create directory at {}: {}", parent.display(), e);
-          return;
-        }
+      if let Some(parent) = path.parent()
+        && let Err(e) = fs::create_dir_all(parent) {
+        println!("Failed to create directory at {}: {}", parent.display(), e);
+        return;
       }
 
       if let Err(e) = fs::write(&path, include_str!("../../src/ops/init/initial_config.json")) {
@@ -5,7 +5,7 @@ use inquire::{Select, Text};
 use serde::Serialize;
 use serde_json::json;
 use tera::{Context, Tera};
-use crate::{cli::{capsule::read_capsule_file}, utils};
+use crate::{cli::{capsule::read_capsule_file, ship}, utils};
 use crate::utils::ConfigStatus;
 
 #[derive(Serialize, Debug)]
@@ -127,6 +127,9 @@ pub fn new(capsule: Option<PathBuf>) {
 
   println!("{}", format!("✓ Created pack: {}", name).green());
   println!("Location: {}", &pack_path_buf.display());
-  //TODO: single pack deployments
-  //println!("Edit your Dockerfile, then run: {}", "nya pack deploy".purple());
+  println!("Edit your Dockerfile, then run: {}", format!("nya pack deploy {}", name).purple());
+}
+
+pub async fn deploy(name: String, config: Option<PathBuf>, capsule: Option<PathBuf>, force: bool) {
+  ship::run(config, capsule, vec![name], force, false).await;
 }
\ No newline at end of file
@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+use colored::*;
+use crate::{core::runtime::Nya, ops::types::PreviewOptions};
+use crate::utils::{ConfigStatus, verify_base_config, verify_capsule};
+
+fn paths(config: Option<PathBuf>, location: Option<PathBuf>) -> Option<(PathBuf, PathBuf)> {
+  let nya_base_config_path = match verify_base_config(config) {
+    ConfigStatus::Exists(path) => path,
+    ConfigStatus::Missing(result) => {
+      println!("No config found at {}. Please create a config file to proceed.", result.0.display());
+      return None;
+    }
+  };
+  let nya_capsule_path = match verify_capsule(location) {
+    ConfigStatus::Exists(path) => path,
+    ConfigStatus::Missing(result) => {
+      println!("{}{}", "No capsule was found at ".red(), result.0.display().to_string().red());
+      return None;
+    }
+  };
+  Some((nya_base_config_path, nya_capsule_path))
+}
+
+pub async fn list(config: Option<PathBuf>, location: Option<PathBuf>) {
+  let Some((nya_base_config_path, nya_capsule_path)) = paths(config, location) else {
+    return;
+  };
+  Nya::run("preview:list", nya_base_config_path, Some(nya_capsule_path)).await;
+}
+
+pub async fn prune(config: Option<PathBuf>, location: Option<PathBuf>, yes: bool) {
+  let Some((nya_base_config_path, nya_capsule_path)) = paths(config, location) else {
+    return;
+  };
+  let options = serde_json::to_value(PreviewOptions { yes }).unwrap();
+  Nya::run_with("preview:prune", nya_base_config_path, Some(nya_capsule_path), vec![("preview_options", options)]).await;
+}
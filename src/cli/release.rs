@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+use crate::core::runtime::Nya;
+use crate::ops::types::ReleaseOptions;
+use crate::utils::{verify_base_config, verify_capsule, ConfigStatus};
+
+fn base_config(config: Option<PathBuf>) -> Option<PathBuf> {
+  match verify_base_config(config) {
+    ConfigStatus::Exists(path) => Some(path),
+    ConfigStatus::Missing(result) => {
+      println!("No config found at {}. Please create a config file to proceed.", result.0.display());
+      None
+    }
+  }
+}
+
+// A missing capsule is reported by the action, which needs it for the namespace
+fn capsule(location: Option<PathBuf>) -> Option<PathBuf> {
+  match verify_capsule(location) {
+    ConfigStatus::Exists(path) => Some(path),
+    ConfigStatus::Missing(_) => None,
+  }
+}
+
+pub async fn history(config: Option<PathBuf>, location: Option<PathBuf>, pack: String) {
+  let Some(path) = base_config(config) else {
+    return;
+  };
+  let options = serde_json::to_value(ReleaseOptions { pack, to: None }).unwrap();
+  Nya::run_with("history", path, capsule(location), vec![("release_options", options)]).await;
+}
+
+pub async fn rollback(config: Option<PathBuf>, location: Option<PathBuf>, pack: String, to: Option<u32>) {
+  let Some(path) = base_config(config) else {
+    return;
+  };
+  let capsule = capsule(location);
+  let options = serde_json::to_value(ReleaseOptions { pack, to }).unwrap();
+  let rolled_back = Nya::run_with("rollback", path, capsule, vec![("release_options", options)]).await;
+  if !rolled_back {
+    std::process::exit(1);
+  }
+}
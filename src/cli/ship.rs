@@ -1,10 +1,11 @@
 use std::path::PathBuf;
 use colored::Colorize;
+use serde_json::json;
 
 use crate::{core::runtime::Nya, utils::{ConfigStatus}};
 use crate::utils::{verify_base_config, verify_capsule};
 
-pub async fn run(config: Option<PathBuf>, capsule: Option<PathBuf>) {
+pub async fn run(config: Option<PathBuf>, capsule: Option<PathBuf>, packs: Vec<String>, force: bool, preview: bool) {
   let config_result = verify_base_config(config);
   let nya_base_config_path = match config_result {
     ConfigStatus::Exists(path) => path,
@@ -22,5 +23,8 @@ pub async fn run(config: Option<PathBuf>, capsule: Option<PathBuf>) {
       return;
     }
   };
-  Nya::run("capsule:ship", nya_base_config_path, Some(nya_capsule_path)).await;
+  let shipped = Nya::run_with("capsule:ship", nya_base_config_path, Some(nya_capsule_path), vec![("ship_packs", json!(packs)), ("ship_force", json!(force)), ("ship_preview", json!(preview))]).await;
+  if !shipped {
+    std::process::exit(1);
+  }
 }
\ No newline at end of file
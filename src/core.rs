@@ -6,8 +6,9 @@ pub mod service;
 pub mod payload;
 mod task_tracker;
 pub mod checks;
+pub mod log;
 
-use crate::core::{payload::{Get, Payload}, service::{Service, ServiceActions, handle_action}, runtime::Nya};
+use crate::core::{log::{HostLine, write_host_line, write_local_line}, payload::{Get, Payload}, service::{Service, ServiceActions, handle_action}, runtime::Nya};
 
 pub struct NyaCore;
 
@@ -21,14 +22,18 @@ impl Service for NyaCore {
   }
 }
 
-pub async fn log(_: Nya, payload: Payload) {
-  println!("{}", payload.get::<String>().unwrap());
+pub async fn log(nya: Nya, payload: Payload) {
+  if let Ok(host_line) = payload.get::<HostLine>() {
+    write_host_line(&nya, host_line);
+    return;
+  }
+  write_local_line(&nya, payload.get::<String>().unwrap());
 }
 
 pub async fn test_nya_service(nya: Nya, payload: Payload) {
   let ctx_val = nya.get("test").await;
   let pay_val = payload.get::<&str>().unwrap();
   println!("Value from payload: {}", pay_val);
-  println!("Value from context: {}", ctx_val.to_string());
+  println!("Value from context: {}", ctx_val);
   nya.trigger("log", Payload::new("test_log")).await;
 }
\ No newline at end of file
@@ -8,6 +8,12 @@ pub struct NyaEventBus {
   event_handlers: HashMap<String, Action>
 }
 
+impl Default for NyaEventBus {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl NyaEventBus {
   pub fn new() -> Self {
     Self {
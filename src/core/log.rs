@@ -0,0 +1,136 @@
+use std::{collections::HashMap, fs::{self, File, OpenOptions}, io::Write, path::{Path, PathBuf}, sync::{Mutex, OnceLock, atomic::{AtomicBool, Ordering}}};
+use colored::*;
+use crate::core::runtime::Nya;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// Log files stay open for the whole run instead of being reopened per line.
+static LOG_FILES: OnceLock<Mutex<HashMap<PathBuf, File>>> = OnceLock::new();
+
+/// The name output from commands run on this machine is logged under.
+pub const LOCAL_HOST: &str = "local";
+
+/// Where a line of remote output came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stream {
+  Stdout,
+  Stderr,
+  /// Stdout kept as a command's result. Written to the log file but not shown.
+  Captured,
+}
+
+/// One line of output from a command on a node, sent through the `log` event.
+#[derive(Clone, Debug)]
+pub struct HostLine {
+  pub host: String,
+  pub line: String,
+  pub stream: Stream,
+}
+
+/// With `--quiet` only step summaries are printed. Remote output still goes to the log files.
+pub fn set_quiet(quiet: bool) {
+  QUIET.store(quiet, Ordering::SeqCst);
+}
+
+pub fn is_quiet() -> bool {
+  QUIET.load(Ordering::SeqCst)
+}
+
+pub fn logs_dir() -> PathBuf {
+  dirs::home_dir()
+    .expect("could not determine home directory")
+    .join(".nya")
+    .join("logs")
+}
+
+pub fn run_log_dir(run_id: &str) -> PathBuf {
+  logs_dir().join(run_id)
+}
+
+pub fn host_log_path(run_id: &str, host: &str) -> PathBuf {
+  let file_name: String = host.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect();
+  run_log_dir(run_id).join(format!("{}.log", file_name))
+}
+
+/// The same host always gets the same color, so interleaved output stays readable.
+fn host_color(host: &str) -> Color {
+  const PALETTE: [Color; 6] = [Color::Cyan, Color::Magenta, Color::Blue, Color::Green, Color::Yellow, Color::BrightCyan];
+  let index = host.bytes().fold(0usize, |acc, b| acc.wrapping_mul(31).wrapping_add(b as usize));
+  PALETTE[index % PALETTE.len()]
+}
+
+pub fn host_prefix(host: &str) -> ColoredString {
+  format!("[{}]", host).color(host_color(host)).bold()
+}
+
+/// Prints a line with its `[host]` prefix and appends it to the host's log file for this run.
+pub fn write_host_line(nya: &Nya, host_line: &HostLine) {
+  append_host_line(nya, host_line);
+  if is_quiet() {
+    return;
+  }
+  match host_line.stream {
+    Stream::Stdout => println!("{} {}", host_prefix(&host_line.host), host_line.line),
+    Stream::Stderr => eprintln!("{} {}", host_prefix(&host_line.host), host_line.line.dimmed()),
+    Stream::Captured => {},
+  }
+}
+
+/// Prints a line of local output, like a docker build's, and appends it to
+/// the run's `local.log`. With `--quiet` it only goes to the log.
+pub fn write_local_line(nya: &Nya, line: &str) {
+  append_host_line(nya, &HostLine { host: LOCAL_HOST.to_string(), line: line.to_string(), stream: Stream::Stdout });
+  if !is_quiet() {
+    println!("{}", line);
+  }
+}
+
+/// Appends a line to the host's log file for this run without printing it.
+pub fn append_host_line(nya: &Nya, host_line: &HostLine) {
+  let path = host_log_path(nya.run_id(), &host_line.host);
+  let marker = match host_line.stream {
+    Stream::Stderr => "err",
+    Stream::Stdout | Stream::Captured => "out",
+  };
+  let line = format!("{} {} {}", chrono::Local::now().format("%H:%M:%S"), marker, host_line.line);
+  if let Err(e) = append_line(&path, &line) {
+    eprintln!("Failed to write log file {}: {}", path.display(), e);
+  }
+}
+
+fn append_line(path: &Path, line: &str) -> std::io::Result<()> {
+  let mut files = LOG_FILES.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+  let file = match files.get_mut(path) {
+    Some(file) => file,
+    None => {
+      if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+      }
+      let file = OpenOptions::new().create(true).append(true).open(path)?;
+      files.entry(path.to_path_buf()).or_insert(file)
+    },
+  };
+  writeln!(file, "{}", line)
+}
+
+#[cfg(test)]
+mod log_tests {
+  use crate::core::log::{append_line, host_color, host_log_path};
+
+  #[test]
+  fn log_files_are_named_after_the_host() {
+    let path = host_log_path("20261019-120000-base-build", "10.0.0.5");
+    assert!(path.ends_with("logs/20261019-120000-base-build/10.0.0.5.log"));
+    let path = host_log_path("run", "fe80::1");
+    assert!(path.ends_with("logs/run/fe80__1.log"));
+    assert_eq!(host_color("10.0.0.5"), host_color("10.0.0.5"));
+  }
+
+  #[test]
+  fn appends_every_line_to_the_same_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("run").join("local.log");
+    append_line(&path, "12:00:00 out Building web...").unwrap();
+    append_line(&path, "12:00:01 out Step 1/4").unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "12:00:00 out Building web...\n12:00:01 out Step 1/4\n");
+  }
+}
@@ -1,9 +1,12 @@
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use colored::*;
 use serde::Serialize;
 use serde_json::Value;
 use tokio::{sync::Mutex, task::JoinHandle};
-use crate::core::{context::NyaContext, event_bus::{EventBus, NyaEventBus}, payload::Payload, schema::NyaSchema, service::Service, task_tracker::TaskTracker};
+use crate::core::{context::NyaContext, event_bus::{EventBus, NyaEventBus}, log::{is_quiet, run_log_dir}, payload::Payload, schema::NyaSchema, service::Service, task_tracker::TaskTracker};
 use crate::external::get_core_services;
 
 struct NyaInternals {
@@ -13,6 +16,8 @@ struct NyaInternals {
   task_tracker: TaskTracker,
   base_config_path: PathBuf,
   capsule_path: Option<PathBuf>,
+  stopped: AtomicBool,
+  run_id: String,
 }
 
 #[derive(Clone)]
@@ -22,9 +27,19 @@ pub struct Nya {
 
 impl Nya {
   pub async fn run(cmd: &str, config: PathBuf, capsule: Option<PathBuf>) {
+    Nya::run_with(cmd, config, capsule, vec![]).await;
+  }
+
+  /// Runs a command with extra values (usually CLI flags) seeded into the context first.
+  /// Returns false when a step stopped the run.
+  pub async fn run_with(cmd: &str, config: PathBuf, capsule: Option<PathBuf>, args: Vec<(&str, Value)>) -> bool {
     let services = get_core_services();
     let nya = Nya::build(cmd, config, capsule, services);
+    for (key, value) in args {
+      nya.set(key, value).await;
+    }
     nya.execute(Payload::empty()).await;
+    !nya.is_stopped()
   }
 
   pub fn build(cmd: &str, config: PathBuf, capsule: Option<PathBuf>, reg: Vec<Box<dyn Service>>) -> Self {
@@ -37,7 +52,9 @@ impl Nya {
       bus: Arc::new(nya_event_bus),
       task_tracker: TaskTracker::new(),
       base_config_path: config.clone(),
-      capsule_path: capsule.clone()
+      capsule_path: capsule.clone(),
+      stopped: AtomicBool::new(false),
+      run_id: format!("{}-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"), cmd.replace(':', "-")),
     };
 
     Self {
@@ -47,8 +64,20 @@ impl Nya {
 
   pub async fn execute(&self, initial_payload: Payload) {
     for step in self.internals.schema.steps.iter() {
+      if self.is_stopped() {
+        break;
+      }
+      let started = Instant::now();
       self.internals.bus.clone().emit(self.clone(), step.clone(), initial_payload.clone()).await;
       self.internals.task_tracker.wait_all().await;
+      if is_quiet() {
+        let mark = if self.is_stopped() { "✗".red() } else { "✓".green() };
+        eprintln!("{} {} ({:.1}s)", mark, step, started.elapsed().as_secs_f32());
+      }
+    }
+    let log_dir = run_log_dir(self.run_id());
+    if log_dir.exists() {
+      eprintln!("Node output was logged to {}", log_dir.display());
     }
   }
 
@@ -57,7 +86,7 @@ impl Nya {
     if let Some(item) = ctx.context.get(key) {
       return item.clone()
     }
-    return Value::Null;
+    Value::Null
   }
 
   pub async fn set<T: Serialize>(&self, key: &str, value: T) {
@@ -76,12 +105,32 @@ impl Nya {
     self.internals.task_tracker.add(handle).await;
   }
 
+  /// Runs an event's handler and waits for it, unlike `trigger`. Used where
+  /// order matters, like lines of command output.
+  pub async fn emit(&self, event: &str, payload: Payload) {
+    self.internals.bus.emit(self.clone(), event.to_string(), payload).await;
+  }
+
   pub async fn trigger_all(&self, triggers: Vec<(&str, Payload)>) {
     for (event, payload) in triggers {
       self.trigger(event, payload).await;
     }
   }
   
+  /// Stops the schema once the current step and its triggered tasks finish.
+  pub fn stop(&self) {
+    self.internals.stopped.store(true, Ordering::SeqCst);
+  }
+
+  pub fn is_stopped(&self) -> bool {
+    self.internals.stopped.load(Ordering::SeqCst)
+  }
+
+  /// Identifies this run, e.g. in the name of its log directory.
+  pub fn run_id(&self) -> &str {
+    &self.internals.run_id
+  }
+
   pub fn base_config_path(&self) -> &Path {
     &self.internals.base_config_path
   }
@@ -106,7 +155,22 @@ fn build_nya_bus(reg: Vec<Box<dyn Service>>) -> NyaEventBus {
 #[cfg(test)]
 mod nya_tests {
     use std::path::PathBuf;
-    use crate::{core::{payload::Payload, service::service_tests::TestService, runtime::Nya}};
+    use crate::{core::{payload::Payload, service::{Service, ServiceActions, handle_action, service_tests::{TestService, test_fn}}, runtime::Nya}};
+
+  async fn stop_fn(nya: Nya, _: Payload) {
+    nya.stop();
+  }
+
+  struct StoppingService;
+  impl Service for StoppingService {
+    fn name(&self) -> String { "Stopping Service".to_string() }
+    fn register(&self) -> ServiceActions {
+      vec![
+        ("test_stop".to_string(), handle_action(stop_fn)),
+        ("test".to_string(), handle_action(test_fn)),
+      ]
+    }
+  }
 
   #[test]
   fn can_build_nya() {
@@ -152,4 +216,12 @@ mod nya_tests {
     let val1 = ctx.context.get("test_key").unwrap().as_str().unwrap();
     assert_eq!("test_value", val1);
   }
+
+  #[tokio::test]
+  async fn stop_skips_remaining_steps() {
+    let nya = Nya::build("test_cmd3", PathBuf::from("./tests/nya_test_config.json"), None, vec![Box::new(StoppingService)]);
+    nya.execute(Payload::empty()).await;
+    assert!(nya.is_stopped());
+    assert_eq!(serde_json::Value::Null, nya.get("test_key").await);
+  }
 }
\ No newline at end of file
@@ -35,7 +35,7 @@ fn get_schema(cmd: &str) -> Result<NyaSchemaSteps, String> {
     for (name, schema) in core_schema_collection {
         all_schemas.insert(name, schema);
     };
-    if let Some(schema) = all_schemas.get(cmd).clone() {
+    if let Some(schema) = all_schemas.get(cmd) {
         return Ok(schema.clone().steps);
     }
     Err("Get Schema(): wasn't able to successfully retrieve schema".to_string())
@@ -9,4 +9,10 @@ pub fn base_config_default_location() -> PathBuf {
 
 pub const BASE_CONFIG_DEFAULT_FILE_NAME: &str = "nya_base_config.json";
 
-pub const CAPSULE_DEFAULT_FILE_DIR_AND_NAME: &str = ".nya/nya.json";
\ No newline at end of file
+pub const CAPSULE_DEFAULT_FILE_DIR_AND_NAME: &str = ".nya/nya.json";
+pub fn inventory_default_location() -> PathBuf {
+    dirs::home_dir()
+        .expect("could not determine home directory")
+        .join(".nya")
+        .join("inventory.json")
+}
@@ -1,10 +1,21 @@
-use crate::{core::{NyaCore, service::Service}, ops::{base::{build::NyaBaseBuild, destroy::NyaBaseDestroy}, ship::NyaShip}};
+use crate::{core::{NyaCore, service::Service}, ops::{base::{build::NyaBaseBuild, bundle::NyaBaseBundle, cloud_init::NyaBaseCloudInit, backup::NyaBaseBackup, destroy::NyaBaseDestroy, inventory::NyaBaseInventory, patch::NyaBasePatch}, capsule::NyaCapsule, distro::NyaDistro, exec::NyaExec, preview::NyaPreview, release::NyaRelease, ship::NyaShip, validate::NyaValidate}};
 
 pub fn get_core_services() -> Vec<Box<dyn Service>> {
   vec![
     Box::new(NyaCore),
     Box::new(NyaBaseBuild),
     Box::new(NyaBaseDestroy),
-    Box::new(NyaShip)
+    Box::new(NyaBaseBundle),
+    Box::new(NyaBaseCloudInit),
+    Box::new(NyaBasePatch),
+    Box::new(NyaBaseBackup),
+    Box::new(NyaBaseInventory),
+    Box::new(NyaCapsule),
+    Box::new(NyaDistro),
+    Box::new(NyaExec),
+    Box::new(NyaPreview),
+    Box::new(NyaRelease),
+    Box::new(NyaShip),
+    Box::new(NyaValidate)
   ]
 }
\ No newline at end of file
@@ -1,27 +1,44 @@
 mod args;
 
-use args::{Cli, Commands, BaseCommands, CapsuleCommands, PackCommands };
+use args::{Cli, Commands, BaseCommands, CapsuleCommands, PackCommands, PreviewCommands };
 use clap::Parser;
 use nya_cloud::cli::{
-  base, capsule, init, pack, ship
+  base, capsule, exec, init, pack, preview, release, ship
 };
+use nya_cloud::core::log::set_quiet;
 
 #[tokio::main]
 async fn main() {
   let cli = Cli::parse();
+  set_quiet(cli.quiet);
 
   match cli.command {
     Commands::Init { output } => { init::run(output) },
     Commands::Base { command } => match command {
-      BaseCommands::Build { config }=> { base::build(config).await },
-      BaseCommands::Destroy { config }=> { base::destroy(config).await }
+      BaseCommands::Build { config, bundle }=> { base::build(config, bundle).await },
+      BaseCommands::Patch { config }=> { base::patch(config).await },
+      BaseCommands::Inventory { config }=> { base::inventory(config).await },
+      BaseCommands::Backup { config, output }=> { base::backup(config, output).await },
+      BaseCommands::Restore { archive, config }=> { base::restore(archive, config).await },
+      BaseCommands::Bundle { config, output }=> { base::bundle(config, output).await },
+      BaseCommands::CloudInit { host, config, output, hostname, docker }=> { base::cloud_init(host, config, output, hostname, docker).await },
+      BaseCommands::Destroy { config, only, keep, node, yes }=> { base::destroy(config, only, keep, node, yes).await }
     },
     Commands::Capsule { command } => match command {
-      CapsuleCommands::New { config } => { capsule::new(config) }
+      CapsuleCommands::New { config } => { capsule::new(config) },
+      CapsuleCommands::Delete { config, location, yes } => { capsule::delete(config, location, yes).await }
     },
     Commands::Pack { command } => match command {
       PackCommands::New { capsule } => { pack::new(capsule) },
+      PackCommands::Deploy { name, config, capsule, force } => { pack::deploy(name, config, capsule, force).await },
     },
-    Commands::Ship { config, location } => { ship::run(config, location).await },
+    Commands::Ship { config, location, packs, force, preview } => { ship::run(config, location, packs, force, preview).await },
+    Commands::Preview { command } => match command {
+      PreviewCommands::List { config, location } => { preview::list(config, location).await },
+      PreviewCommands::Prune { config, location, yes } => { preview::prune(config, location, yes).await },
+    },
+    Commands::History { pack, config, location } => { release::history(config, location, pack).await },
+    Commands::Rollback { pack, to, config, location } => { release::rollback(config, location, pack, to).await },
+    Commands::Exec { config, node, role, all, script, sudo, concurrency, command } => { exec::run(config, node, role, all, script, sudo, concurrency, command).await },
   }
 }
\ No newline at end of file
@@ -0,0 +1,360 @@
+use std::{fs, path::PathBuf, process::Stdio};
+use chrono::Local;
+use colored::*;
+use serde::Serialize;
+use serde_json::Value;
+use tera::Context;
+use tokio::process::Command;
+
+use crate::{core::{checks::{Check, CheckIf}, payload::Payload, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::{distro::{Component, package_manager}, types::{BaseNodeConfig, NodeCommandResult}, utils::{create_ssh_session, get_control_plane_config, run_on_node}}};
+
+const CREATE_BACKUP_SCRIPT: &str = include_str!("scripts/create_backup.sh");
+const STAGE_RESTORE_SCRIPT: &str = include_str!("scripts/stage_restore.sh");
+const RESTORE_DNS_SCRIPT: &str = include_str!("scripts/restore_dns.sh");
+const RESTORE_REMOTE_ARCHIVE: &str = "/tmp/nya-restore.tar.gz";
+const RESTORE_STAGE_DIR: &str = "/tmp/nya-restore";
+
+pub struct NyaBaseBackup;
+
+impl Service for NyaBaseBackup {
+  fn name(&self) -> String {"NyaBaseBackup".to_string()}
+  fn register(&self) -> ServiceActions {
+    vec![
+      (String::from("onBackupBase"), handle_action(backup_base)),
+      (String::from("onRestoreBackup"), handle_action(restore_backup)),
+      (String::from("onRestoreDns"), handle_action(restore_dns)),
+    ]
+  }
+}
+
+/// Where a backup archive is written to or read from.
+#[derive(Debug, PartialEq)]
+enum BackupTarget {
+  Local(PathBuf),
+  S3 { url: String, endpoint: Option<String> },
+}
+
+#[derive(Serialize)]
+struct BackupContext {
+  name: String,
+  created_at: String,
+  control_plane_ip: String,
+  domain: String,
+  stop_k3s: String,
+  start_k3s: String,
+}
+
+#[derive(Serialize)]
+struct RestoreContext {
+  stage: String,
+  archive: String,
+  restart_bind9: String,
+}
+
+/// Picks the target from `-o`, then `nya.backup.target` in the base config,
+/// then `~/.nya/backups/`. Directories get the archive name appended.
+fn resolve_target(output: Option<&str>, configured: Option<&str>, endpoint: Option<&str>, archive_name: &str) -> BackupTarget {
+  let target = output.or(configured).map(|t| t.to_string()).unwrap_or_else(|| {
+    dirs::home_dir()
+      .unwrap_or_default()
+      .join(".nya")
+      .join("backups")
+      .display()
+      .to_string() + "/"
+  });
+
+  if target.starts_with("s3://") {
+    let url = if target.ends_with(".tar.gz") {
+      target
+    } else {
+      format!("{}/{}", target.trim_end_matches('/'), archive_name)
+    };
+    return BackupTarget::S3 { url, endpoint: endpoint.map(|e| e.to_string()) };
+  }
+
+  let path = PathBuf::from(shellexpand::tilde(&target).to_string());
+  if target.ends_with('/') || path.is_dir() {
+    BackupTarget::Local(path.join(archive_name))
+  } else {
+    BackupTarget::Local(path)
+  }
+}
+
+async fn backup_base(nya: Nya, _: Payload) {
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let backup_config: Value = nya.get("nya.backup").await;
+  let now = Local::now();
+  let name = format!("nya-backup-{}", now.format("%Y%m%d-%H%M%S"));
+  let archive_name = format!("{}.tar.gz", name);
+  let target = resolve_target(
+    nya.get("backup_output").await.as_str(),
+    backup_config.get("target").and_then(|v| v.as_str()),
+    backup_config.get("s3_endpoint").and_then(|v| v.as_str()),
+    &archive_name,
+  );
+
+  let Some(session) = create_ssh_session(&nya, &control_plane_config).await else {
+    return;
+  };
+  let package_manager = match package_manager(&nya, &control_plane_config.host, &session).await {
+    Ok(package_manager) => package_manager,
+    Err(err) => {
+      eprintln!("{}", format!("Cannot back up {}: {}", control_plane_config.host, err).red());
+      let _ = session.close().await;
+      return;
+    },
+  };
+
+  let control_plane_vars: Value = nya.get("nya.control_plane.vars").await;
+  let backup_context = BackupContext {
+    name: name.clone(),
+    created_at: now.to_rfc3339(),
+    control_plane_ip: control_plane_config.host.clone(),
+    domain: control_plane_vars.get("domain_name").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+    stop_k3s: package_manager.stop_service("k3s"),
+    start_k3s: package_manager.restart_service("k3s"),
+  };
+  let context_value: Value = serde_json::to_value(&backup_context).unwrap();
+  let tera_context: Context = Context::from_serialize(&context_value).unwrap();
+  let rendered_script: String = match tera::Tera::one_off(CREATE_BACKUP_SCRIPT, &tera_context, false) {
+    Ok(script) => script,
+    Err(e) => {
+      eprintln!("Failed to render backup script: {}", e);
+      let _ = session.close().await;
+      return;
+    },
+  };
+
+  println!("Backing up the control plane {}", control_plane_config.host);
+  println!("k3s will be stopped briefly while its datastore is copied.");
+  if let NodeCommandResult::Failure(err) = run_on_node(&nya, &control_plane_config.host, &session, &rendered_script).await {
+    eprintln!("{}", format!("Backup failed on {}: {}", control_plane_config.host, err).red());
+    let _ = session.close().await;
+    return;
+  }
+
+  let remote_archive = format!("/tmp/{}", archive_name);
+  let local_archive = match &target {
+    BackupTarget::Local(path) => path.clone(),
+    BackupTarget::S3 { .. } => std::env::temp_dir().join(&archive_name),
+  };
+  if let Some(parent) = local_archive.parent()
+    && let Err(e) = fs::create_dir_all(parent) {
+    eprintln!("Failed to create {}: {}", parent.display(), e);
+    let _ = session.close().await;
+    return;
+  }
+
+  let downloaded = scp(&control_plane_config, &format!("{}@{}:{}", control_plane_config.user, control_plane_config.host, remote_archive), &local_archive.display().to_string()).await;
+  run_on_node(&nya, &control_plane_config.host, &session, &format!("rm -f {}", remote_archive)).await;
+  if let Err(e) = session.close().await {
+    eprintln!("backup_base: failed to close session: {}", e);
+  }
+  if let Err(err) = downloaded {
+    eprintln!("{}", format!("Failed to download the backup: {}", err).red());
+    return;
+  }
+
+  match target {
+    BackupTarget::Local(path) => {
+      println!("{}", "Backup created successfully!".green());
+      println!("Location: {}", path.display());
+    },
+    BackupTarget::S3 { url, endpoint } => {
+      let uploaded = aws_s3_cp(&local_archive.display().to_string(), &url, endpoint.as_deref()).await;
+      let _ = fs::remove_file(&local_archive);
+      match uploaded {
+        Ok(()) => {
+          println!("{}", "Backup created successfully!".green());
+          println!("Location: {}", url);
+        },
+        Err(err) => eprintln!("{}", format!("Failed to upload the backup to {}: {}", url, err).red()),
+      }
+    },
+  }
+}
+
+async fn restore_backup(nya: Nya, _: Payload) {
+  let archive: String = nya.get("restore_archive").await.as_str().unwrap_or("").to_string();
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let Some(session) = create_ssh_session(&nya, &control_plane_config).await else {
+    return;
+  };
+
+  if Check::run(CheckIf::K3sIsInstalled, &session).await {
+    eprintln!("{}", format!("k3s is still installed on {}.", control_plane_config.host).red());
+    eprintln!("Restore rebuilds a destroyed control plane. Run `nya base destroy --node {}` first.", control_plane_config.host);
+    let _ = session.close().await;
+    nya.stop();
+    return;
+  }
+
+  println!("Restoring from {}", archive);
+  let local_archive: PathBuf = if archive.starts_with("s3://") {
+    let backup_config: Value = nya.get("nya.backup").await;
+    let endpoint = backup_config.get("s3_endpoint").and_then(|v| v.as_str());
+    let download = std::env::temp_dir().join("nya-restore.tar.gz");
+    if let Err(err) = aws_s3_cp(&archive, &download.display().to_string(), endpoint).await {
+      eprintln!("{}", format!("Failed to download {}: {}", archive, err).red());
+      let _ = session.close().await;
+      nya.stop();
+      return;
+    }
+    download
+  } else {
+    PathBuf::from(&archive)
+  };
+
+  let uploaded = scp(&control_plane_config, &local_archive.display().to_string(), &format!("{}@{}:{}", control_plane_config.user, control_plane_config.host, RESTORE_REMOTE_ARCHIVE)).await;
+  if archive.starts_with("s3://") {
+    let _ = fs::remove_file(&local_archive);
+  }
+  if let Err(err) = uploaded {
+    eprintln!("{}", format!("Failed to upload the backup to {}: {}", control_plane_config.host, err).red());
+    let _ = session.close().await;
+    nya.stop();
+    return;
+  }
+
+  let rendered_script = match render_restore_script(STAGE_RESTORE_SCRIPT, String::new()) {
+    Ok(script) => script,
+    Err(e) => {
+      eprintln!("Failed to render restore script: {}", e);
+      let _ = session.close().await;
+      nya.stop();
+      return;
+    },
+  };
+  match run_on_node(&nya, &control_plane_config.host, &session, &rendered_script).await {
+    NodeCommandResult::Success => println!("Backup staged on {}. Rebuilding the base from it.", control_plane_config.host),
+    NodeCommandResult::Failure(err) => {
+      eprintln!("{}", format!("Failed to stage the backup on {}: {}", control_plane_config.host, err).red());
+      nya.stop();
+    },
+  }
+
+  if let Err(e) = session.close().await {
+    eprintln!("restore_backup: failed to close session: {}", e);
+  }
+}
+
+/// Runs after the build so the restored zones replace the freshly rendered ones.
+async fn restore_dns(nya: Nya, _: Payload) {
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let Some(session) = create_ssh_session(&nya, &control_plane_config).await else {
+    return;
+  };
+  let restart_bind9 = match package_manager(&nya, &control_plane_config.host, &session).await {
+    Ok(package_manager) => package_manager.restart_service(package_manager.service(Component::Bind9)),
+    Err(err) => {
+      eprintln!("{}", format!("Failed to restore Bind9 zones: {}", err).red());
+      let _ = session.close().await;
+      return;
+    },
+  };
+  let rendered_script = match render_restore_script(RESTORE_DNS_SCRIPT, restart_bind9) {
+    Ok(script) => script,
+    Err(e) => {
+      eprintln!("Failed to render DNS restore script: {}", e);
+      let _ = session.close().await;
+      return;
+    },
+  };
+
+  match run_on_node(&nya, &control_plane_config.host, &session, &rendered_script).await {
+    NodeCommandResult::Success => println!("{}", "Bind9 zones restored.".green()),
+    NodeCommandResult::Failure(err) => eprintln!("{}", format!("Failed to restore Bind9 zones: {}", err).red()),
+  }
+  if let Err(e) = session.close().await {
+    eprintln!("restore_dns: failed to close session: {}", e);
+  }
+}
+
+fn render_restore_script(template: &str, restart_bind9: String) -> Result<String, tera::Error> {
+  let restore_context = RestoreContext {
+    stage: RESTORE_STAGE_DIR.to_string(),
+    archive: RESTORE_REMOTE_ARCHIVE.to_string(),
+    restart_bind9,
+  };
+  let context_value: Value = serde_json::to_value(&restore_context).unwrap();
+  let tera_context: Context = Context::from_serialize(&context_value).unwrap();
+  tera::Tera::one_off(template, &tera_context, false)
+}
+
+async fn scp(node: &BaseNodeConfig, from: &str, to: &str) -> Result<(), String> {
+  let ssh_key = shellexpand::tilde(&node.ssh_key_path).to_string();
+  let status = Command::new("scp")
+    .args(["-i", &ssh_key, "-o", "StrictHostKeyChecking=no", from, to])
+    .stdin(Stdio::null())
+    .status()
+    .await
+    .map_err(|e| e.to_string())?;
+  if !status.success() {
+    return Err(format!("scp exited with {}", status));
+  }
+  Ok(())
+}
+
+/// Copies to or from an S3-compatible store with the AWS CLI. Credentials come
+/// from the usual `AWS_*` environment; `endpoint` points it at MinIO or similar.
+async fn aws_s3_cp(from: &str, to: &str, endpoint: Option<&str>) -> Result<(), String> {
+  let mut command = Command::new("aws");
+  command.args(["s3", "cp", from, to]);
+  if let Some(endpoint) = endpoint {
+    command.args(["--endpoint-url", endpoint]);
+  }
+  let status = command
+    .stdin(Stdio::null())
+    .status()
+    .await
+    .map_err(|e| format!("failed to run the aws cli: {}", e))?;
+  if !status.success() {
+    return Err(format!("aws s3 cp exited with {}", status));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod backup_tests {
+  use std::path::PathBuf;
+  use tera::Context;
+  use crate::ops::{base::backup::{BackupContext, BackupTarget, CREATE_BACKUP_SCRIPT, resolve_target}, distro::PackageManager};
+
+  #[test]
+  fn resolves_backup_targets() {
+    let name = "nya-backup-20260101-000000.tar.gz";
+    assert_eq!(
+      resolve_target(Some("/srv/backups/"), Some("s3://ignored"), None, name),
+      BackupTarget::Local(PathBuf::from("/srv/backups").join(name))
+    );
+    assert_eq!(
+      resolve_target(Some("/srv/nya.tar.gz"), None, None, name),
+      BackupTarget::Local(PathBuf::from("/srv/nya.tar.gz"))
+    );
+    assert_eq!(
+      resolve_target(None, Some("s3://nya/base/"), Some("http://localhost:9000"), name),
+      BackupTarget::S3 { url: format!("s3://nya/base/{}", name), endpoint: Some("http://localhost:9000".to_string()) }
+    );
+    match resolve_target(None, None, None, name) {
+      BackupTarget::Local(path) => assert!(path.ends_with(PathBuf::from(".nya/backups").join(name))),
+      other => panic!("expected a local target, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn restarts_k3s_when_the_datastore_copy_fails() {
+    let context = BackupContext {
+      name: "nya-backup-20260101-000000".to_string(),
+      created_at: "2026-01-01T00:00:00Z".to_string(),
+      control_plane_ip: "10.0.0.7".to_string(),
+      domain: "nya.local".to_string(),
+      stop_k3s: PackageManager::Apt.stop_service("k3s"),
+      start_k3s: PackageManager::Apt.restart_service("k3s"),
+    };
+    let script = tera::Tera::one_off(CREATE_BACKUP_SCRIPT, &Context::from_serialize(&context).unwrap(), false).unwrap();
+    let stop = script.find("sudo systemctl stop k3s\n").unwrap();
+    let trap = script.find("trap 'sudo systemctl restart k3s' EXIT").unwrap();
+    let copy = script.find("sudo cp -a /var/lib/rancher/k3s/server").unwrap();
+    assert!(stop < trap && trap < copy);
+  }
+}
@@ -0,0 +1,41 @@
+#!/bin/bash
+set -euo pipefail
+
+STAGE=/tmp/{{ name }}
+sudo rm -rf "$STAGE"
+sudo mkdir -p "$STAGE"/k3s "$STAGE"/registry "$STAGE"/bind9 "$STAGE"/mkcert "$STAGE"/certs
+
+# Stop k3s while the datastore is copied so the snapshot is consistent,
+# and start it again even if a copy fails
+{{ stop_k3s }}
+trap '{{ start_k3s }}' EXIT
+for item in db tls token cred; do
+  if sudo test -e /var/lib/rancher/k3s/server/$item; then
+    sudo cp -a /var/lib/rancher/k3s/server/$item "$STAGE"/k3s/
+  fi
+done
+{{ start_k3s }}
+trap - EXIT
+
+if sudo docker inspect registry > /dev/null 2>&1; then
+  sudo docker cp registry:/var/lib/registry/. "$STAGE"/registry/
+else
+  echo "registry container not found, skipping registry images" >&2
+fi
+
+sudo cp -a /etc/bind/named.conf.local /etc/bind/named.conf.options /etc/bind/zones "$STAGE"/bind9/
+sudo cp -a /root/.local/share/mkcert/. "$STAGE"/mkcert/
+sudo cp -a /etc/nya/certs/. "$STAGE"/certs/
+
+sudo tee "$STAGE"/manifest.json > /dev/null <<MANIFEST
+{
+  "created_at": "{{ created_at }}",
+  "control_plane": "{{ control_plane_ip }}",
+  "domain": "{{ domain }}",
+  "k3s_version": "$(k3s --version | head -1 | awk '{print $3}')"
+}
+MANIFEST
+
+sudo tar -C "$STAGE" -czf "$STAGE".tar.gz .
+sudo chown "$USER" "$STAGE".tar.gz
+sudo rm -rf "$STAGE"
@@ -0,0 +1,9 @@
+#!/bin/bash
+set -euo pipefail
+
+STAGE={{ stage }}
+sudo mkdir -p /etc/bind/zones
+sudo cp -a "$STAGE"/bind9/named.conf.local "$STAGE"/bind9/named.conf.options /etc/bind/
+sudo cp -a "$STAGE"/bind9/zones/. /etc/bind/zones/
+{{ restart_bind9 }}
+sudo rm -rf "$STAGE"
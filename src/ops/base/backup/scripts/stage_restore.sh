@@ -0,0 +1,25 @@
+#!/bin/bash
+set -euo pipefail
+
+STAGE={{ stage }}
+sudo rm -rf "$STAGE"
+sudo mkdir -p "$STAGE"
+sudo tar -xzf {{ archive }} -C "$STAGE"
+rm -f {{ archive }}
+
+# k3s picks the datastore, certificates and token up when it's installed
+sudo mkdir -p /var/lib/rancher/k3s/server
+sudo cp -a "$STAGE"/k3s/. /var/lib/rancher/k3s/server/
+
+if sudo docker inspect registry > /dev/null 2>&1; then
+  sudo docker cp "$STAGE"/registry/. registry:/var/lib/registry/
+  sudo docker restart registry
+else
+  sudo mkdir -p /var/lib/nya/registry
+  sudo cp -a "$STAGE"/registry/. /var/lib/nya/registry/
+fi
+
+# mkcert reuses an existing CA, so client trust survives the rebuild
+sudo mkdir -p /root/.local/share/mkcert /etc/nya/certs
+sudo cp -a "$STAGE"/mkcert/. /root/.local/share/mkcert/
+sudo cp -a "$STAGE"/certs/. /etc/nya/certs/
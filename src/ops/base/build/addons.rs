@@ -0,0 +1,223 @@
+use std::{fs, path::{Path, PathBuf}};
+use colored::*;
+use openssh::Session;
+use serde::Serialize;
+use serde_json::Value;
+use tera::Context;
+
+use crate::{core::{payload::Payload, runtime::Nya}, ops::{retry::RetryPolicy, types::{AddonConfig, BaseNodeConfig, NodeCommandResult}, upload::{UploadOptions, upload_dir, upload_file}, utils::{create_ssh_session, get_addons, get_control_plane_config, run_on_node_with}}};
+
+const INSTALL_ADDON_SCRIPT: &str = include_str!("scripts/install_addon.sh");
+const REMOVE_ADDON_SCRIPT: &str = include_str!("scripts/remove_addon.sh");
+const ADDONS_REMOTE_DIR: &str = "/opt/nya/addons";
+
+#[derive(Serialize)]
+struct AddonContext {
+  name: String,
+  repo: Option<String>,
+  chart: String,
+  namespace: String,
+  version: Option<String>,
+  values_files: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct RemoveAddonContext {
+  name: String,
+  namespace: String,
+}
+
+pub async fn install_addons(nya: Nya, _: Payload) {
+  let addons: Vec<AddonConfig> = get_addons(nya.clone()).await;
+  if addons.is_empty() {
+    return;
+  }
+
+  println!("Installing addons");
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let Some(session) = create_ssh_session(&nya, &control_plane_config).await else {
+    return;
+  };
+  let config_dir: PathBuf = nya.base_config_path().parent().map(Path::to_path_buf).unwrap_or_default();
+
+  let mut failed: Vec<String> = Vec::new();
+  for addon in addons.iter() {
+    println!("Installing addon {}...", addon.name);
+    match install_addon(&nya, &control_plane_config.host, &session, addon, &config_dir).await {
+      Ok(()) => println!("Addon {} installed successfully.", addon.name),
+      Err(err) => {
+        eprintln!("Failed to install addon {}: {}", addon.name, err);
+        failed.push(addon.name.clone());
+      }
+    }
+  }
+
+  if let Err(e) = session.close().await {
+    eprintln!("install_addons: failed to close control plane session: {}", e);
+  }
+
+  if !failed.is_empty() {
+    eprintln!("{}", format!("Failed to install addons: {}", failed.join(", ")).red());
+    nya.stop();
+  }
+}
+
+async fn install_addon(nya: &Nya, host: &str, session: &Session, addon: &AddonConfig, config_dir: &Path) -> Result<(), String> {
+  let remote_dir = format!("{}/{}", ADDONS_REMOTE_DIR, addon.name);
+
+  let chart = match chart_source(addon, config_dir)? {
+    ChartSource::Repo(chart) => chart,
+    ChartSource::Local(local_chart) => {
+      let remote_chart = format!("{}/chart", remote_dir);
+      upload_dir(session, &local_chart, &remote_chart, &UploadOptions { prune: true, ..Default::default() }).await?;
+      remote_chart
+    },
+  };
+
+  let mut values_files: Vec<String> = Vec::new();
+  if let Some(values_file) = &addon.values_file {
+    let local_values = resolve_local_path(config_dir, values_file);
+    let content = fs::read(&local_values)
+      .map_err(|e| format!("failed to read values file '{}': {}", local_values.display(), e))?;
+    let remote_values = format!("{}/values.yaml", remote_dir);
+    upload_file(session, &content, &remote_values, &UploadOptions::default()).await?;
+    values_files.push(remote_values);
+  }
+
+  // Inline values are applied last so they win over the values file
+  if let Some(values) = &addon.values {
+    let content = serde_json::to_vec_pretty(values).map_err(|e| e.to_string())?;
+    let remote_values = format!("{}/inline-values.json", remote_dir);
+    upload_file(session, &content, &remote_values, &UploadOptions::default()).await?;
+    values_files.push(remote_values);
+  }
+
+  let addon_context = AddonContext {
+    name: addon.name.clone(),
+    repo: addon.repo.clone(),
+    chart,
+    namespace: addon.namespace.clone(),
+    version: addon.version.clone(),
+    values_files,
+  };
+  let rendered_script: String = install_script(&addon_context)?;
+
+  match run_on_node_with(nya, host, session, &rendered_script, &RetryPolicy::INSTALL).await {
+    NodeCommandResult::Success => Ok(()),
+    NodeCommandResult::Failure(err) => Err(err),
+  }
+}
+
+#[derive(Debug, PartialEq)]
+enum ChartSource {
+  Repo(String),
+  Local(PathBuf),
+}
+
+/// Where an addon's chart comes from; a repo wins over a local path.
+fn chart_source(addon: &AddonConfig, config_dir: &Path) -> Result<ChartSource, String> {
+  match (&addon.repo, &addon.path) {
+    (Some(_), _) => Ok(ChartSource::Repo(repo_chart(addon))),
+    (None, Some(path)) => Ok(ChartSource::Local(resolve_local_path(config_dir, path))),
+    (None, None) => Err("addon needs either a repo or a path".to_string()),
+  }
+}
+
+/// A chart from the addon's repo, which is added under the addon's name.
+fn repo_chart(addon: &AddonConfig) -> String {
+  format!("{}/{}", addon.name, addon.chart.as_deref().unwrap_or(&addon.name))
+}
+
+fn install_script(addon_context: &AddonContext) -> Result<String, String> {
+  let context_value: Value = serde_json::to_value(addon_context).map_err(|e| e.to_string())?;
+  let tera_context: Context = Context::from_serialize(&context_value).map_err(|e| e.to_string())?;
+  tera::Tera::one_off(INSTALL_ADDON_SCRIPT, &tera_context, false).map_err(|e| e.to_string())
+}
+
+/// Uninstalls the addon's release and removes the files uploaded for it.
+pub(crate) fn remove_script(addon: &AddonConfig) -> String {
+  let context = RemoveAddonContext { name: addon.name.clone(), namespace: addon.namespace.clone() };
+  tera::Tera::one_off(
+    REMOVE_ADDON_SCRIPT,
+    &Context::from_serialize(serde_json::to_value(context).unwrap()).unwrap(),
+    false,
+  ).unwrap()
+}
+
+fn resolve_local_path(config_dir: &Path, path: &str) -> PathBuf {
+  let expanded = PathBuf::from(shellexpand::tilde(path).to_string());
+  if expanded.is_absolute() {
+    expanded
+  } else {
+    config_dir.join(expanded)
+  }
+}
+
+#[cfg(test)]
+mod addons_tests {
+  use std::path::{Path, PathBuf};
+  use serde_json::json;
+  use crate::ops::{base::build::addons::{AddonContext, ChartSource, chart_source, install_script, remove_script, repo_chart}, types::AddonConfig};
+
+  fn addon(config: serde_json::Value) -> AddonConfig {
+    serde_json::from_value(config).unwrap()
+  }
+
+  #[test]
+  fn installs_a_repo_chart_with_its_values_in_order() {
+    let addon = addon(json!({ "name": "monitoring", "repo": "https://prometheus-community.github.io/helm-charts", "chart": "kube-prometheus-stack", "version": "58.2.1", "namespace": "monitoring" }));
+    let chart = repo_chart(&addon);
+    assert_eq!(chart, "monitoring/kube-prometheus-stack");
+
+    let script = install_script(&AddonContext {
+      name: addon.name.clone(),
+      repo: addon.repo.clone(),
+      chart,
+      namespace: addon.namespace.clone(),
+      version: addon.version.clone(),
+      values_files: vec!["/opt/nya/addons/monitoring/values.yaml".to_string(), "/opt/nya/addons/monitoring/inline-values.json".to_string()],
+    }).unwrap();
+    assert!(script.contains("helm repo add monitoring https://prometheus-community.github.io/helm-charts --force-update"));
+    assert!(script.contains("helm upgrade --install monitoring monitoring/kube-prometheus-stack"));
+    assert!(script.contains("--namespace monitoring"));
+    assert!(script.contains("--version 58.2.1"));
+    let values = script.find("-f /opt/nya/addons/monitoring/values.yaml").unwrap();
+    let inline = script.find("-f /opt/nya/addons/monitoring/inline-values.json").unwrap();
+    assert!(values < inline);
+  }
+
+  #[test]
+  fn installs_a_local_chart_without_adding_a_repo() {
+    let addon = addon(json!({ "name": "cert-manager", "path": "charts/cert-manager" }));
+    assert_eq!(
+      chart_source(&addon, Path::new("/home/nya/base")).unwrap(),
+      ChartSource::Local(PathBuf::from("/home/nya/base/charts/cert-manager")),
+    );
+
+    let script = install_script(&AddonContext {
+      name: addon.name.clone(),
+      repo: None,
+      chart: "/opt/nya/addons/cert-manager/chart".to_string(),
+      namespace: addon.namespace.clone(),
+      version: None,
+      values_files: Vec::new(),
+    }).unwrap();
+    assert!(!script.contains("helm repo add"));
+    assert!(script.contains("helm upgrade --install cert-manager /opt/nya/addons/cert-manager/chart"));
+    assert!(script.contains("--namespace default"));
+    assert!(!script.contains("--version"));
+    assert!(!script.contains(" -f "));
+  }
+
+  #[test]
+  fn needs_a_repo_or_a_path() {
+    assert!(chart_source(&addon(json!({ "name": "cert-manager" })), Path::new("/home/nya/base")).is_err());
+  }
+
+  #[test]
+  fn uninstalls_the_release_from_its_namespace() {
+    let script = remove_script(&addon(json!({ "name": "monitoring", "repo": "https://example.com/charts", "namespace": "monitoring" })));
+    assert!(script.contains("helm uninstall monitoring \\\n  --namespace monitoring"));
+    assert!(script.contains("rm -rf /opt/nya/addons/monitoring"));
+  }
+}
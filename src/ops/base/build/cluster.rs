@@ -5,28 +5,30 @@ use openssh::Session;
 use tera::Context;
 use serde_json::Value;
 use include_dir::{include_dir, Dir};
-use base64::{Engine as _, engine::general_purpose};
 
-use crate::{core::{checks::{Check, CheckIf}, payload::{Payload, Take}, runtime::Nya}, ops::{types::{BaseNodeConfig, NodeCommandResult, ClusterBind9Context}, utils::{create_ssh_session, get_control_plane_config, get_node_configs, run_on_node}}};
-use crate::ops::utils::get_from_node;
+use crate::{core::{checks::{Check, CheckIf}, payload::{Payload, Take}, runtime::Nya}, ops::{types::{BaseNodeConfig, NodeCommandResult, ClusterBind9Context}, utils::{create_ssh_session, get_control_plane_config, get_node_configs, run_on_node, run_on_node_with}}};
+use crate::ops::base::build::{mirror::{RegistryMirror, registry_mirrors}, wireguard::{OVERLAY_INTERFACE, overlay_address}};
+use crate::ops::{base::bundle::BUNDLE_REMOTE_DIR, distro::{Component, PackageManager, package_manager, run_package_command}, retry::RetryPolicy, upload::{TreeUploadResult, UploadOptions, UploadResult, upload_embedded_dir, upload_file, upload_files}, utils::{get_from_node, is_offline}};
 
 const K3S_REGISTRIES_TEMPLATE: &str = include_str!("templates/registries.yaml");
-const NAMED_CONF_LOCAL_TEMPLATE: &str = include_str!("templates/named.conf.local");
-const NAMED_CONF_OPTIONS_TEMPLATE: &str = include_str!("templates/named.conf.options");
-const BIND9_DB_TEMPLATE: &str = include_str!("templates/bind9.db");
+pub(crate) const NAMED_CONF_LOCAL_TEMPLATE: &str = include_str!("templates/named.conf.local");
+pub(crate) const NAMED_CONF_OPTIONS_TEMPLATE: &str = include_str!("templates/named.conf.options");
+pub(crate) const BIND9_DB_TEMPLATE: &str = include_str!("templates/bind9.db");
+pub(crate) const INSTALL_MKCERT_SCRIPT: &str = include_str!("scripts/install_mkcert.sh");
 const HELM_DIR: Dir = include_dir!("src/ops/base/build/helm");
-const HELM_TEMPLATES_DIR: Dir = include_dir!("src/ops/base/build/helm/templates");
 
 
 #[derive(serde::Serialize, Clone, Debug)]
 struct K3sAgentScriptContext {
   control_plane_ip: String,
   registry_host: String,
+  mirrors: Vec<RegistryMirror>,
 }
 
 #[derive(serde::Serialize, Clone, Debug)]
 struct TLSScriptContext {
   domain: String,
+  offline: bool,
 }
 
 pub async fn complete_cluster(nya: Nya, _: Payload) {
@@ -34,11 +36,15 @@ pub async fn complete_cluster(nya: Nya, _: Payload) {
   let nodes_configs: Vec<BaseNodeConfig> = get_node_configs(nya.clone()).await;
   let mut node_triggers: Vec<(&str, Payload)> = Vec::new();
   for node in nodes_configs.iter() {
-    let session: Session = create_ssh_session(node).await;
+    let Some(session) = create_ssh_session(&nya, node).await else {
+      return;
+    };
     node_triggers.push(("registerNode", Payload::new((session, node.clone()))));
   }
 
-  let control_plane_session = create_ssh_session(&control_plane_config).await;
+  let Some(control_plane_session) = create_ssh_session(&nya, &control_plane_config).await else {
+    return;
+  };
   let session_arc = Arc::new(control_plane_session);
 
   let _ = nya.trigger_all(node_triggers).await;
@@ -61,48 +67,69 @@ pub async fn register_node(nya: Nya, payload: Payload) {
   let session_obj = payload.take::<(Session, BaseNodeConfig)>().unwrap();
   let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
   let k3s_token: String = nya.get("k3s_node_token").await.as_str().unwrap().to_string();
-  let k3s_install_cmd = format!(
-    "curl -sfL https://get.k3s.io | K3S_URL=https://{}:6443 K3S_TOKEN={} sh - > /tmp/k3s-install.log 2>&1",
-    control_plane_config.host, k3s_token
-  );
-  let k3s_wait_cmd = "sudo systemctl is-active --wait k3s-agent";
+  // Over the WireGuard overlay the agent joins and registers with overlay addresses
+  let (server_host, agent_env) = match (overlay_address(&nya, &control_plane_config.host).await, overlay_address(&nya, &session_obj.1.host).await) {
+    (Some(server_ip), Some(node_ip)) => (server_ip, format!("INSTALL_K3S_EXEC='--node-ip={} --flannel-iface={}' ", node_ip, OVERLAY_INTERFACE)),
+    _ => (control_plane_config.host.clone(), String::new()),
+  };
+  let k3s_install_cmd = if is_offline(nya.clone()).await {
+    format!(
+      "sudo mkdir -p /var/lib/rancher/k3s/agent/images && \
+      sudo cp /opt/nya/bundle/k3s/k3s-airgap-images-*.tar.zst /opt/nya/bundle/images/nya-images.tar /var/lib/rancher/k3s/agent/images/ && \
+      sudo install -m 0755 /opt/nya/bundle/k3s/k3s /usr/local/bin/k3s && \
+      INSTALL_K3S_SKIP_DOWNLOAD=true {}K3S_URL=https://{}:6443 K3S_TOKEN={} sh /opt/nya/bundle/k3s/install.sh",
+      agent_env, server_host, k3s_token
+    )
+  } else {
+    format!(
+      "curl -sfL https://get.k3s.io | {}K3S_URL=https://{}:6443 K3S_TOKEN={} sh -",
+      agent_env, server_host, k3s_token
+    )
+  };
+  let k3s_wait_cmd = "for i in $(seq 1 60); do \
+    (systemctl is-active --quiet k3s-agent 2>/dev/null || sudo rc-service k3s-agent status > /dev/null 2>&1) && exit 0; \
+    sleep 5; \
+  done; exit 1";
 
   let control_plane_context = K3sAgentScriptContext {
     control_plane_ip: control_plane_config.host.clone(),
     registry_host: nya.get("nya.registry_host").await.as_str().unwrap_or("").to_string(),
+    mirrors: registry_mirrors(&nya).await,
   };
 
   let context_value: Value = serde_json::to_value(&control_plane_context).unwrap();
   let tera_context: Context = Context::from_serialize(&context_value).unwrap();
   let rendered_registries = tera::Tera::one_off(K3S_REGISTRIES_TEMPLATE, &tera_context, false).unwrap();
 
-  if !Check::run(CheckIf::K3sAgentIsRunning, &session_obj.0).await {
-    let create_k3s_dir_cmd: &str = "sudo mkdir -p /etc/rancher/k3s && sudo chmod 755 /etc/rancher/k3s";
-    let create_dir_result: NodeCommandResult = run_on_node(&session_obj.0, create_k3s_dir_cmd).await;
-    match create_dir_result {
-      NodeCommandResult::Success => println!("Created /etc/rancher/k3s directory successfully."),
-      NodeCommandResult::Failure(err) => {
-        eprintln!("Failed to create /etc/rancher/k3s directory: {}", err);
+  let agent_running = Check::run(CheckIf::K3sAgentIsRunning, &session_obj.0).await;
+  let registry_result = upload_file(&session_obj.0, rendered_registries.as_bytes(), "/etc/rancher/k3s/registries.yaml", &UploadOptions::default()).await;
+  match registry_result {
+    Ok(UploadResult::Uploaded) if agent_running => {
+      // A running agent only picks up registries.yaml when it restarts
+      let restart = match package_manager(&nya, &session_obj.1.host, &session_obj.0).await {
+        Ok(package_manager) => package_manager.restart_service("k3s-agent"),
+        Err(err) => {
+          eprintln!("Failed to restart the K3s agent on node {}: {}", session_obj.1.host, err);
+          return;
+        },
+      };
+      if let NodeCommandResult::Failure(err) = run_on_node_with(&nya, &session_obj.1.host, &session_obj.0, &restart, &RetryPolicy::COMMAND).await {
+        eprintln!("Failed to restart the K3s agent on node {}: {}", session_obj.1.host, err);
         return;
-      },
-    }
+      }
+      println!("K3s registry configuration applied successfully.");
+    },
+    Ok(_) => println!("K3s registry configuration applied successfully."),
+    Err(err) => {
+      eprintln!("Failed to apply K3s registry configuration: {}", err);
+      return;
+    },
+  }
 
-    let encoded_registries = general_purpose::STANDARD.encode(&rendered_registries);
-    let registry_cmd = format!(
-      "echo '{}' | base64 -d | sudo tee /etc/rancher/k3s/registries.yaml",
-      encoded_registries
-    );
-    let registry_result: NodeCommandResult = run_on_node(&session_obj.0, &registry_cmd).await;
-    match registry_result {
-      NodeCommandResult::Success => println!("K3s registry configuration applied successfully."),
-      NodeCommandResult::Failure(err) => {
-        eprintln!("Failed to apply K3s registry configuration: {}", err);
-        return;
-      },
-    }
+  if !agent_running {
 
     println!("Starting K3s agent install on node {} and registering with control plane...", session_obj.1.host);
-    let k3s_agent_install_result = run_on_node(&session_obj.0, &k3s_install_cmd).await;
+    let k3s_agent_install_result = run_on_node_with(&nya, &session_obj.1.host, &session_obj.0, &k3s_install_cmd, &RetryPolicy::INSTALL).await;
     match k3s_agent_install_result {
       NodeCommandResult::Success => println!("K3s agent install running on node {}.", session_obj.1.host),
       NodeCommandResult::Failure(err) => {
@@ -112,12 +139,11 @@ pub async fn register_node(nya: Nya, payload: Payload) {
     }
 
     println!("Waiting for K3s agent install on node {}.", session_obj.1.host);
-    let k3s_install_wait_result = run_on_node(&session_obj.0, &k3s_wait_cmd).await;
+    let k3s_install_wait_result = run_on_node_with(&nya, &session_obj.1.host, &session_obj.0, k3s_wait_cmd, &RetryPolicy::WAIT).await;
     match k3s_install_wait_result {
       NodeCommandResult::Success => println!("K3s agent successfully installed and registered on node {}.", session_obj.1.host),
       NodeCommandResult::Failure(err) => {
         eprintln!("K3s install on node {} did not complete successfully: {}", session_obj.1.host, err);
-        return;
       },
     }
   } else {
@@ -125,15 +151,25 @@ pub async fn register_node(nya: Nya, payload: Payload) {
   }
 }
 
+/// Installs bind9 from the packages in the bundle, failing clearly when the
+/// bundle was made for another distro family.
+fn offline_bind9_install(package_manager: PackageManager) -> String {
+  let dir = format!("{}/bind9", BUNDLE_REMOTE_DIR);
+  format!(
+    "if ! ls {0}/*.{1} > /dev/null 2>&1; then echo 'The bundle has no bind9 .{1} packages, create it with nya.bundle.bind9_image set to the same distro as the nodes' >&2; exit 1; fi && {2}",
+    dir, package_manager.package_ext(), package_manager.install_local(&dir),
+  )
+}
+
 pub async fn setup_bind9(nya: Nya, payload: Payload) {
   let session: Arc<Session> = payload.take::<Arc<Session>>().unwrap();
 
   let control_plane: Value = nya.get("nya.control_plane").await;
   let control_plane_vars: Value = nya.get("nya.control_plane.vars").await;
-  let control_plane_ip: &str = control_plane.get("host").unwrap().as_str().unwrap_or("");
+  let control_plane_ip: &str = control_plane.get("host").and_then(|v| v.as_str()).unwrap_or("");
   let network_cidr_value= nya.get("network_cidr").await;
   let network_cidr = network_cidr_value.as_str().unwrap_or("");
-  let domain_name: &str = control_plane_vars.get("domain_name").unwrap().as_str().unwrap_or("");
+  let domain_name: &str = control_plane_vars.get("domain_name").and_then(|v| v.as_str()).unwrap_or("");
   let bind9_context: ClusterBind9Context = ClusterBind9Context {
     control_plane_ip: control_plane_ip.to_string(),
     network_cidr: network_cidr.to_string(),
@@ -146,34 +182,32 @@ pub async fn setup_bind9(nya: Nya, payload: Payload) {
   let rendered_options: String = tera::Tera::one_off(NAMED_CONF_OPTIONS_TEMPLATE, &tera_context, false).unwrap();
   let rendered_db: String = tera::Tera::one_off(BIND9_DB_TEMPLATE, &tera_context, false).unwrap();
 
-  let dpkg_config: &str = "sudo dpkg --configure -a";
-  let apt_get: &str = "sudo apt-get install -f -y";
-
-  let install_bind9_cmd: &str = "sudo apt update && sudo apt install -y bind9 bind9utils bind9-doc";
-  let configure_bind9_cmd: &str = "sudo mkdir -p /etc/bind/zones && sudo chmod 755 /etc/bind/zones";
-  let local_cmd: String = format!("echo '{}' | sudo tee /etc/bind/named.conf.local", rendered_local);
-  let options_cmd: String = format!("echo '{}' | sudo tee /etc/bind/named.conf.options", rendered_options);
-  let db_cmd: String = format!("echo '{}' | sudo tee /etc/bind/zones/db.{}", rendered_db, bind9_context.domain_name);
-
-  let dpkg_result = run_on_node(&session, dpkg_config).await;
-  match dpkg_result {
-    NodeCommandResult::Success => println!("dpkg configured successfully."),
-    NodeCommandResult::Failure(err) => {
-      eprintln!("Failed to configure dpkg: {}", err);
+  let package_manager = match package_manager(&nya, control_plane_ip, &session).await {
+    Ok(package_manager) => package_manager,
+    Err(err) => {
+      eprintln!("Failed to install Bind9: {}", err);
       return;
     },
-  }
+  };
+  let bind9_service: &str = package_manager.service(Component::Bind9);
 
-  let apt_get_result = run_on_node(&session, apt_get).await;
-  match apt_get_result {
-    NodeCommandResult::Success => println!("apt-get dependencies installed successfully."),
-    NodeCommandResult::Failure(err) => {
-      eprintln!("Failed to install apt-get dependencies: {}", err);
-      return;
-    },
-  }
-  
-  let bind9_install_result = run_on_node(&session, install_bind9_cmd).await;
+  let install_bind9_cmd: String = if is_offline(nya.clone()).await {
+    offline_bind9_install(package_manager)
+  } else {
+    package_manager.install(package_manager.packages(Component::Bind9))
+  };
+  let configure_bind9_cmd: String = format!(
+    "sudo mkdir -p /etc/bind/zones /var/cache/bind && sudo chmod 755 /etc/bind/zones && sudo chgrp {} /var/cache/bind && sudo chmod 775 /var/cache/bind",
+    package_manager.bind_user()
+  );
+  let bind9_files: Vec<(String, Vec<u8>)> = vec![
+    ("named.conf.local".to_string(), rendered_local.into_bytes()),
+    ("named.conf.options".to_string(), rendered_options.into_bytes()),
+    (format!("zones/db.{}", bind9_context.domain_name), rendered_db.into_bytes()),
+  ];
+  let bind9_upload_options = UploadOptions { owner: Some(format!("root:{}", package_manager.bind_user())), ..Default::default() };
+
+  let bind9_install_result = run_package_command(&nya, control_plane_ip, &session, package_manager, &install_bind9_cmd).await;
   match bind9_install_result {
     NodeCommandResult::Success => println!("Bind9 installed successfully."),
     NodeCommandResult::Failure(err) => {
@@ -182,7 +216,7 @@ pub async fn setup_bind9(nya: Nya, payload: Payload) {
     },
   }
 
-  let bind9_configure_result = run_on_node(&session, configure_bind9_cmd).await;
+  let bind9_configure_result = run_on_node(&nya, control_plane_ip, &session, &configure_bind9_cmd).await;
   match bind9_configure_result {
     NodeCommandResult::Success => println!("Bind9 configured successfully."),
     NodeCommandResult::Failure(err) => {
@@ -190,81 +224,83 @@ pub async fn setup_bind9(nya: Nya, payload: Payload) {
       return;
     },
   }
-  
-  let bind9_local_result = run_on_node(&session, &local_cmd).await;
-  match bind9_local_result {
-    NodeCommandResult::Success => println!("Bind9 local configuration applied successfully."),
-    NodeCommandResult::Failure(err) => {
-      eprintln!("Failed to apply Bind9 local configuration: {}", err);
-      return;
-    },
+
+  if let Some(main_conf) = package_manager.bind_main_conf() {
+    let main_conf_content: &str = "include \"/etc/bind/named.conf.options\";\ninclude \"/etc/bind/named.conf.local\";\n";
+    let main_conf_result = upload_file(&session, main_conf_content.as_bytes(), main_conf, &bind9_upload_options).await;
+    match main_conf_result {
+      Ok(_) => println!("Bind9 main configuration applied successfully."),
+      Err(err) => {
+        eprintln!("Failed to apply Bind9 main configuration: {}", err);
+        return;
+      },
+    }
   }
   
-  let bind9_options_result = run_on_node(&session, &options_cmd).await;
-  match bind9_options_result {
-    NodeCommandResult::Success => println!("Bind9 options configuration applied successfully."),
-    NodeCommandResult::Failure(err) => {
-      eprintln!("Failed to apply Bind9 options configuration: {}", err);
+  let bind9_files_result = upload_files(&session, bind9_files, "/etc/bind", &bind9_upload_options).await;
+  match bind9_files_result {
+    Ok(result) => println!("Bind9 configuration and zone files applied successfully ({} updated, {} unchanged).", result.uploaded, result.unchanged),
+    Err(err) => {
+      eprintln!("Failed to apply Bind9 configuration: {}", err);
       return;
     },
   }
 
-  let bind9_db_result = run_on_node(&session, &db_cmd).await;
-  match bind9_db_result {
-    NodeCommandResult::Success => println!("Bind9 zone file applied successfully."),
+  let bind9_enable_result = run_on_node(&nya, control_plane_ip, &session, &package_manager.enable_service(bind9_service)).await;
+  match bind9_enable_result {
+    NodeCommandResult::Success => println!("Bind9 service enabled successfully."),
     NodeCommandResult::Failure(err) => {
-      eprintln!("Failed to apply Bind9 zone file: {}", err);
-      return;
+      eprintln!("Failed to enable Bind9 service: {}", err);
     },
   }
 }
 
-pub async fn setup_helm(_: Nya, payload: Payload) {
+/// Uploads the Nya chart to the control plane, skipping files that haven't changed.
+pub(crate) async fn upload_chart(session: &Session) -> Result<TreeUploadResult, String> {
+  upload_embedded_dir(session, &HELM_DIR, "/opt/nya/charts", &UploadOptions { prune: true, ..Default::default() }).await
+}
+
+pub async fn setup_helm(nya: Nya, payload: Payload) {
   let session: Arc<Session> = payload.take::<Arc<Session>>().unwrap();
-  for file in HELM_DIR.files() {
-    let path: String = format!("/opt/nya/charts/{}", file.path().display());
-    let content: &str = file.contents_utf8().unwrap();
-    let encoded = general_purpose::STANDARD.encode(content);
-    let cmd: String = format!("sudo mkdir -p $(dirname {}) && echo '{}' | base64 -d | sudo tee {}", path, encoded, path);
-    run_on_node(&session, &cmd).await;
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  match upload_chart(&session).await {
+    Ok(result) => println!("Nya chart uploaded ({} updated, {} unchanged).", result.uploaded, result.unchanged),
+    Err(err) => eprintln!("Failed to upload the Nya chart: {}", err),
   }
 
-    for file in HELM_TEMPLATES_DIR.files() {
-        let path: String = format!("/opt/nya/charts/templates/{}", file.path().display());
-        let content: &str = file.contents_utf8().unwrap();
-        let encoded = general_purpose::STANDARD.encode(content);
-        let cmd: String = format!("sudo mkdir -p $(dirname {}) && echo '{}' | base64 -d | sudo tee {}", path, encoded, path);
-        run_on_node(&session, &cmd).await;
-    }
-
   let k3s_yaml_cmd = "sudo chmod 0644 /etc/rancher/k3s/k3s.yaml && sudo chown $USER:$USER /etc/rancher/k3s/k3s.yaml";
-  let _ = run_on_node(&session, k3s_yaml_cmd).await;
+  let _ = run_on_node(&nya, &control_plane_config.host, &session, k3s_yaml_cmd).await;
 }
 
 pub async fn setup_tls(nya: Nya, payload: Payload) {
   let session: Arc<Session> = payload.take::<Arc<Session>>().unwrap();
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
   let domain_name: Value = nya.get("nya.control_plane.vars").await;
-  let domain_name_str: &str = domain_name.get("domain_name").unwrap().as_str().unwrap_or("");
+  let domain_name_str: &str = domain_name.get("domain_name").and_then(|v| v.as_str()).unwrap_or("");
   let tls_context = TLSScriptContext {
     domain: domain_name_str.to_string(),
+    offline: is_offline(nya.clone()).await,
   };
   let context_value: Value = serde_json::to_value(&tls_context).unwrap();
   let tera_context: Context = Context::from_serialize(&context_value).unwrap();
-  let setup_mkcert_cmd: String = tera::Tera::one_off(include_str!("scripts/install_mkcert.sh"), &tera_context, false).unwrap();
-  let result: NodeCommandResult = run_on_node(&session, &setup_mkcert_cmd).await;
+  let setup_mkcert_cmd: String = tera::Tera::one_off(INSTALL_MKCERT_SCRIPT, &tera_context, false).unwrap();
+  let result: NodeCommandResult = run_on_node_with(&nya, &control_plane_config.host, &session, &setup_mkcert_cmd, &RetryPolicy::INSTALL).await;
   match result {
     NodeCommandResult::Success => println!("TLS setup completed successfully."),
     NodeCommandResult::Failure(err) => {
       eprintln!("Failed to set up TLS: {}", err);
-      return;
     },
   }
 }
 
 pub async fn on_build_complete(nya: Nya, _: Payload) {
   let control_plane_base_config = get_control_plane_config(nya.clone()).await;
-  let control_plane_session = create_ssh_session(&control_plane_base_config).await;
+  let Some(control_plane_session) = create_ssh_session(&nya, &control_plane_base_config).await else {
+    return;
+  };
   let ingress_ip = get_from_node(
+    &nya,
+    &control_plane_base_config.host,
     &control_plane_session,
     "kubectl get svc -n ingress-nginx ingress-nginx-controller -o jsonpath='{.status.loadBalancer.ingress[0].ip}'"
   ).await.unwrap();
@@ -273,7 +309,7 @@ pub async fn on_build_complete(nya: Nya, _: Payload) {
   let control_plane_vars: Value = nya.get("nya.control_plane.vars").await;
   let network_cidr_value= nya.get("network_cidr").await;
   let network_cidr = network_cidr_value.as_str().unwrap_or("");
-  let domain_name: &str = control_plane_vars.get("domain_name").unwrap().as_str().unwrap_or("");
+  let domain_name: &str = control_plane_vars.get("domain_name").and_then(|v| v.as_str()).unwrap_or("");
   let bind9_context: ClusterBind9Context = ClusterBind9Context {
     control_plane_ip: ingress_ip,
     network_cidr: network_cidr.to_string(),
@@ -283,19 +319,18 @@ pub async fn on_build_complete(nya: Nya, _: Payload) {
   let context_value: Value = serde_json::to_value(&bind9_context).unwrap();
   let tera_context: Context = Context::from_serialize(&context_value).unwrap();
   let rendered_db: String = tera::Tera::one_off(BIND9_DB_TEMPLATE, &tera_context, false).unwrap();
-  let encoded_db = general_purpose::STANDARD.encode(&rendered_db);
-  let db_cmd = format!(
-    "echo '{}' | base64 -d | sudo tee /etc/bind/zones/db.{}",
-    encoded_db, bind9_context.domain_name
-  );
-  let bind9_db_result = run_on_node(&control_plane_session, &db_cmd).await;
-  match bind9_db_result {
-    NodeCommandResult::Success => println!("Updated Bind9 zone file."),
-    NodeCommandResult::Failure(err) => {
-      eprintln!("Failed to update Bind9 zone file: {}", err);
+  match package_manager(&nya, &control_plane_base_config.host, &control_plane_session).await {
+    Ok(package_manager) => {
+      let db_path = format!("/etc/bind/zones/db.{}", bind9_context.domain_name);
+      let db_options = UploadOptions { owner: Some(format!("root:{}", package_manager.bind_user())), ..Default::default() };
+      match upload_file(&control_plane_session, rendered_db.as_bytes(), &db_path, &db_options).await {
+        Ok(_) => println!("Updated Bind9 zone file."),
+        Err(err) => eprintln!("Failed to update Bind9 zone file: {}", err),
+      }
+      run_on_node(&nya, &control_plane_base_config.host, &control_plane_session, &package_manager.restart_service(package_manager.service(Component::Bind9))).await;
+      run_on_node(&nya, &control_plane_base_config.host, &control_plane_session, &package_manager.restart_service("k3s")).await;
     },
+    Err(err) => eprintln!("Failed to restart services: {}", err),
   }
-  run_on_node(&control_plane_session, "sudo systemctl restart bind9").await;
-  run_on_node(&control_plane_session, "sudo systemctl restart k3s").await;
   println!("Restarted k3s on node {}", &control_plane_base_config.host);
 }
\ No newline at end of file
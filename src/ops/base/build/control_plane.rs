@@ -1,42 +1,56 @@
-use crate::{core::{checks::{Check, CheckIf}, payload::Payload, runtime::Nya}, ops::{types::NodeCommandResult, utils::{get_control_plane_config, get_from_node, run_on_node}}};
+use crate::{core::{checks::{Check, CheckIf}, payload::Payload, runtime::Nya}, ops::{retry::RetryPolicy, types::NodeCommandResult, utils::{get_control_plane_config, get_from_node, is_offline, run_on_node_with}}};
 use crate::ops::{types, utils};
-use openssh::Session;
 use tera::Context;
 use types::BaseNodeConfig;
 use utils::create_ssh_session;
-use crate::ops::utils::get_node_configs;
+use crate::ops::{base::build::{mirror::{RegistryMirror, registry_mirrors}, wireguard::{OVERLAY_INTERFACE, overlay_address}}, distro::package_manager, upload::{UploadOptions, UploadResult, upload_file}, utils::get_node_configs};
 
-const INSTALL_K3S_SCRIPT: &str = include_str!("scripts/install_k3s.sh");
-const K3S_REGISTRIES_TEMPLATE: &str = include_str!("templates/registries.yaml");
-const INSTALL_HELM_SCRIPT: &str = include_str!("scripts/install_helm.sh");
+pub(crate) const INSTALL_K3S_SCRIPT: &str = include_str!("scripts/install_k3s.sh");
+pub(crate) const K3S_REGISTRIES_TEMPLATE: &str = include_str!("templates/registries.yaml");
+pub(crate) const INSTALL_HELM_SCRIPT: &str = include_str!("scripts/install_helm.sh");
 
 #[derive(serde::Serialize)]
 struct K3sScriptContext {
   control_plane_ip: String,
+  /// The overlay address when WireGuard is enabled, the control plane's host otherwise.
+  node_ip: String,
+  flannel_iface: Option<String>,
   k3s_token: String,
   registry_host: String,
+  mirrors: Vec<RegistryMirror>,
+  offline: bool,
 }
 
 pub async fn build_control_plane_action(nya: Nya, _: Payload) {
   println!("Building the control plane");
 
   let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
-  let k3s_token: String = nya.get("k3s_token").await.as_str().unwrap_or("").to_string();
+  let k3s_token: String = nya.get("nya.k3s_token").await.as_str().unwrap_or("").to_string();
   let registry_host: String = nya.get("nya.registry_host").await.as_str().unwrap_or("").to_string();
+  let overlay_ip = overlay_address(&nya, &control_plane_config.host).await;
   let control_plane_context = K3sScriptContext {
     control_plane_ip: control_plane_config.host.clone(),
+    node_ip: overlay_ip.clone().unwrap_or_else(|| control_plane_config.host.clone()),
+    flannel_iface: overlay_ip.map(|_| OVERLAY_INTERFACE.to_string()),
     k3s_token,
     registry_host,
+    mirrors: registry_mirrors(&nya).await,
+    offline: is_offline(nya.clone()).await,
   };
 
   let context_value = serde_json::to_value(&control_plane_context).unwrap();
   let tera_context = Context::from_serialize(&context_value).unwrap();
   let rendered_script = tera::Tera::one_off(INSTALL_K3S_SCRIPT, &tera_context, false).unwrap();
   let rendered_registries = tera::Tera::one_off(K3S_REGISTRIES_TEMPLATE, &tera_context, false).unwrap();
+  let rendered_helm_script = tera::Tera::one_off(INSTALL_HELM_SCRIPT, &tera_context, false).unwrap();
 
-  let session: Session = create_ssh_session(&control_plane_config).await;
+  let Some(session) = create_ssh_session(&nya, &control_plane_config).await else {
+    return;
+  };
 
   let cidr_result = get_from_node(
+      &nya,
+      &control_plane_config.host,
       &session,
       "ip route | grep -v default | awk '{print $1}' | head -1"
   ).await;
@@ -46,7 +60,7 @@ pub async fn build_control_plane_action(nya: Nya, _: Payload) {
   }
 
   if !Check::run(CheckIf::K3sIsInstalled, &session).await {
-    let k3s_install_result: NodeCommandResult = run_on_node(&session, &rendered_script).await;
+    let k3s_install_result: NodeCommandResult = run_on_node_with(&nya, &control_plane_config.host, &session, &rendered_script, &RetryPolicy::INSTALL).await;
     match k3s_install_result {
       NodeCommandResult::Success => println!("K3s installed successfully on control plane."),
       NodeCommandResult::Failure(err) =>  {
@@ -54,32 +68,43 @@ pub async fn build_control_plane_action(nya: Nya, _: Payload) {
         return;
       },
     }
+  } else {
+    println!("K3s is already installed on control plane, skipping installation.");
+  }
 
-    let create_k3s_dir_cmd = "sudo mkdir -p /etc/rancher/k3s && sudo chmod 755 /etc/rancher/k3s";
-    let create_dir_result = run_on_node(&session, create_k3s_dir_cmd).await;
-    match create_dir_result {
-      NodeCommandResult::Success => println!("Created /etc/rancher/k3s directory successfully."),
-      NodeCommandResult::Failure(err) => {
-        eprintln!("Failed to create /etc/rancher/k3s directory: {}", err);
-        return;
-      },
+  for mirror in control_plane_context.mirrors.iter() {
+    match run_on_node_with(&nya, &control_plane_config.host, &session, &mirror.run_command(), &RetryPolicy::INSTALL).await {
+      NodeCommandResult::Success => println!("Pull-through mirror for {} running at {}.", mirror.upstream, mirror.endpoint),
+      NodeCommandResult::Failure(err) => eprintln!("Failed to start the pull-through mirror for {}: {}", mirror.upstream, err),
     }
+  }
 
-    let registry_cmd = format!("echo '{}' | sudo tee /etc/rancher/k3s/registries.yaml", rendered_registries);
-    let registry_result = run_on_node(&session, &registry_cmd).await;
-    match registry_result {
-      NodeCommandResult::Success => println!("K3s registry configuration applied successfully."),
-      NodeCommandResult::Failure(err) => {
-        eprintln!("Failed to apply K3s registry configuration: {}", err);
+  // k3s only reads registries.yaml when it starts
+  let registry_result = upload_file(&session, rendered_registries.as_bytes(), "/etc/rancher/k3s/registries.yaml", &UploadOptions::default()).await;
+  match registry_result {
+    Ok(UploadResult::Uploaded) => {
+      let restart = match package_manager(&nya, &control_plane_config.host, &session).await {
+        Ok(package_manager) => package_manager.restart_service("k3s"),
+        Err(err) => {
+          eprintln!("Failed to restart K3s after changing its registry configuration: {}", err);
+          return;
+        },
+      };
+      if let NodeCommandResult::Failure(err) = run_on_node_with(&nya, &control_plane_config.host, &session, &restart, &RetryPolicy::COMMAND).await {
+        eprintln!("Failed to restart K3s after changing its registry configuration: {}", err);
         return;
-      },
-    }
-  } else {
-    println!("K3s is already installed on control plane, skipping installation.");
+      }
+      println!("K3s registry configuration applied successfully.");
+    },
+    Ok(UploadResult::Unchanged) => println!("K3s registry configuration is up to date."),
+    Err(err) => {
+      eprintln!("Failed to apply K3s registry configuration: {}", err);
+      return;
+    },
   }
 
   if !Check::run(CheckIf::HelmIsInstalled, &session).await {
-    let helm_install_result: NodeCommandResult = run_on_node(&session, INSTALL_HELM_SCRIPT).await;
+    let helm_install_result: NodeCommandResult = run_on_node_with(&nya, &control_plane_config.host, &session, &rendered_helm_script, &RetryPolicy::INSTALL).await;
     match helm_install_result {
       NodeCommandResult::Success => println!("Helm installed successfully on control plane."),
       NodeCommandResult::Failure(err) => {
@@ -92,9 +117,9 @@ pub async fn build_control_plane_action(nya: Nya, _: Payload) {
   }
   
   let node_configs = get_node_configs(nya.clone()).await;
-  if node_configs.len() > 0 {
+  if !node_configs.is_empty() {
     let get_node_token_cmd = "sudo cat /var/lib/rancher/k3s/server/node-token";
-    let token_result = get_from_node(&session, get_node_token_cmd).await;
+    let token_result = get_from_node(&nya, &control_plane_config.host, &session, get_node_token_cmd).await;
     match token_result {
       Ok(token) => {
         let _ = nya.set("k3s_node_token", token.trim().to_string()).await;
@@ -102,7 +127,6 @@ pub async fn build_control_plane_action(nya: Nya, _: Payload) {
       },
       Err(err) => {
         eprintln!("Failed to retrieve K3s node token: {}", err);
-        return;
       },
     }
   }
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{core::runtime::Nya, ops::utils::is_offline};
+
+/// `nya.registry_mirror` in the base config.
+#[derive(Deserialize, Clone, Debug)]
+pub struct RegistryMirrorConfig {
+  #[serde(default)]
+  pub enabled: bool,
+  /// The first upstream listens on this port, the next one on the port after it.
+  #[serde(default = "default_port")]
+  pub port: u16,
+  #[serde(default = "default_upstreams")]
+  pub upstreams: Vec<String>,
+}
+
+fn default_port() -> u16 {
+  5001
+}
+
+fn default_upstreams() -> Vec<String> {
+  vec!["docker.io".to_string(), "ghcr.io".to_string(), "quay.io".to_string()]
+}
+
+/// A pull-through cache for one upstream registry. `registry:2` proxies a
+/// single remote, so each upstream runs in its own container.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RegistryMirror {
+  pub upstream: String,
+  /// `host:port` the nodes pull through.
+  pub endpoint: String,
+  pub container: String,
+  remote_url: String,
+  port: u16,
+}
+
+impl RegistryMirror {
+  /// Starts the cache on the control plane unless it's already there.
+  pub fn run_command(&self) -> String {
+    format!(
+      "sudo docker inspect {0} > /dev/null 2>&1 || sudo docker run -d \
+        -p {1}:5000 \
+        -e REGISTRY_PROXY_REMOTEURL={2} \
+        -e REGISTRY_HTTP_TLS_ENABLE=false \
+        -v /var/lib/nya/mirror/{3}:/var/lib/registry \
+        --restart always \
+        --name {0} \
+        registry:2",
+      self.container, self.port, self.remote_url, self.upstream,
+    )
+  }
+}
+
+fn remote_url(upstream: &str) -> String {
+  match upstream {
+    "docker.io" => "https://registry-1.docker.io".to_string(),
+    other => format!("https://{}", other),
+  }
+}
+
+/// The mirrors for a registry host, one per upstream on consecutive ports.
+pub fn mirrors_for(config: &RegistryMirrorConfig, registry_host: &str) -> Vec<RegistryMirror> {
+  let host = registry_host.rsplit_once(':').map(|(host, _)| host).unwrap_or(registry_host);
+  config.upstreams.iter().enumerate().map(|(i, upstream)| {
+    let port = config.port + i as u16;
+    RegistryMirror {
+      upstream: upstream.clone(),
+      endpoint: format!("{}:{}", host, port),
+      container: format!("registry-mirror-{}", upstream.replace('.', "-")),
+      remote_url: remote_url(upstream),
+      port,
+    }
+  }).collect()
+}
+
+/// The pull-through mirrors next to the nya registry. Empty when they aren't
+/// enabled, and for offline builds, which have no upstream to pull from.
+pub async fn registry_mirrors(nya: &Nya) -> Vec<RegistryMirror> {
+  let config: Option<RegistryMirrorConfig> = serde_json::from_value(nya.get("nya.registry_mirror").await).ok();
+  let Some(config) = config.filter(|config| config.enabled) else {
+    return Vec::new();
+  };
+  if is_offline(nya.clone()).await {
+    return Vec::new();
+  }
+  let registry_host = nya.get("nya.registry_host").await;
+  mirrors_for(&config, registry_host.as_str().unwrap_or(""))
+}
+
+/// Docker's `daemon.json`: the nya registry and the mirrors are plain HTTP,
+/// and Docker only takes a mirror for Docker Hub.
+pub fn docker_daemon_json(registry_host: &str, mirrors: &[RegistryMirror]) -> String {
+  let mut insecure = vec![registry_host.to_string()];
+  insecure.extend(mirrors.iter().map(|mirror| mirror.endpoint.clone()));
+  let mut daemon = serde_json::json!({ "insecure-registries": insecure });
+  if let Some(hub) = mirrors.iter().find(|mirror| mirror.upstream == "docker.io") {
+    daemon["registry-mirrors"] = Value::from(vec![format!("http://{}", hub.endpoint)]);
+  }
+  serde_json::to_string_pretty(&daemon).unwrap()
+}
+
+#[cfg(test)]
+mod mirror_tests {
+  use crate::ops::base::build::mirror::{RegistryMirrorConfig, docker_daemon_json, mirrors_for};
+
+  #[test]
+  fn runs_one_mirror_per_upstream_next_to_the_registry() {
+    let config = RegistryMirrorConfig { enabled: true, port: 5001, upstreams: vec!["docker.io".to_string(), "ghcr.io".to_string()] };
+    let mirrors = mirrors_for(&config, "10.0.0.7:5000");
+    assert_eq!(mirrors[0].endpoint, "10.0.0.7:5001");
+    assert_eq!(mirrors[1].endpoint, "10.0.0.7:5002");
+    assert_eq!(mirrors[1].container, "registry-mirror-ghcr-io");
+    assert!(mirrors[0].run_command().contains("REGISTRY_PROXY_REMOTEURL=https://registry-1.docker.io"));
+
+    let daemon = docker_daemon_json("10.0.0.7:5000", &mirrors);
+    assert!(daemon.contains("\"http://10.0.0.7:5001\""));
+    assert!(daemon.contains("\"10.0.0.7:5002\""));
+    assert!(!docker_daemon_json("10.0.0.7:5000", &[]).contains("registry-mirrors"));
+  }
+}
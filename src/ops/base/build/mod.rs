@@ -1,12 +1,17 @@
 use crate::core::service::{Service, ServiceActions, handle_action};
 use crate::ops::base::build::cluster::{complete_cluster, register_node, setup_bind9, setup_helm, setup_tls, on_build_complete, on_finish};
+use crate::ops::base::build::addons::install_addons;
 use crate::ops::base::build::control_plane::build_control_plane_action;
 use crate::ops::base::build::ingress::setup_ingress;
 use crate::ops::base::build::prebuild::{prebuild_action, run_prebuild_script};
+use crate::ops::base::build::wireguard::setup_wireguard;
 pub(crate) mod prebuild;
 pub(crate) mod control_plane;
 pub(crate) mod cluster;
 pub(crate) mod ingress; 
+pub(crate) mod addons;
+pub(crate) mod mirror;
+pub(crate) mod wireguard;
 
 
 pub struct NyaBaseBuild;
@@ -17,6 +22,7 @@ impl Service for NyaBaseBuild {
     vec![
       (String::from("onPreBuild"), handle_action(prebuild_action)),
       (String::from("runPreBuild"), handle_action(run_prebuild_script)),
+      (String::from("onSetupWireguard"), handle_action(setup_wireguard)),
       (String::from("onBuildControlPlane"), handle_action(build_control_plane_action)),
       (String::from("onCompleteCluster"), handle_action(complete_cluster)),
       (String::from("registerNode"), handle_action(register_node)),
@@ -24,6 +30,7 @@ impl Service for NyaBaseBuild {
       (String::from("setupHelm"), handle_action(setup_helm)),
       (String::from("setupTLS"), handle_action(setup_tls)),
       (String::from("onClusterReady"), handle_action(setup_ingress)),
+      (String::from("onInstallAddons"), handle_action(install_addons)),
       (String::from("onBuildComplete"), handle_action(on_build_complete)),
       (String::from("onFinish"), handle_action(on_finish)),
     ]
@@ -1,10 +1,23 @@
-use crate::{core::{checks::{Check, CheckIf}, payload::{Payload, Take}, runtime::Nya}, ops::{types::NodeCommandResult, utils::{get_base_nodes, prepare_base_context, run_on_node}}};
+use crate::{core::{checks::{Check, CheckIf}, payload::{Payload, Take}, runtime::Nya}, ops::{distro::{PackageManager, package_manager, run_package_command}, types::NodeCommandResult, upload::{UploadOptions, UploadResult, upload_file}, utils::{get_base_nodes, is_offline, prepare_base_context, run_on_node}}};
+use crate::ops::base::build::mirror::{docker_daemon_json, registry_mirrors};
 use crate::ops::{types, utils};
 use openssh::Session;
 use types::BaseNodeConfig;
 use utils::create_ssh_session;
 
-const INSTALL_DOCKER_SCRIPT: &str = include_str!("scripts/install_docker.sh");
+pub(crate) const INSTALL_DOCKER_APT_SCRIPT: &str = include_str!("scripts/apt/install_docker.sh");
+pub(crate) const INSTALL_DOCKER_DNF_SCRIPT: &str = include_str!("scripts/dnf/install_docker.sh");
+pub(crate) const INSTALL_DOCKER_APK_SCRIPT: &str = include_str!("scripts/apk/install_docker.sh");
+pub(crate) const INSTALL_DOCKER_OFFLINE_SCRIPT: &str = include_str!("scripts/install_docker_offline.sh");
+
+fn install_docker_script(package_manager: PackageManager, offline: bool) -> &'static str {
+  match (package_manager, offline) {
+    (_, true) => INSTALL_DOCKER_OFFLINE_SCRIPT,
+    (PackageManager::Apt, false) => INSTALL_DOCKER_APT_SCRIPT,
+    (PackageManager::Dnf, false) => INSTALL_DOCKER_DNF_SCRIPT,
+    (PackageManager::Apk, false) => INSTALL_DOCKER_APK_SCRIPT,
+  }
+}
 
 pub async fn prebuild_action(nya: Nya, _: Payload) {
   println!("Building the base");
@@ -16,22 +29,29 @@ pub async fn prebuild_action(nya: Nya, _: Payload) {
 
   let mut pre_build_tasks: Vec<(&str, Payload)> = Vec::new();
   for node in node_configs.iter() {
-    let session: Session = create_ssh_session(node).await;
-    pre_build_tasks.push(("runPreBuild", Payload::new(session)));
+    let Some(session) = create_ssh_session(&nya, node).await else {
+      return;
+    };
+    pre_build_tasks.push(("runPreBuild", Payload::new((session, node.clone()))));
   }
   nya.trigger_all(pre_build_tasks).await;
 }
 
 pub async fn run_prebuild_script(nya: Nya, payload: Payload) {
-  let session: Session = payload.take::<Session>().unwrap();
+  let (session, node) = payload.take::<(Session, BaseNodeConfig)>().unwrap();
   let registry_host: String = nya.get("nya.registry_host").await.as_str().unwrap_or("").to_string();
-  let daemon_json: String = format!(r#"{{
-  "insecure-registries": ["{}"]
-}}"#, registry_host);
-  let registry_cmd = format!("sudo mkdir -p /etc/docker && echo '{}' | sudo tee /etc/docker/daemon.json", daemon_json);
+  let daemon_json: String = docker_daemon_json(&registry_host, &registry_mirrors(&nya).await);
+  let package_manager = match package_manager(&nya, &node.host, &session).await {
+    Ok(package_manager) => package_manager,
+    Err(err) => {
+      eprintln!("Docker installation failed on {}: {}", node.host, err);
+      return;
+    }
+  };
 
   if !Check::run(CheckIf::DockerIsInstalled, &session).await {
-    let result = run_on_node(&session, INSTALL_DOCKER_SCRIPT).await;
+    let install_docker_script = install_docker_script(package_manager, is_offline(nya.clone()).await);
+    let result = run_package_command(&nya, &node.host, &session, package_manager, install_docker_script).await;
     match result {
       NodeCommandResult::Success => {},
       NodeCommandResult::Failure(err) => { 
@@ -39,16 +59,20 @@ pub async fn run_prebuild_script(nya: Nya, payload: Payload) {
         return;
       }
     }
+  } else {
+    println!("Docker is already installed, skipping installation.");
+  }
 
-    let registry_result = run_on_node(&session, &registry_cmd).await;
-    match registry_result {
-      NodeCommandResult::Success => {},
-      NodeCommandResult::Failure(err) => {
-        eprintln!("Failed to configure Docker registry: {}", err);
-        return;
+  let registry_result = upload_file(&session, daemon_json.as_bytes(), "/etc/docker/daemon.json", &UploadOptions::default()).await;
+  match registry_result {
+    Ok(UploadResult::Uploaded) => {
+      if let NodeCommandResult::Failure(err) = run_on_node(&nya, &node.host, &session, &package_manager.restart_service("docker")).await {
+        eprintln!("Failed to restart Docker with the new registry configuration: {}", err);
       }
+    },
+    Ok(UploadResult::Unchanged) => {},
+    Err(err) => {
+      eprintln!("Failed to configure Docker registry: {}", err);
     }
-  } else {
-    println!("Docker is already installed, skipping installation.");
   }
 }
\ No newline at end of file
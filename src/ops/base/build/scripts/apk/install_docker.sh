@@ -0,0 +1,7 @@
+#!/bin/bash
+set -euo pipefail
+
+sudo apk add --no-cache docker
+sudo rc-update add docker default
+sudo rc-service docker start
+sudo addgroup ${USER} docker
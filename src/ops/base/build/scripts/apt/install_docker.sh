@@ -0,0 +1,19 @@
+#!/bin/bash
+set -euo pipefail
+
+. /etc/os-release
+# Docker publishes repos for Debian and Ubuntu, derivatives use their parent's
+case "$ID" in
+  ubuntu|debian) DOCKER_DISTRO=$ID ;;
+  *) if [[ " ${ID_LIKE:-} " == *" ubuntu "* ]]; then DOCKER_DISTRO=ubuntu; else DOCKER_DISTRO=debian; fi ;;
+esac
+CODENAME=${UBUNTU_CODENAME:-$VERSION_CODENAME}
+
+sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 update -y
+sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 install -y ca-certificates curl gnupg
+sudo install -m 0755 -d /etc/apt/keyrings
+curl -fsSL "https://download.docker.com/linux/${DOCKER_DISTRO}/gpg" | sudo gpg --batch --yes --no-tty --dearmor -o /etc/apt/keyrings/docker.gpg
+echo "deb [arch=$(dpkg --print-architecture) signed-by=/etc/apt/keyrings/docker.gpg] https://download.docker.com/linux/${DOCKER_DISTRO} ${CODENAME} stable" | sudo tee /etc/apt/sources.list.d/docker.list
+sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 update -y
+sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 install -y docker-ce
+sudo usermod -aG docker ${USER}
@@ -0,0 +1,13 @@
+#!/bin/bash
+set -euo pipefail
+
+. /etc/os-release
+# Rocky and Alma use Docker's CentOS repo
+if [ "$ID" = "fedora" ]; then DOCKER_DISTRO=fedora; else DOCKER_DISTRO=centos; fi
+
+sudo dnf install -y dnf-plugins-core
+sudo dnf config-manager --add-repo "https://download.docker.com/linux/${DOCKER_DISTRO}/docker-ce.repo" \
+  || sudo dnf config-manager addrepo --from-repofile="https://download.docker.com/linux/${DOCKER_DISTRO}/docker-ce.repo"
+sudo dnf install -y docker-ce docker-ce-cli containerd.io
+sudo systemctl enable --now docker
+sudo usermod -aG docker ${USER}
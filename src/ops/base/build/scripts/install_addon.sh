@@ -0,0 +1,13 @@
+#!/bin/bash
+set -euo pipefail
+
+export KUBECONFIG=/etc/rancher/k3s/k3s.yaml
+{% if repo %}
+sudo -E helm repo add {{ name }} {{ repo }} --force-update
+sudo -E helm repo update {{ name }}
+{% endif %}
+sudo -E helm upgrade --install {{ name }} {{ chart }} \
+  --namespace {{ namespace }} \
+  --create-namespace{% if version %} \
+  --version {{ version }}{% endif %}{% for file in values_files %} \
+  -f {{ file }}{% endfor %}
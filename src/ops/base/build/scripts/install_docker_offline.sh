@@ -0,0 +1,22 @@
+#!/bin/bash
+set -euo pipefail
+sudo tar -xzf /opt/nya/bundle/docker/docker.tgz -C /tmp
+sudo install -m 0755 /tmp/docker/* /usr/bin/
+sudo rm -rf /tmp/docker
+sudo groupadd -f docker
+cat <<EOF | sudo tee /etc/systemd/system/docker.service > /dev/null
+[Unit]
+Description=Docker Application Container Engine
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+ExecStart=/usr/bin/dockerd
+Restart=always
+
+[Install]
+WantedBy=multi-user.target
+EOF
+sudo systemctl daemon-reload
+sudo systemctl enable --now docker
+sudo usermod -aG docker ${USER}
\ No newline at end of file
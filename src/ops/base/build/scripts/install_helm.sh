@@ -1,4 +1,7 @@
 #!/bin/bash
 set -euo pipefail
-
-curl -fsSL https://raw.githubusercontent.com/helm/helm/main/scripts/get-helm-3 | bash
\ No newline at end of file
+{% if offline %}
+sudo install -m 0755 /opt/nya/bundle/helm/helm /usr/local/bin/helm
+{% else %}
+curl -fsSL https://raw.githubusercontent.com/helm/helm/main/scripts/get-helm-3 | bash
+{% endif %}
@@ -1,17 +1,28 @@
 #!/bin/bash
 set -euo pipefail
-
+{% if offline %}
+sudo docker load -i /opt/nya/bundle/images/nya-images.tar
+sudo mkdir -p /var/lib/rancher/k3s/agent/images
+sudo cp /opt/nya/bundle/k3s/k3s-airgap-images-*.tar.zst /opt/nya/bundle/images/nya-images.tar /var/lib/rancher/k3s/agent/images/
+sudo install -m 0755 /opt/nya/bundle/k3s/k3s /usr/local/bin/k3s
+{% endif %}
 sudo docker run -d \
   -p 5000:5000 \
   -e REGISTRY_HTTP_TLS_ENABLE=false \
   -e REGISTRY_STORAGE_DELETE_ENABLED=true \
+  -v /var/lib/nya/registry:/var/lib/registry \
   --restart always \
   --name registry \
   registry:2
-
+{% if offline %}
+INSTALL_K3S_SKIP_DOWNLOAD=true INSTALL_K3S_TOKEN={{ k3s_token }} sh /opt/nya/bundle/k3s/install.sh server \
+{%- else %}
 curl -sfL https://get.k3s.io | INSTALL_K3S_TOKEN={{ k3s_token }} sh -s - server \
+{%- endif %}
   --disable traefik \
   --disable servicelb \
   --disable local-storage \
-  --advertise-address={{ control_plane_ip }} \
-  --node-ip={{ control_plane_ip }}
\ No newline at end of file
+  --advertise-address={{ node_ip }} \
+  --node-ip={{ node_ip }}{% if flannel_iface %} \
+  --flannel-iface={{ flannel_iface }} \
+  --tls-san={{ control_plane_ip }}{% endif %}
@@ -1,12 +1,15 @@
 #!/bin/bash
 set -euo pipefail
-
+{% if offline %}
+sudo install -m 0755 /opt/nya/bundle/mkcert/mkcert /usr/local/bin/mkcert
+{% else %}
 LATEST=$(sudo curl -fsSL https://api.github.com/repos/FiloSottile/mkcert/releases/latest | grep '"tag_name"' | cut -d'"' -f4)
 sudo curl -fsSL "https://github.com/FiloSottile/mkcert/releases/download/${LATEST}/mkcert-${LATEST}-linux-amd64" -o /usr/local/bin/mkcert
 sudo chmod +x /usr/local/bin/mkcert
+{% endif %}
 sudo mkcert -install
 sudo mkdir -p /etc/nya/certs
 sudo mkcert \
   -cert-file /etc/nya/certs/{{ domain }}.pem \
   -key-file /etc/nya/certs/{{ domain }}-key.pem \
-  "*.{{ domain }}" {{ domain }}
\ No newline at end of file
+  "*.{{ domain }}" {{ domain }}
@@ -0,0 +1,17 @@
+#!/bin/bash
+set -euo pipefail
+
+if [ ! -f /etc/rancher/k3s/k3s.yaml ]; then
+  echo "k3s not found, skipping {{ name }} cleanup"
+  exit 0
+fi
+
+export KUBECONFIG=/etc/rancher/k3s/k3s.yaml
+
+sudo -E helm uninstall {{ name }} \
+  --namespace {{ namespace }} \
+  --ignore-not-found \
+  --timeout 60s \
+  --wait=false
+
+sudo rm -rf /opt/nya/addons/{{ name }}
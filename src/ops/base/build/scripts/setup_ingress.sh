@@ -2,13 +2,17 @@
 set -euo pipefail
 
 export KUBECONFIG=/etc/rancher/k3s/k3s.yaml
-
-export KUBECONFIG=/etc/rancher/k3s/k3s.yaml
-
+{% if offline %}
+METALLB_CHART=$(ls /opt/nya/bundle/charts/metallb-*.tgz)
+INGRESS_NGINX_CHART=$(ls /opt/nya/bundle/charts/ingress-nginx-*.tgz)
+{% else %}
 sudo -E helm repo add ingress-nginx https://kubernetes.github.io/ingress-nginx
 sudo -E helm repo add metallb https://metallb.github.io/metallb
 sudo -E helm repo update
-sudo -E helm upgrade --install metallb metallb/metallb \
+METALLB_CHART=metallb/metallb
+INGRESS_NGINX_CHART=ingress-nginx/ingress-nginx
+{% endif %}
+sudo -E helm upgrade --install metallb "$METALLB_CHART" \
   --namespace metallb-system \
   --create-namespace
 
@@ -32,13 +36,16 @@ metadata:
   namespace: metallb-system
 EOF
 
-sudo -E helm upgrade --install ingress-nginx ingress-nginx/ingress-nginx \
+sudo -E helm upgrade --install ingress-nginx "$INGRESS_NGINX_CHART" \
   --namespace ingress-nginx \
   --create-namespace \
   --set controller.kind=DaemonSet \
-  --set controller.service.type=LoadBalancer
+  --set controller.service.type=LoadBalancer{% if offline %} \
+  --set controller.image.digest= \
+  --set controller.admissionWebhooks.patch.image.digest={% endif %}
 
 sudo -E kubectl create secret tls {{ secret_name }} \
   --key /etc/nya/certs/{{ domain }}-key.pem \
   --cert /etc/nya/certs/{{ domain }}.pem \
-  --namespace ingress-nginx
\ No newline at end of file
+  --namespace ingress-nginx \
+  --dry-run=client -o yaml | sudo -E kubectl apply -f -
\ No newline at end of file
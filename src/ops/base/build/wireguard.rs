@@ -0,0 +1,331 @@
+use std::net::Ipv4Addr;
+use colored::*;
+use futures::future::join_all;
+use openssh::Session;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tera::Context;
+
+use crate::{core::{log::host_prefix, payload::Payload, runtime::Nya}, ops::{distro::{Component, PackageManager, package_manager, run_package_command}, types::{BaseNodeConfig, NodeCommandResult}, upload::{UploadOptions, UploadResult, upload_file}, utils::{create_ssh_session, get_base_nodes, get_from_node, run_on_node}}};
+
+pub(crate) const WG0_CONF_TEMPLATE: &str = include_str!("templates/wg0.conf");
+/// The interface k3s runs flannel over when the overlay is enabled.
+pub(crate) const OVERLAY_INTERFACE: &str = "wg0";
+const PRIVATE_KEY_PATH: &str = "/etc/wireguard/nya.key";
+
+/// `nya.wireguard` in the base config.
+#[derive(Deserialize, Clone, Debug)]
+pub struct WireguardConfig {
+  #[serde(default)]
+  pub enabled: bool,
+  #[serde(default = "default_subnet")]
+  pub subnet: String,
+  #[serde(default = "default_port")]
+  pub port: u16,
+}
+
+fn default_subnet() -> String {
+  "10.222.0.0/24".to_string()
+}
+
+fn default_port() -> u16 {
+  51820
+}
+
+/// A node's place on the overlay.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct OverlayPeer {
+  pub host: String,
+  pub address: String,
+  /// Where the other nodes reach this one, `wireguard_endpoint` or the node's `host`.
+  pub endpoint: String,
+}
+
+#[derive(Serialize)]
+struct PeerContext {
+  host: String,
+  address: String,
+  endpoint: String,
+  public_key: String,
+}
+
+#[derive(Serialize)]
+struct Wg0Context {
+  address: String,
+  prefix: u8,
+  port: u16,
+  private_key_path: String,
+  peers: Vec<PeerContext>,
+}
+
+/// The overlay settings, or `None` when the overlay isn't enabled.
+pub async fn wireguard_config(nya: &Nya) -> Option<WireguardConfig> {
+  serde_json::from_value::<WireguardConfig>(nya.get("nya.wireguard").await)
+    .ok()
+    .filter(|config| config.enabled)
+}
+
+fn parse_subnet(subnet: &str) -> Result<(u32, u8), String> {
+  let invalid = || format!("`nya.wireguard.subnet` must be an IPv4 CIDR such as 10.222.0.0/24, not \"{}\"", subnet);
+  let (ip, prefix) = subnet.split_once('/').ok_or_else(invalid)?;
+  let ip: Ipv4Addr = ip.parse().map_err(|_| invalid())?;
+  let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+  if !(8..=30).contains(&prefix) {
+    return Err(invalid());
+  }
+  let mask = u32::MAX << (32 - prefix);
+  Ok((u32::from(ip) & mask, prefix))
+}
+
+/// Gives every node an overlay address: the control plane first, then the
+/// nodes in config order. A node can pin its address with `overlay_ip`, which
+/// keeps it stable when nodes are added or removed ahead of it.
+pub fn assign_overlay(subnet: &str, nodes: &[Value]) -> Result<Vec<OverlayPeer>, String> {
+  let (network, prefix) = parse_subnet(subnet)?;
+  let broadcast = network | (u32::MAX >> prefix);
+
+  let mut pinned: Vec<u32> = Vec::new();
+  for node in nodes.iter() {
+    if let Some(ip) = node.get("overlay_ip").and_then(|v| v.as_str()) {
+      let host = node.get("host").and_then(|v| v.as_str()).unwrap_or("");
+      let parsed: Ipv4Addr = ip.parse().map_err(|_| format!("{}: `overlay_ip` \"{}\" is not an IPv4 address", host, ip))?;
+      let parsed = u32::from(parsed);
+      if parsed <= network || parsed >= broadcast {
+        return Err(format!("{}: `overlay_ip` {} is not a host address in {}", host, ip, subnet));
+      }
+      if pinned.contains(&parsed) {
+        return Err(format!("{}: `overlay_ip` {} is used by another node", host, ip));
+      }
+      pinned.push(parsed);
+    }
+  }
+
+  let mut next = network + 1;
+  let mut peers = Vec::new();
+  for node in nodes.iter() {
+    let host = node.get("host").and_then(|v| v.as_str()).unwrap_or("").to_string();
+    let address = match node.get("overlay_ip").and_then(|v| v.as_str()) {
+      Some(ip) => ip.to_string(),
+      None => {
+        while pinned.contains(&next) {
+          next += 1;
+        }
+        if next >= broadcast {
+          return Err(format!("{} has no room for {} nodes", subnet, nodes.len()));
+        }
+        let address = Ipv4Addr::from(next).to_string();
+        next += 1;
+        address
+      },
+    };
+    let endpoint = node.get("wireguard_endpoint").and_then(|v| v.as_str()).unwrap_or(&host).to_string();
+    peers.push(OverlayPeer { host, address, endpoint });
+  }
+  Ok(peers)
+}
+
+/// Every node in the base with its overlay address.
+pub async fn overlay_peers(nya: &Nya, config: &WireguardConfig) -> Result<Vec<OverlayPeer>, String> {
+  let control_plane = nya.get("nya.control_plane").await;
+  let control_plane_host = control_plane.get("host").and_then(|v| v.as_str()).unwrap_or("").to_string();
+  let mut nodes = vec![control_plane];
+  if let Value::Array(items) = nya.get("nya.nodes").await {
+    nodes.extend(items.into_iter().filter(|node| node.get("host").and_then(|v| v.as_str()) != Some(control_plane_host.as_str())));
+  }
+  assign_overlay(&config.subnet, &nodes)
+}
+
+/// The address k3s should use for a node: its overlay address when the
+/// overlay is enabled, `None` otherwise.
+pub async fn overlay_address(nya: &Nya, host: &str) -> Option<String> {
+  let config = wireguard_config(nya).await?;
+  overlay_peers(nya, &config).await.ok()?
+    .into_iter()
+    .find(|peer| peer.host == host)
+    .map(|peer| peer.address)
+}
+
+fn render_wg0_conf(config: &WireguardConfig, node: &OverlayPeer, peers: &[(OverlayPeer, String)]) -> Result<String, String> {
+  let (_, prefix) = parse_subnet(&config.subnet)?;
+  let context = Wg0Context {
+    address: node.address.clone(),
+    prefix,
+    port: config.port,
+    private_key_path: PRIVATE_KEY_PATH.to_string(),
+    peers: peers.iter()
+      .filter(|(peer, _)| peer.host != node.host)
+      .map(|(peer, public_key)| PeerContext {
+        host: peer.host.clone(),
+        address: peer.address.clone(),
+        endpoint: peer.endpoint.clone(),
+        public_key: public_key.clone(),
+      })
+      .collect(),
+  };
+  let context = Context::from_serialize(serde_json::to_value(context).unwrap()).unwrap();
+  tera::Tera::one_off(WG0_CONF_TEMPLATE, &context, false).map_err(|e| e.to_string())
+}
+
+/// Installs wireguard-tools if needed and returns the node's public key,
+/// generating the private key on the node the first time.
+async fn prepare_node(nya: &Nya, host: &str, session: &Session) -> Result<(PackageManager, String), String> {
+  let package_manager = package_manager(nya, host, session).await?;
+  if get_from_node(nya, host, session, "command -v wg").await.is_err() {
+    let install = package_manager.install(package_manager.packages(Component::Wireguard));
+    if let NodeCommandResult::Failure(err) = run_package_command(nya, host, session, package_manager, &install).await {
+      return Err(format!("could not install wireguard-tools: {}", err));
+    }
+  }
+  let public_key = get_from_node(nya, host, session, &format!(
+    "sudo sh -c 'umask 077; mkdir -p /etc/wireguard; [ -s {0} ] || wg genkey > {0}; wg pubkey < {0}'",
+    PRIVATE_KEY_PATH,
+  )).await?;
+  Ok((package_manager, public_key.trim().to_string()))
+}
+
+/// Writes `wg0.conf` and (re)starts the tunnel when the config changed or the interface is down.
+async fn bring_up(nya: &Nya, host: &str, session: &Session, package_manager: PackageManager, conf: &str) -> Result<(), String> {
+  let options = UploadOptions { mode: "0600".to_string(), ..Default::default() };
+  let uploaded = upload_file(session, conf.as_bytes(), "/etc/wireguard/wg0.conf", &options).await?;
+  let is_up = get_from_node(nya, host, session, &format!("ip link show {}", OVERLAY_INTERFACE)).await.is_ok();
+  if uploaded == UploadResult::Unchanged && is_up {
+    return Ok(());
+  }
+
+  let service = package_manager.service(Component::Wireguard);
+  let mut command = String::new();
+  if package_manager == PackageManager::Apk {
+    command.push_str(&format!("sudo ln -sf wg-quick /etc/init.d/{} && ", service));
+  }
+  command.push_str(&format!("{} && {}", package_manager.enable_service(service), package_manager.restart_service(service)));
+  match run_on_node(nya, host, session, &command).await {
+    NodeCommandResult::Success => Ok(()),
+    NodeCommandResult::Failure(err) => Err(format!("could not start {}: {}", service, err)),
+  }
+}
+
+pub async fn setup_wireguard(nya: Nya, _: Payload) {
+  let Some(config) = wireguard_config(&nya).await else {
+    return;
+  };
+  let peers = match overlay_peers(&nya, &config).await {
+    Ok(peers) => peers,
+    Err(err) => {
+      eprintln!("{}", format!("Cannot set up the WireGuard overlay: {}", err).red());
+      nya.stop();
+      return;
+    },
+  };
+  println!("Setting up the WireGuard overlay on {}", config.subnet);
+
+  let node_configs: Vec<BaseNodeConfig> = get_base_nodes(nya.clone()).await;
+  let mut targets: Vec<(OverlayPeer, Session)> = Vec::new();
+  for peer in peers.iter() {
+    let Some(node) = node_configs.iter().find(|node| node.host == peer.host) else {
+      continue;
+    };
+    let Some(session) = create_ssh_session(&nya, node).await else {
+      return;
+    };
+    targets.push((peer.clone(), session));
+  }
+
+  let prepared = join_all(targets.iter().map(|(peer, session)| prepare_node(&nya, &peer.host, session))).await;
+  let mut keyed: Vec<(OverlayPeer, String)> = Vec::new();
+  let mut package_managers: Vec<PackageManager> = Vec::new();
+  let mut failed = false;
+  for ((peer, _), result) in targets.iter().zip(prepared) {
+    match result {
+      Ok((package_manager, public_key)) => {
+        keyed.push((peer.clone(), public_key));
+        package_managers.push(package_manager);
+      },
+      Err(err) => {
+        eprintln!("{} {}", host_prefix(&peer.host), format!("WireGuard setup failed: {}", err).red());
+        failed = true;
+      },
+    }
+  }
+  if failed {
+    nya.stop();
+    return;
+  }
+
+  let started = join_all(targets.iter().zip(package_managers.iter()).map(|((peer, session), package_manager)| {
+    let keyed = &keyed;
+    let config = &config;
+    let nya = &nya;
+    async move {
+      let conf = render_wg0_conf(config, peer, keyed)?;
+      bring_up(nya, &peer.host, session, *package_manager, &conf).await
+    }
+  })).await;
+  for ((peer, _), result) in targets.iter().zip(started) {
+    match result {
+      Ok(()) => println!("{} {} is up at {}", host_prefix(&peer.host), OVERLAY_INTERFACE, peer.address),
+      Err(err) => {
+        eprintln!("{} {}", host_prefix(&peer.host), format!("WireGuard setup failed: {}", err).red());
+        failed = true;
+      },
+    }
+  }
+  if failed {
+    nya.stop();
+    return;
+  }
+
+  // Handshakes only happen once traffic flows, so a few pings also warm the tunnels up
+  let control_plane_address = &peers[0].address;
+  for (peer, session) in targets.iter().skip(1) {
+    let ping = format!("ping -c 3 -W 2 {}", control_plane_address);
+    if get_from_node(&nya, &peer.host, session, &ping).await.is_err() {
+      println!("{} {}", host_prefix(&peer.host), format!(
+        "Can't reach the control plane at {} over the overlay yet. Check that UDP port {} is open on every node.",
+        control_plane_address, config.port,
+      ).yellow());
+    }
+  }
+
+  for (_, session) in targets {
+    let _ = session.close().await;
+  }
+}
+
+#[cfg(test)]
+mod wireguard_tests {
+  use serde_json::json;
+  use crate::ops::base::build::wireguard::{OverlayPeer, WireguardConfig, assign_overlay, render_wg0_conf};
+
+  #[test]
+  fn assigns_addresses_in_config_order_around_pinned_ones() {
+    let nodes = vec![
+      json!({"host": "10.0.0.7"}),
+      json!({"host": "203.0.113.4", "overlay_ip": "10.222.0.2"}),
+      json!({"host": "10.0.0.9", "wireguard_endpoint": "home.example.com"}),
+    ];
+    let peers = assign_overlay("10.222.0.0/24", &nodes).unwrap();
+    let addresses: Vec<&str> = peers.iter().map(|p| p.address.as_str()).collect();
+    assert_eq!(addresses, vec!["10.222.0.1", "10.222.0.2", "10.222.0.3"]);
+    assert_eq!(peers[2].endpoint, "home.example.com");
+    assert_eq!(peers[0].endpoint, "10.0.0.7");
+
+    assert!(assign_overlay("10.222.0.0/30", &nodes).is_err());
+    assert!(assign_overlay("10.222.0.0", &nodes).is_err());
+    assert!(assign_overlay("10.222.0.0/24", &[json!({"host": "a", "overlay_ip": "10.223.0.1"})]).is_err());
+  }
+
+  #[test]
+  fn renders_every_other_node_as_a_peer() {
+    let config = WireguardConfig { enabled: true, subnet: "10.222.0.0/24".to_string(), port: 51820 };
+    let peer = |host: &str, address: &str| OverlayPeer { host: host.to_string(), address: address.to_string(), endpoint: host.to_string() };
+    let keyed = vec![
+      (peer("10.0.0.7", "10.222.0.1"), "cp-key".to_string()),
+      (peer("203.0.113.4", "10.222.0.2"), "node-key".to_string()),
+    ];
+    let conf = render_wg0_conf(&config, &keyed[0].0, &keyed).unwrap();
+    assert!(conf.contains("Address = 10.222.0.1/24"));
+    assert!(conf.contains("PublicKey = node-key"));
+    assert!(conf.contains("Endpoint = 203.0.113.4:51820"));
+    assert!(!conf.contains("cp-key"));
+  }
+}
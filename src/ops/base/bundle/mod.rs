@@ -0,0 +1,286 @@
+use std::{path::PathBuf, process::Stdio};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tera::Context;
+use tokio::process::Command;
+
+use crate::{core::{payload::{Payload, Take}, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::{distro::{Component, PackageManager}, types::{BaseNodeConfig, NodeCommandResult}, utils::{create_ssh_session, get_base_nodes, is_offline, run_on_node}}};
+
+const CREATE_BUNDLE_SCRIPT: &str = include_str!("scripts/create_bundle.sh");
+pub(crate) const BUNDLE_REMOTE_DIR: &str = "/opt/nya/bundle";
+
+/// Images the base needs that k3s doesn't ship in its own airgap tarball.
+const DEFAULT_IMAGES: &[&str] = &[
+  "registry:2",
+  "quay.io/metallb/controller:v0.14.9",
+  "quay.io/metallb/speaker:v0.14.9",
+  "quay.io/frrouting/frr:9.1.0",
+  "registry.k8s.io/ingress-nginx/controller:v1.12.0",
+  "registry.k8s.io/ingress-nginx/kube-webhook-certgen:v1.5.0",
+];
+
+pub struct NyaBaseBundle;
+
+impl Service for NyaBaseBundle {
+  fn name(&self) -> String {"NyaBaseBundle".to_string()}
+  fn register(&self) -> ServiceActions {
+    vec![
+      (String::from("onCreateBundle"), handle_action(create_bundle)),
+      (String::from("onUploadBundle"), handle_action(upload_bundle)),
+      (String::from("runUploadBundle"), handle_action(run_upload_bundle)),
+    ]
+  }
+}
+
+/// Versions pinned into the bundle. Any field can be overridden under `nya.bundle` in the base config.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+struct BundleVersions {
+  arch: String,
+  k3s_version: String,
+  helm_version: String,
+  metallb_version: String,
+  ingress_nginx_version: String,
+  mkcert_version: String,
+  docker_version: String,
+  /// The distro the bind9 packages are downloaded for. It has to match the
+  /// nodes', e.g. `ubuntu:24.04` or `rockylinux:9`.
+  bind9_image: String,
+  images: Vec<String>,
+}
+
+impl Default for BundleVersions {
+  fn default() -> Self {
+    Self {
+      arch: "amd64".to_string(),
+      k3s_version: "v1.31.4+k3s1".to_string(),
+      helm_version: "v3.16.4".to_string(),
+      metallb_version: "0.14.9".to_string(),
+      ingress_nginx_version: "4.12.0".to_string(),
+      mkcert_version: "v1.4.4".to_string(),
+      docker_version: "27.4.1".to_string(),
+      bind9_image: "debian:12".to_string(),
+      images: DEFAULT_IMAGES.iter().map(|i| i.to_string()).collect(),
+    }
+  }
+}
+
+#[derive(Serialize)]
+struct BundleContext {
+  #[serde(flatten)]
+  versions: BundleVersions,
+  k3s_binary: String,
+  docker_arch: String,
+  bind9_download: String,
+  manifest: String,
+  output: String,
+}
+
+/// The `nya.bundle` versions, with the arch falling back to the one the
+/// base config already declares for mkcert.
+fn bundle_versions(mut bundle_config: Value, vars: &Value) -> BundleVersions {
+  if bundle_config.get("arch").is_none()
+    && let Some(arch) = vars.get("mkcert_arch").and_then(|v| v.as_str()).filter(|a| docker_arch(a).is_some()) {
+    if !bundle_config.is_object() {
+      bundle_config = Value::Object(Default::default());
+    }
+    bundle_config["arch"] = Value::String(arch.to_string());
+  }
+  serde_json::from_value(bundle_config).unwrap_or_default()
+}
+
+/// The directory Docker's static builds use for an arch.
+fn docker_arch(arch: &str) -> Option<&'static str> {
+  match arch {
+    "amd64" => Some("x86_64"),
+    "arm64" => Some("aarch64"),
+    _ => None,
+  }
+}
+
+/// The package manager of a distro image, from its name without registry or tag.
+fn image_package_manager(image: &str) -> Option<PackageManager> {
+  let name = image.rsplit('/').next().unwrap_or(image);
+  let name = name.split(':').next().unwrap_or(name);
+  match name {
+    "debian" | "ubuntu" => Some(PackageManager::Apt),
+    "fedora" | "rockylinux" | "almalinux" | "centos" | "ubi8" | "ubi9" => Some(PackageManager::Dnf),
+    _ => None,
+  }
+}
+
+fn bundle_context(versions: BundleVersions, output: String) -> Result<BundleContext, String> {
+  let docker_arch = docker_arch(&versions.arch)
+    .ok_or_else(|| format!("`nya.bundle.arch` is {}, bundles can be created for amd64 or arm64", versions.arch))?;
+  let package_manager = image_package_manager(&versions.bind9_image)
+    .ok_or_else(|| format!("`nya.bundle.bind9_image` is {}, which isn't a Debian, Ubuntu or RHEL-family image", versions.bind9_image))?;
+  Ok(BundleContext {
+    k3s_binary: if versions.arch == "amd64" { "k3s".to_string() } else { format!("k3s-{}", versions.arch) },
+    docker_arch: docker_arch.to_string(),
+    bind9_download: package_manager.download(package_manager.packages(Component::Bind9), "/out"),
+    manifest: serde_json::to_string_pretty(&versions).unwrap(),
+    output,
+    versions,
+  })
+}
+
+async fn create_bundle(nya: Nya, _: Payload) {
+  let versions = bundle_versions(nya.get("nya.bundle").await, &nya.get("nya.control_plane.vars").await);
+
+  let output: String = match nya.get("bundle_output").await.as_str() {
+    Some(path) => path.to_string(),
+    None => format!("nya-bundle-{}.tar.gz", versions.arch),
+  };
+  let output: String = std::path::absolute(&output)
+    .map(|p| p.display().to_string())
+    .unwrap_or(output);
+
+  let bundle_context = match bundle_context(versions, output.clone()) {
+    Ok(context) => context,
+    Err(e) => {
+      eprintln!("{}", e.red());
+      return;
+    },
+  };
+
+  let context_value: Value = serde_json::to_value(&bundle_context).unwrap();
+  let tera_context: Context = Context::from_serialize(&context_value).unwrap();
+  let rendered_script: String = match tera::Tera::one_off(CREATE_BUNDLE_SCRIPT, &tera_context, false) {
+    Ok(script) => script,
+    Err(e) => {
+      eprintln!("Failed to render bundle script: {}", e);
+      return;
+    },
+  };
+
+  println!("Creating offline bundle");
+  let status = Command::new("bash")
+    .arg("-c")
+    .arg(&rendered_script)
+    .stdin(Stdio::null())
+    .status()
+    .await;
+  match status {
+    Ok(status) if status.success() => {
+      println!("{}", "Bundle created successfully!".green());
+      println!("Build an offline base with: {}", format!("nya base build --bundle {}", output).purple());
+    },
+    Ok(status) => eprintln!("{}", format!("Bundle creation failed with {}", status).red()),
+    Err(e) => eprintln!("{}", format!("Failed to run bundle script: {}", e).red()),
+  }
+}
+
+async fn upload_bundle(nya: Nya, _: Payload) {
+  if !is_offline(nya.clone()).await {
+    return;
+  }
+  let bundle_path: PathBuf = PathBuf::from(nya.get("bundle_path").await.as_str().unwrap_or(""));
+  println!("Uploading offline bundle {}", bundle_path.display());
+
+  let node_configs: Vec<BaseNodeConfig> = get_base_nodes(nya.clone()).await;
+  let mut upload_tasks: Vec<(&str, Payload)> = Vec::new();
+  for node in node_configs.iter() {
+    upload_tasks.push(("runUploadBundle", Payload::new((node.clone(), bundle_path.clone()))));
+  }
+  nya.trigger_all(upload_tasks).await;
+}
+
+async fn run_upload_bundle(nya: Nya, payload: Payload) {
+  let (node, bundle_path) = payload.take::<(BaseNodeConfig, PathBuf)>().unwrap();
+  let remote_archive = "/tmp/nya-bundle.tar.gz";
+  let ssh_key = shellexpand::tilde(&node.ssh_key_path).to_string();
+
+  let scp_status = Command::new("scp")
+    .args([
+      "-i", &ssh_key,
+      "-o", "StrictHostKeyChecking=no",
+      &bundle_path.display().to_string(),
+      &format!("{}@{}:{}", node.user, node.host, remote_archive),
+    ])
+    .stdin(Stdio::null())
+    .status()
+    .await;
+  match scp_status {
+    Ok(status) if status.success() => {},
+    Ok(status) => {
+      eprintln!("Failed to upload bundle to {}: scp exited with {}", node.host, status);
+      nya.stop();
+      return;
+    },
+    Err(e) => {
+      eprintln!("Failed to upload bundle to {}: {}", node.host, e);
+      nya.stop();
+      return;
+    },
+  }
+
+  let Some(session) = create_ssh_session(&nya, &node).await else {
+    return;
+  };
+  let extract_cmd = format!(
+    "sudo rm -rf {dir} && sudo mkdir -p {dir} && sudo tar -xzf {archive} -C {dir} --strip-components=1 && rm -f {archive}",
+    dir = BUNDLE_REMOTE_DIR, archive = remote_archive
+  );
+  match run_on_node(&nya, &node.host, &session, &extract_cmd).await {
+    NodeCommandResult::Success => println!("Bundle unpacked on {}.", node.host),
+    NodeCommandResult::Failure(err) => {
+      eprintln!("Failed to unpack bundle on {}: {}", node.host, err);
+      nya.stop();
+    },
+  }
+
+  if let Err(e) = session.close().await {
+    eprintln!("run_upload_bundle: failed to close session: {}", e);
+  }
+}
+
+#[cfg(test)]
+mod bundle_tests {
+  use serde_json::json;
+  use crate::ops::base::bundle::{BundleVersions, bundle_context, bundle_versions};
+
+  #[test]
+  fn takes_the_arch_from_mkcert_unless_the_bundle_sets_one() {
+    let vars = json!({ "mkcert_arch": "arm64" });
+    assert_eq!(bundle_versions(json!(null), &vars).arch, "arm64");
+    assert_eq!(bundle_versions(json!({ "arch": "amd64" }), &vars).arch, "amd64");
+    assert_eq!(bundle_versions(json!(null), &json!({ "mkcert_arch": "armv6" })).arch, "amd64");
+  }
+
+  #[test]
+  fn overrides_only_the_versions_it_is_given() {
+    let versions = bundle_versions(json!({ "k3s_version": "v1.30.8+k3s1" }), &json!({}));
+    let defaults = BundleVersions::default();
+    assert_eq!(versions.k3s_version, "v1.30.8+k3s1");
+    assert_eq!(versions.helm_version, defaults.helm_version);
+    assert_eq!(versions.images, defaults.images);
+  }
+
+  #[test]
+  fn maps_the_arch_to_each_download() {
+    let amd64 = bundle_context(BundleVersions::default(), "nya-bundle.tar.gz".to_string()).unwrap();
+    assert_eq!(amd64.k3s_binary, "k3s");
+    assert_eq!(amd64.docker_arch, "x86_64");
+
+    let arm64 = BundleVersions { arch: "arm64".to_string(), ..Default::default() };
+    let arm64 = bundle_context(arm64, "nya-bundle.tar.gz".to_string()).unwrap();
+    assert_eq!(arm64.k3s_binary, "k3s-arm64");
+    assert_eq!(arm64.docker_arch, "aarch64");
+
+    let riscv = BundleVersions { arch: "riscv64".to_string(), ..Default::default() };
+    assert!(bundle_context(riscv, "nya-bundle.tar.gz".to_string()).is_err());
+  }
+
+  #[test]
+  fn downloads_bind9_for_the_nodes_distro() {
+    let debian = bundle_context(BundleVersions::default(), String::new()).unwrap();
+    assert!(debian.bind9_download.contains("apt-get install -y --download-only --no-install-recommends bind9 bind9utils"));
+
+    let rocky = BundleVersions { bind9_image: "docker.io/rockylinux/rockylinux:9".to_string(), ..Default::default() };
+    assert!(bundle_context(rocky, String::new()).unwrap().bind9_download.starts_with("dnf install -y --downloadonly --downloaddir=/out bind bind-utils"));
+
+    let alpine = BundleVersions { bind9_image: "alpine:3.20".to_string(), ..Default::default() };
+    assert!(bundle_context(alpine, String::new()).is_err());
+  }
+}
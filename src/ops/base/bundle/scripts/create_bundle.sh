@@ -0,0 +1,42 @@
+#!/bin/bash
+set -euo pipefail
+
+WORK_DIR=$(mktemp -d)
+BUNDLE_DIR="$WORK_DIR/nya-bundle"
+trap 'rm -rf "$WORK_DIR"' EXIT
+mkdir -p "$BUNDLE_DIR/k3s" "$BUNDLE_DIR/helm" "$BUNDLE_DIR/charts" "$BUNDLE_DIR/images" "$BUNDLE_DIR/mkcert" "$BUNDLE_DIR/docker" "$BUNDLE_DIR/bind9"
+
+echo "Downloading k3s {{ k3s_version }} ({{ arch }})..."
+curl -fsSL "https://github.com/k3s-io/k3s/releases/download/{{ k3s_version | urlencode }}/{{ k3s_binary }}" -o "$BUNDLE_DIR/k3s/k3s"
+curl -fsSL "https://github.com/k3s-io/k3s/releases/download/{{ k3s_version | urlencode }}/k3s-airgap-images-{{ arch }}.tar.zst" -o "$BUNDLE_DIR/k3s/k3s-airgap-images-{{ arch }}.tar.zst"
+curl -fsSL https://get.k3s.io -o "$BUNDLE_DIR/k3s/install.sh"
+
+echo "Downloading helm {{ helm_version }}..."
+curl -fsSL "https://get.helm.sh/helm-{{ helm_version }}-linux-{{ arch }}.tar.gz" | tar -xz -C "$WORK_DIR"
+mv "$WORK_DIR/linux-{{ arch }}/helm" "$BUNDLE_DIR/helm/helm"
+
+echo "Downloading charts..."
+curl -fsSL "https://github.com/metallb/metallb/releases/download/metallb-chart-{{ metallb_version }}/metallb-{{ metallb_version }}.tgz" -o "$BUNDLE_DIR/charts/metallb-{{ metallb_version }}.tgz"
+curl -fsSL "https://github.com/kubernetes/ingress-nginx/releases/download/helm-chart-{{ ingress_nginx_version }}/ingress-nginx-{{ ingress_nginx_version }}.tgz" -o "$BUNDLE_DIR/charts/ingress-nginx-{{ ingress_nginx_version }}.tgz"
+
+echo "Downloading mkcert {{ mkcert_version }}..."
+curl -fsSL "https://github.com/FiloSottile/mkcert/releases/download/{{ mkcert_version }}/mkcert-{{ mkcert_version }}-linux-{{ arch }}" -o "$BUNDLE_DIR/mkcert/mkcert"
+
+echo "Downloading docker {{ docker_version }}..."
+curl -fsSL "https://download.docker.com/linux/static/stable/{{ docker_arch }}/docker-{{ docker_version }}.tgz" -o "$BUNDLE_DIR/docker/docker.tgz"
+
+echo "Downloading bind9 packages for {{ bind9_image }}..."
+docker run --rm --platform linux/{{ arch }} -v "$BUNDLE_DIR/bind9:/out" {{ bind9_image }} sh -c '{{ bind9_download }}'
+
+echo "Saving images..."
+{% for image in images %}
+docker pull --platform linux/{{ arch }} {{ image }}
+{% endfor %}
+docker save -o "$BUNDLE_DIR/images/nya-images.tar"{% for image in images %} {{ image }}{% endfor %}
+
+cat > "$BUNDLE_DIR/manifest.json" <<MANIFEST
+{{ manifest }}
+MANIFEST
+
+tar -czf "{{ output }}" -C "$WORK_DIR" nya-bundle
+echo "Bundle written to {{ output }}"
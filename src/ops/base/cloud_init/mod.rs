@@ -0,0 +1,165 @@
+use std::{fs, path::PathBuf, process::Command};
+use colored::*;
+use serde::Serialize;
+use serde_json::Value;
+use tera::Context;
+
+use crate::{core::{payload::Payload, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::{types::{BaseNodeConfig, CloudInitOptions}, utils::{get_control_plane_config, get_node_configs}}};
+
+const USER_DATA_TEMPLATE: &str = include_str!("templates/user-data.yaml");
+
+pub struct NyaBaseCloudInit;
+
+impl Service for NyaBaseCloudInit {
+  fn name(&self) -> String {"NyaBaseCloudInit".to_string()}
+  fn register(&self) -> ServiceActions {
+    vec![
+      (String::from("onCloudInit"), handle_action(render_cloud_init)),
+    ]
+  }
+}
+
+#[derive(Serialize)]
+struct UserDataContext {
+  host: String,
+  role: String,
+  user: String,
+  ssh_public_key: String,
+  hostname: Option<String>,
+  docker: bool,
+}
+
+async fn render_cloud_init(nya: Nya, _: Payload) {
+  let options: CloudInitOptions = match serde_json::from_value(nya.get("cloud_init_options").await) {
+    Ok(options) => options,
+    Err(e) => {
+      eprintln!("Invalid cloud-init options: {}", e);
+      return;
+    },
+  };
+
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let node_configs: Vec<BaseNodeConfig> = get_node_configs(nya.clone()).await;
+  let (node, role) = if control_plane_config.host == options.host {
+    (control_plane_config, "control_plane")
+  } else if let Some(node) = node_configs.into_iter().find(|n| n.host == options.host) {
+    (node, "node")
+  } else {
+    eprintln!("{}", format!("No node with host {} found in the base config.", options.host).red());
+    return;
+  };
+
+  let ssh_public_key = match public_key_for(&node.ssh_key_path) {
+    Ok(key) => key,
+    Err(e) => {
+      eprintln!("{}", e.red());
+      return;
+    },
+  };
+
+  let user_data_context = UserDataContext {
+    host: node.host.clone(),
+    role: role.to_string(),
+    user: node.user.clone(),
+    ssh_public_key,
+    hostname: options.hostname.clone(),
+    docker: options.docker,
+  };
+  let rendered: String = match render_user_data(&user_data_context) {
+    Ok(rendered) => rendered,
+    Err(e) => {
+      eprintln!("Failed to render cloud-init user-data: {}", e);
+      return;
+    },
+  };
+
+  match options.output {
+    Some(output) => {
+      if let Err(e) = fs::write(&output, rendered) {
+        eprintln!("Failed to write user-data to {}: {}", output.display(), e);
+        return;
+      }
+      println!("{}", format!("Wrote cloud-init user-data for {} ({})", node.host, role).green());
+      println!("Location: {}", output.display());
+    },
+    None => print!("{}", rendered),
+  }
+}
+
+fn render_user_data(user_data_context: &UserDataContext) -> Result<String, tera::Error> {
+  let context_value: Value = serde_json::to_value(user_data_context).unwrap();
+  let tera_context: Context = Context::from_serialize(&context_value)?;
+  tera::Tera::one_off(USER_DATA_TEMPLATE, &tera_context, false)
+}
+
+/// Reads the `.pub` next to the private key, or derives it with `ssh-keygen -y`.
+fn public_key_for(private_key: &str) -> Result<String, String> {
+  let private_key = PathBuf::from(shellexpand::tilde(private_key).to_string());
+  let public_key = PathBuf::from(format!("{}.pub", private_key.display()));
+  if let Ok(key) = fs::read_to_string(&public_key) {
+    return Ok(key.trim().to_string());
+  }
+
+  let output = Command::new("ssh-keygen")
+    .arg("-y")
+    .arg("-f")
+    .arg(&private_key)
+    .output()
+    .map_err(|e| format!("Failed to run ssh-keygen for {}: {}", private_key.display(), e))?;
+  if !output.status.success() {
+    return Err(format!(
+      "Could not read a public key for {}: {}",
+      private_key.display(),
+      String::from_utf8_lossy(&output.stderr).trim()
+    ));
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod cloud_init_tests {
+  use serde_json::Value;
+  use crate::ops::base::cloud_init::{UserDataContext, public_key_for, render_user_data};
+
+  fn user_data(hostname: Option<&str>, docker: bool) -> (String, Value) {
+    let rendered = render_user_data(&UserDataContext {
+      host: "10.0.0.8".to_string(),
+      role: "node".to_string(),
+      user: "nya".to_string(),
+      ssh_public_key: "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH5 nya@laptop".to_string(),
+      hostname: hostname.map(String::from),
+      docker,
+    }).unwrap();
+    let parsed: Value = serde_yaml_ng::from_str(&rendered).unwrap();
+    (rendered, parsed)
+  }
+
+  #[test]
+  fn renders_the_user_and_key_as_configured() {
+    let (rendered, user_data) = user_data(Some("nya-worker-1"), false);
+    assert!(rendered.starts_with("#cloud-config\n"));
+    assert_eq!(user_data["hostname"], "nya-worker-1");
+    let user = &user_data["users"][1];
+    assert_eq!(user["name"], "nya");
+    assert_eq!(user["sudo"], "ALL=(ALL) NOPASSWD:ALL");
+    assert_eq!(user["ssh_authorized_keys"][0], "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH5 nya@laptop");
+    assert_eq!(user_data["write_files"][0]["content"], "node\n");
+    assert!(user_data.get("runcmd").is_none());
+  }
+
+  #[test]
+  fn installs_docker_only_when_asked() {
+    let (_, user_data) = user_data(None, true);
+    assert!(user_data.get("hostname").is_none());
+    assert_eq!(user_data["runcmd"][0], "curl -fsSL https://get.docker.com | sh");
+    assert_eq!(user_data["runcmd"][1], "usermod -aG docker nya");
+  }
+
+  #[test]
+  fn reads_the_public_key_next_to_the_private_one() {
+    let dir = tempfile::tempdir().unwrap();
+    let private_key = dir.path().join("id_ed25519");
+    std::fs::write(format!("{}.pub", private_key.display()), "ssh-ed25519 AAAAC3Nza nya@laptop\n").unwrap();
+    assert_eq!(public_key_for(&private_key.display().to_string()).unwrap(), "ssh-ed25519 AAAAC3Nza nya@laptop");
+  }
+}
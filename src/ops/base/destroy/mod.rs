@@ -1,21 +1,31 @@
-use crate::{core::{payload::{Payload, Take}, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::utils::run_on_node};
-use crate::ops::{types, utils};
+use crate::{core::{payload::{Payload, Take}, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::{retry::RetryPolicy, utils::stream_from_node}};
+use crate::ops::{base::build::addons::remove_script, distro::{Component, PackageManager, package_manager}, types, utils};
+use colored::*;
+use inquire::Confirm;
 use openssh::Session;
 use serde::Serialize;
 use serde_json::Value;
 use tera::Context;
 
-const REMOVE_DOCKER_SCRIPT: &str = include_str!("scripts/remove_docker.sh");
+const REMOVE_DOCKER_APT_SCRIPT: &str = include_str!("scripts/apt/remove_docker.sh");
+const REMOVE_DOCKER_DNF_SCRIPT: &str = include_str!("scripts/dnf/remove_docker.sh");
+const REMOVE_DOCKER_APK_SCRIPT: &str = include_str!("scripts/apk/remove_docker.sh");
 const REMOVE_K3S_SERVER_SCRIPT: &str = include_str!("scripts/remove_k3s_server.sh");
 const REMOVE_K3S_AGENT_SCRIPT: &str = include_str!("scripts/remove_k3s_agent.sh");
 const REMOVE_MKCERT_SCRIPT: &str = include_str!("scripts/remove_mkcert.sh");
 const REMOVE_INGRESS_SCRIPT: &str = include_str!("scripts/remove_ingress.sh");
 const REMOVE_HELM_SCRIPT: &str = include_str!("scripts/remove_helm.sh");
 const REMOVE_BIND9_SCRIPT: &str = include_str!("scripts/remove_bind9.sh");
+const REMOVE_WIREGUARD_SCRIPT: &str = include_str!("scripts/remove_wireguard.sh");
+
+/// Every component `nya base destroy` knows how to remove, in teardown order.
+pub(crate) const DESTROY_COMPONENTS: &[&str] = &["addons", "ingress", "helm", "mkcert", "bind9", "k3s", "docker", "wireguard"];
+/// The subset of components that exist on worker nodes.
+const NODE_COMPONENTS: &[&str] = &["k3s", "docker", "wireguard"];
 
 pub struct NyaBaseDestroy;
 
-use types::BaseNodeConfig;
+use types::{AddonConfig, BaseNodeConfig, DestroyOptions, RemovalResult};
 use utils::create_ssh_session;
 
 impl Service for NyaBaseDestroy {
@@ -24,75 +34,262 @@ impl Service for NyaBaseDestroy {
     vec![
       (String::from("onDestroyBase"), handle_action(destroy_action)),
       (String::from("runCleanupNode"), handle_action(run_cleanup_node_script)),
+      (String::from("onDestroyComplete"), handle_action(destroy_report)),
     ]
   }
 }
 
+fn selected_components(options: &DestroyOptions) -> Vec<String> {
+  DESTROY_COMPONENTS
+    .iter()
+    .filter(|c| options.only.is_empty() || options.only.iter().any(|o| o == *c))
+    .filter(|c| !options.keep.iter().any(|k| k == *c))
+    .map(|c| c.to_string())
+    .collect()
+}
+
 async fn destroy_action(nya: Nya, _: Payload) {
+  let options: DestroyOptions = serde_json::from_value(nya.get("destroy_options").await).unwrap_or_default();
+  let components: Vec<String> = selected_components(&options);
+  let node_components: Vec<String> = components.iter().filter(|c| NODE_COMPONENTS.contains(&c.as_str())).cloned().collect();
+
+  let control_plane_config: BaseNodeConfig = utils::get_control_plane_config(nya.clone()).await;
+  let mut node_configs: Vec<BaseNodeConfig> = utils::get_node_configs(nya.clone()).await;
+  let mut include_control_plane = true;
+  if let Some(target) = &options.node {
+    include_control_plane = control_plane_config.host == *target;
+    node_configs.retain(|node| node.host == *target);
+    if !include_control_plane && node_configs.is_empty() {
+      eprintln!("{}", format!("No node with host {} found in the base config.", target).red());
+      return;
+    }
+  }
+
+  if components.is_empty() || (!include_control_plane && node_components.is_empty()) {
+    println!("{}", "Nothing selected to remove.".yellow());
+    return;
+  }
+
+  println!("The following will be removed:");
+  if include_control_plane {
+    println!("  {} (control plane): {}", control_plane_config.host, components.join(", "));
+  }
+  if !node_components.is_empty() {
+    for node in node_configs.iter() {
+      println!("  {} (node): {}", node.host, node_components.join(", "));
+    }
+  }
+
+  if !options.yes {
+    let confirmed = Confirm::new("Do you want to continue?")
+      .with_default(false)
+      .prompt();
+    if !matches!(confirmed, Ok(true)) {
+      println!("{}", "Destroy cancelled".yellow());
+      return;
+    }
+  }
+
   println!("Destroying the base");
 
+  let mut targets: Vec<String> = Vec::new();
+
   // Fan out node cleanup in parallel via trigger
-  let node_configs: Vec<BaseNodeConfig> = utils::get_node_configs(nya.clone()).await;
   let mut cleanup_tasks: Vec<(&str, Payload)> = Vec::new();
-  for node in node_configs.iter() {
-    let session = create_ssh_session(node).await;
-    cleanup_tasks.push(("runCleanupNode", Payload::new(session)));
+  if !node_components.is_empty() {
+    for node in node_configs.iter() {
+      let Some(session) = create_ssh_session(&nya, node).await else {
+        return;
+      };
+      targets.push(node.host.clone());
+      cleanup_tasks.push(("runCleanupNode", Payload::new((session, node.clone(), node_components.clone()))));
+    }
   }
 
   // Control plane teardown in dependency order — blocking, single session
-  let control_plane_config: BaseNodeConfig = utils::get_control_plane_config(nya.clone()).await;
-  let session = create_ssh_session(&control_plane_config).await;
-  let control_plane_vars: Value = nya.get("nya.control_plane.vars").await;
-  
-  println!("Removing ingress...");
-  remove_ingress(&session, &control_plane_vars).await;
-  println!("Removing helm...");
-  remove_helm(&session).await;
-  println!("Removing mkcert...");
-  remove_mkcert(&session, &control_plane_vars).await;
-  println!("Removing bind9...");
-  remove_bind9(&session).await;
-  println!("Removing k3s server...");
-  remove_k3s_server(&session).await;
-  println!("Removing docker...");
-  remove_docker(&session).await;
-  if let Err(e) = session.close().await {
-    eprintln!("destroy_action: failed to close control plane session: {}", e);
-  }
+  if include_control_plane {
+    targets.insert(0, control_plane_config.host.clone());
+    let Some(session) = create_ssh_session(&nya, &control_plane_config).await else {
+      return;
+    };
+    let package_manager = match package_manager(&nya, &control_plane_config.host, &session).await {
+      Ok(package_manager) => package_manager,
+      Err(err) => {
+        eprintln!("{}", format!("Cannot tear down {}: {}", control_plane_config.host, err).red());
+        return;
+      },
+    };
+    let control_plane_vars: Value = nya.get("nya.control_plane.vars").await;
+    let addons: Vec<AddonConfig> = utils::get_addons(nya.clone()).await;
+    let mut results: Vec<RemovalResult> = Vec::new();
 
-  println!("Starting node cleanup...");
-  nya.trigger_all(cleanup_tasks).await;
-  println!("Node cleanup done");
-  // Not sure why the sessions don't close properly after the trigger
-  // Putting this here just to let them time out
-  // TODO: figure out a better way to ensure sessions are cleaned up after trigger tasks complete
-  println!("Finishing up...");
-}
+    for component in DESTROY_COMPONENTS.iter() {
+      if !components.iter().any(|c| c == component) {
+        results.push(RemovalResult::kept(component));
+        continue;
+      }
+      println!("Removing {}...", component);
+      match *component {
+        "addons" => results.extend(remove_addons(&nya, &control_plane_config.host, &session, &addons).await),
+        "ingress" => results.push(remove_ingress(&nya, &control_plane_config.host, &session, &control_plane_vars).await),
+        "helm" => results.push(run_removal(&nya, &control_plane_config.host, &session, "helm", REMOVE_HELM_SCRIPT).await),
+        "mkcert" => results.push(remove_mkcert(&nya, &control_plane_config.host, &session, &control_plane_vars).await),
+        "bind9" => results.push(run_removal(&nya, &control_plane_config.host, &session, "bind9", &render_distro_script(REMOVE_BIND9_SCRIPT, package_manager)).await),
+        "k3s" => results.push(run_removal(&nya, &control_plane_config.host, &session, "k3s", &render_distro_script(REMOVE_K3S_SERVER_SCRIPT, package_manager)).await),
+        "docker" => results.push(run_removal(&nya, &control_plane_config.host, &session, "docker", remove_docker_script(package_manager)).await),
+        "wireguard" => results.push(run_removal(&nya, &control_plane_config.host, &session, "wireguard", &render_distro_script(REMOVE_WIREGUARD_SCRIPT, package_manager)).await),
+        _ => {},
+      }
+    }
+    nya.set(&format!("destroy_report.{}", control_plane_config.host), results).await;
+
+    if let Err(e) = session.close().await {
+      eprintln!("destroy_action: failed to close control plane session: {}", e);
+    }
+  }
 
-async fn run_cleanup_node_script(_: Nya, payload: Payload) {
-  let session = payload.take::<Session>().unwrap();
+  nya.set("destroy_targets", targets).await;
 
-  match run_on_node(&session, REMOVE_K3S_AGENT_SCRIPT).await {
-    types::NodeCommandResult::Success => {},
-    types::NodeCommandResult::Failure(err) => eprintln!("run_cleanup_node_script: remove k3s-agent script failed: {}", err),
+  if !cleanup_tasks.is_empty() {
+    println!("Starting node cleanup...");
+    nya.trigger_all(cleanup_tasks).await;
   }
+}
+
+async fn run_cleanup_node_script(nya: Nya, payload: Payload) {
+  let (session, node, components) = payload.take::<(Session, BaseNodeConfig, Vec<String>)>().unwrap();
+  let mut results: Vec<RemovalResult> = Vec::new();
+  let package_manager = match package_manager(&nya, &node.host, &session).await {
+    Ok(package_manager) => package_manager,
+    Err(err) => {
+      results.push(RemovalResult { component: "all".to_string(), status: "failed".to_string(), detail: err });
+      nya.set(&format!("destroy_report.{}", node.host), results).await;
+      let _ = session.close().await;
+      return;
+    },
+  };
 
-  match run_on_node(&session, REMOVE_DOCKER_SCRIPT).await {
-    types::NodeCommandResult::Success => {},
-    types::NodeCommandResult::Failure(err) => eprintln!("run_cleanup_node_script: remove docker script failed: {}", err),
+  for component in NODE_COMPONENTS.iter() {
+    if !components.iter().any(|c| c == component) {
+      results.push(RemovalResult::kept(component));
+      continue;
+    }
+    match *component {
+      "k3s" => results.push(run_removal(&nya, &node.host, &session, "k3s", &render_distro_script(REMOVE_K3S_AGENT_SCRIPT, package_manager)).await),
+      "docker" => results.push(run_removal(&nya, &node.host, &session, "docker", remove_docker_script(package_manager)).await),
+      "wireguard" => results.push(run_removal(&nya, &node.host, &session, "wireguard", &render_distro_script(REMOVE_WIREGUARD_SCRIPT, package_manager)).await),
+      _ => {},
+    }
   }
+  nya.set(&format!("destroy_report.{}", node.host), results).await;
 
   if let Err(e) = session.close().await {
     eprintln!("run_cleanup_node_script: failed to close session: {}", e);
   }
 }
 
+async fn destroy_report(nya: Nya, _: Payload) {
+  let targets: Vec<String> = serde_json::from_value(nya.get("destroy_targets").await).unwrap_or_default();
+  if targets.is_empty() {
+    return;
+  }
+
+  println!();
+  println!("{}", "Destroy report".bold());
+  let mut failed = false;
+  for host in targets.iter() {
+    let results: Vec<RemovalResult> = serde_json::from_value(nya.get(&format!("destroy_report.{}", host)).await).unwrap_or_default();
+    println!("{}", host.cyan());
+    for result in results.iter() {
+      let status = match result.status.as_str() {
+        "removed" => result.status.green(),
+        "failed" => { failed = true; result.status.red() },
+        _ => result.status.yellow(),
+      };
+      println!("  {:<24} {:<8} {}", result.component, status, result.detail);
+    }
+  }
+
+  if failed {
+    println!("{}", "Some components could not be removed, see the report above.".red());
+  } else {
+    println!("{}", "Destroy completed.".green());
+  }
+}
+
+impl RemovalResult {
+  fn kept(component: &str) -> Self {
+    Self { component: component.to_string(), status: "kept".to_string(), detail: String::new() }
+  }
+}
+
+/// Runs a removal script and condenses its output into a report line.
+async fn run_removal(nya: &Nya, host: &str, session: &Session, component: &str, script: &str) -> RemovalResult {
+  let (status, output) = match stream_from_node(nya, host, session, script, &RetryPolicy::INSTALL).await {
+    Ok(output) => ("removed", output),
+    Err(err) => ("failed", err),
+  };
+  let detail = output
+    .lines()
+    .rev()
+    .find(|line| !line.trim().is_empty())
+    .unwrap_or("")
+    .trim()
+    .to_string();
+  RemovalResult { component: component.to_string(), status: status.to_string(), detail }
+}
+
+fn remove_docker_script(package_manager: PackageManager) -> &'static str {
+  match package_manager {
+    PackageManager::Apt => REMOVE_DOCKER_APT_SCRIPT,
+    PackageManager::Dnf => REMOVE_DOCKER_DNF_SCRIPT,
+    PackageManager::Apk => REMOVE_DOCKER_APK_SCRIPT,
+  }
+}
+
+#[derive(Serialize)]
+struct RemoveDistroContext {
+  install_iptables: String,
+  stop_bind9: String,
+  remove_bind9: String,
+  main_conf: Option<String>,
+  disable_wireguard: String,
+  remove_wireguard: String,
+}
+
+/// Fills in the package manager and service commands for the node's distro.
+fn render_distro_script(script: &str, package_manager: PackageManager) -> String {
+  let context = RemoveDistroContext {
+    install_iptables: package_manager.install(package_manager.packages(Component::Iptables)),
+    stop_bind9: package_manager.stop_service(package_manager.service(Component::Bind9)),
+    remove_bind9: package_manager.remove(package_manager.packages(Component::Bind9)),
+    main_conf: package_manager.bind_main_conf().map(|c| c.to_string()),
+    disable_wireguard: package_manager.disable_service(package_manager.service(Component::Wireguard)),
+    remove_wireguard: package_manager.remove(package_manager.packages(Component::Wireguard)),
+  };
+  tera::Tera::one_off(
+    script,
+    &Context::from_serialize(serde_json::to_value(context).unwrap()).unwrap(),
+    false,
+  ).unwrap()
+}
+
+async fn remove_addons(nya: &Nya, host: &str, session: &Session, addons: &[AddonConfig]) -> Vec<RemovalResult> {
+  let mut results = Vec::new();
+  // Uninstall in reverse so addons that depend on earlier ones go first
+  for addon in addons.iter().rev() {
+    let rendered = remove_script(addon);
+    results.push(run_removal(nya, host, session, &format!("addon:{}", addon.name), &rendered).await);
+  }
+  results
+}
+
 #[derive(Serialize)]
 struct RemoveIngressContext {
   secret_name: String,
 }
 
-async fn remove_ingress(session: &Session, vars: &Value) {
+async fn remove_ingress(nya: &Nya, host: &str, session: &Session, vars: &Value) -> RemovalResult {
   let secret_name = vars.get("secret_name").and_then(|v| v.as_str()).unwrap_or("").to_string();
   let rendered = tera::Tera::one_off(
     REMOVE_INGRESS_SCRIPT,
@@ -100,10 +297,7 @@ async fn remove_ingress(session: &Session, vars: &Value) {
     false,
   ).unwrap();
 
-  match run_on_node(session, &rendered).await {
-    types::NodeCommandResult::Success => {},
-    types::NodeCommandResult::Failure(err) => eprintln!("remove_ingress: failed: {}", err),
-  }
+  run_removal(nya, host, session, "ingress", &rendered).await
 }
 
 #[derive(Serialize)]
@@ -111,7 +305,7 @@ struct RemoveMkcertContext {
   domain: String,
 }
 
-async fn remove_mkcert(session: &Session, vars: &Value) {
+async fn remove_mkcert(nya: &Nya, host: &str, session: &Session, vars: &Value) -> RemovalResult {
   let domain = vars.get("domain_name").and_then(|v| v.as_str()).unwrap_or("").to_string();
   let rendered = tera::Tera::one_off(
     REMOVE_MKCERT_SCRIPT,
@@ -119,36 +313,26 @@ async fn remove_mkcert(session: &Session, vars: &Value) {
     false,
   ).unwrap();
 
-  match run_on_node(session, &rendered).await {
-    types::NodeCommandResult::Success => {},
-    types::NodeCommandResult::Failure(err) => eprintln!("remove_mkcert: failed: {}", err),
-  }
+  run_removal(nya, host, session, "mkcert", &rendered).await
 }
 
-async fn remove_helm(session: &Session) {
-  match run_on_node(session, REMOVE_HELM_SCRIPT).await {
-    types::NodeCommandResult::Success => {},
-    types::NodeCommandResult::Failure(err) => eprintln!("remove_helm: failed: {}", err),
-  }
-}
-
-async fn remove_bind9(session: &Session) {
-  match run_on_node(session, REMOVE_BIND9_SCRIPT).await {
-    types::NodeCommandResult::Success => {},
-    types::NodeCommandResult::Failure(err) => eprintln!("remove_bind9: failed: {}", err),
-  }
-}
+#[cfg(test)]
+mod destroy_tests {
+  use crate::ops::{base::destroy::selected_components, types::DestroyOptions};
 
-async fn remove_k3s_server(session: &Session) {
-  match run_on_node(session, REMOVE_K3S_SERVER_SCRIPT).await {
-    types::NodeCommandResult::Success => {},
-    types::NodeCommandResult::Failure(err) => eprintln!("remove_k3s_server: failed: {}", err),
+  #[test]
+  fn selects_every_component_by_default() {
+    let components = selected_components(&DestroyOptions::default());
+    assert_eq!(components, vec!["addons", "ingress", "helm", "mkcert", "bind9", "k3s", "docker", "wireguard"]);
   }
-}
 
-async fn remove_docker(session: &Session) {
-  match run_on_node(session, REMOVE_DOCKER_SCRIPT).await {
-    types::NodeCommandResult::Success => {},
-    types::NodeCommandResult::Failure(err) => eprintln!("remove_docker: failed: {}", err),
+  #[test]
+  fn only_and_keep_narrow_the_selection() {
+    let options = DestroyOptions {
+      only: vec!["docker".to_string(), "k3s".to_string(), "ingress".to_string()],
+      keep: vec!["docker".to_string()],
+      ..Default::default()
+    };
+    assert_eq!(selected_components(&options), vec!["ingress", "k3s"]);
   }
 }
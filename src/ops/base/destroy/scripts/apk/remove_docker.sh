@@ -0,0 +1,14 @@
+#!/bin/bash
+set -euo pipefail
+
+sudo rm -f /etc/docker/daemon.json
+sudo rc-service docker stop || true
+sudo rc-update del docker default || true
+sudo apk del docker || true
+
+sudo rm -rf /var/lib/docker
+sudo rm -rf /var/lib/containerd
+
+sudo delgroup ${USER} docker || true
+
+echo "Docker uninstalled successfully"
@@ -0,0 +1,17 @@
+#!/bin/bash
+set -euo pipefail
+
+sudo rm -f /etc/docker/daemon.json
+sudo systemctl stop docker.socket || true
+sudo systemctl stop docker || true
+sudo systemctl disable docker.socket || true
+sudo systemctl disable docker || true
+sudo dnf remove -y docker-ce docker-ce-cli containerd.io docker-buildx-plugin docker-compose-plugin || true
+sudo rm -f /etc/yum.repos.d/docker-ce.repo
+
+sudo rm -rf /var/lib/docker
+sudo rm -rf /var/lib/containerd
+
+sudo gpasswd -d ${USER} docker || true
+
+echo "Docker uninstalled successfully"
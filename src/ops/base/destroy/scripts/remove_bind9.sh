@@ -1,9 +1,10 @@
 #!/bin/bash
 set -euo pipefail
 
-sudo systemctl stop bind9 || true
-sudo apt remove --purge bind9 bind9utils -y
-sudo apt autoremove -y
+{{ stop_bind9 }} || true
+{{ remove_bind9 }}
 sudo rm -f /etc/bind/named.conf.local
 sudo rm -f /etc/bind/named.conf.options
 sudo rm -rf /etc/bind/zones
+{% if main_conf %}sudo rm -f {{ main_conf }}
+{% endif %}
@@ -1,7 +1,7 @@
 #!/bin/bash
 set -euo pipefail
 
-sudo apt-get install -y iptables || true
+{{ install_iptables }} || true
 sudo iptables -F
 sudo iptables -t nat -F
 sudo iptables -t mangle -F
@@ -1,7 +1,7 @@
 #!/bin/bash
 set -euo pipefail
 
-sudo apt-get install -y iptables || true
+{{ install_iptables }} || true
 sudo iptables -F
 sudo iptables -t nat -F
 sudo iptables -t mangle -F
@@ -9,6 +9,9 @@ sudo iptables -X
 
 sudo docker stop registry || true
 sudo docker rm registry || true
+sudo rm -rf /var/lib/nya/registry
+sudo docker ps -aq --filter name=registry-mirror- | xargs -r sudo docker rm -f || true
+sudo rm -rf /var/lib/nya/mirror
 
 if [ -f /usr/local/bin/k3s-uninstall.sh ]; then
   sudo /usr/local/bin/k3s-uninstall.sh
@@ -0,0 +1,12 @@
+#!/bin/bash
+set -euo pipefail
+
+if [ ! -e /etc/wireguard/nya.key ]; then
+  echo "No WireGuard overlay set up by Nya"
+  exit 0
+fi
+{{ disable_wireguard }} || true
+sudo ip link delete wg0 2>/dev/null || true
+sudo rm -f /etc/wireguard/wg0.conf /etc/wireguard/nya.key /etc/init.d/wg-quick.wg0
+{{ remove_wireguard }} || true
+echo "WireGuard overlay removed"
@@ -0,0 +1,258 @@
+use std::fs;
+use colored::*;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{core::{payload::Payload, runtime::Nya, service::{Service, ServiceActions, handle_action}}, defaults::inventory_default_location, ops::{types::BaseNodeConfig, utils::{get_control_plane_config, get_from_node, get_node_configs, try_create_ssh_session}}};
+
+const KUBECTL: &str = "sudo kubectl --kubeconfig=/etc/rancher/k3s/k3s.yaml";
+
+/// Prints `key=value` lines. Sticks to POSIX tools so it also runs on Alpine.
+const NODE_FACTS_CMD: &str = "echo \"hostname=$(hostname)\"; \
+  echo \"cpus=$(nproc)\"; \
+  awk '/MemTotal/ {print \"memory_kb=\" $2}' /proc/meminfo; \
+  df -Pk / | awk 'NR==2 {print \"disk_total_kb=\" $2; print \"disk_free_kb=\" $4}'; \
+  echo \"arch=$(uname -m)\"; \
+  echo \"kernel=$(uname -r)\"";
+
+pub struct NyaBaseInventory;
+
+impl Service for NyaBaseInventory {
+  fn name(&self) -> String {"NyaBaseInventory".to_string()}
+  fn register(&self) -> ServiceActions {
+    vec![
+      (String::from("onCollectInventory"), handle_action(collect_inventory)),
+    ]
+  }
+}
+
+/// What a node has, from the node itself and from what Kubernetes can schedule on it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct NodeInventory {
+  pub host: String,
+  pub role: String,
+  pub hostname: String,
+  pub cpus: u64,
+  pub memory_bytes: u64,
+  pub disk_total_bytes: u64,
+  pub disk_free_bytes: u64,
+  pub arch: String,
+  pub kernel: String,
+  /// None when the node isn't part of the cluster yet.
+  pub allocatable_cpu_millis: Option<u64>,
+  pub allocatable_memory_bytes: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Inventory {
+  pub collected_at: String,
+  pub nodes: Vec<NodeInventory>,
+}
+
+impl Inventory {
+  /// CPU in millicores and memory in bytes the cluster can schedule in total,
+  /// or None when no node had allocatable data when the inventory was collected.
+  pub fn allocatable(&self) -> Option<(u64, u64)> {
+    self.nodes.iter()
+      .filter_map(|node| Some((node.allocatable_cpu_millis?, node.allocatable_memory_bytes?)))
+      .reduce(|(cpu, memory), (node_cpu, node_memory)| (cpu + node_cpu, memory + node_memory))
+  }
+}
+
+/// The inventory saved by the last `nya base inventory`, if there is one.
+pub fn load_inventory() -> Option<Inventory> {
+  let content = fs::read_to_string(inventory_default_location()).ok()?;
+  serde_json::from_str(&content).ok()
+}
+
+/// Parses a Kubernetes CPU quantity ("2", "0.5", "250m") into millicores.
+pub fn parse_cpu_millis(quantity: &str) -> Option<u64> {
+  let quantity = quantity.trim();
+  if let Some(millis) = quantity.strip_suffix('m') {
+    return millis.parse::<u64>().ok();
+  }
+  quantity.parse::<f64>().ok().map(|cores| (cores * 1000.0).round() as u64)
+}
+
+/// Parses a Kubernetes memory quantity ("128Mi", "1G", "1048576") into bytes.
+pub fn parse_memory_bytes(quantity: &str) -> Option<u64> {
+  const UNITS: [(&str, f64); 10] = [
+    ("Ki", 1024.0), ("Mi", 1048576.0), ("Gi", 1073741824.0), ("Ti", 1099511627776.0),
+    ("k", 1e3), ("K", 1e3), ("M", 1e6), ("G", 1e9), ("T", 1e12), ("", 1.0),
+  ];
+  let quantity = quantity.trim();
+  UNITS.iter().find_map(|(suffix, factor)| {
+    let number = quantity.strip_suffix(suffix)?;
+    number.parse::<f64>().ok().map(|n| (n * factor).round() as u64)
+  })
+}
+
+async fn collect_inventory(nya: Nya, _: Payload) {
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let node_configs: Vec<BaseNodeConfig> = get_node_configs(nya.clone()).await;
+  let targets: Vec<(BaseNodeConfig, &str)> = std::iter::once((control_plane_config.clone(), "control_plane"))
+    .chain(node_configs.into_iter().map(|node| (node, "node")))
+    .collect();
+
+  println!("Collecting inventory from {} node(s)", targets.len());
+  let collections = targets.iter().map(|(node, role)| {
+    let nya = nya.clone();
+    async move {
+      let session = try_create_ssh_session(node).await.map_err(|e| format!("{}: could not connect: {}", node.host, e))?;
+      let facts = get_from_node(&nya, &node.host, &session, NODE_FACTS_CMD).await;
+      let _ = session.close().await;
+      let mut inventory = parse_node_facts(&facts.map_err(|e| format!("{}: {}", node.host, e.trim()))?);
+      inventory.host = node.host.clone();
+      inventory.role = role.to_string();
+      Ok::<NodeInventory, String>(inventory)
+    }
+  });
+  let results = join_all(collections).await;
+
+  let mut nodes: Vec<NodeInventory> = Vec::new();
+  for result in results {
+    match result {
+      Ok(node) => nodes.push(node),
+      Err(err) => eprintln!("{}", format!("Skipping {}", err).yellow()),
+    }
+  }
+
+  match try_create_ssh_session(&control_plane_config).await {
+    Ok(session) => {
+      match get_from_node(&nya, &control_plane_config.host, &session, &format!("{} get nodes -o json", KUBECTL)).await {
+        Ok(output) => match serde_json::from_str::<Value>(&output) {
+          Ok(kube_nodes) => apply_allocatable(&mut nodes, &kube_nodes),
+          Err(e) => eprintln!("Failed to read the node list from kubectl: {}", e),
+        },
+        Err(err) => eprintln!("{}", format!("Could not ask Kubernetes for allocatable resources: {}", err.trim()).yellow()),
+      }
+      let _ = session.close().await;
+    },
+    Err(err) => eprintln!("Failed to connect to the control plane: {}", err),
+  }
+
+  let inventory = Inventory { collected_at: chrono::Utc::now().to_rfc3339(), nodes };
+  print_inventory(&inventory);
+
+  let path = inventory_default_location();
+  if let Some(dir) = path.parent()
+    && let Err(e) = fs::create_dir_all(dir) {
+    eprintln!("Failed to create {}: {}", dir.display(), e);
+    return;
+  }
+  match fs::write(&path, serde_json::to_string_pretty(&inventory).unwrap()) {
+    Ok(()) => println!("Inventory saved to {}", path.display()),
+    Err(e) => eprintln!("{}", format!("Failed to save the inventory to {}: {}", path.display(), e).red()),
+  }
+}
+
+fn parse_node_facts(output: &str) -> NodeInventory {
+  let mut node = NodeInventory::default();
+  for line in output.lines() {
+    let Some((key, value)) = line.split_once('=') else {
+      continue;
+    };
+    let value = value.trim();
+    let number = || value.parse::<u64>().unwrap_or(0);
+    match key.trim() {
+      "hostname" => node.hostname = value.to_string(),
+      "cpus" => node.cpus = number(),
+      "memory_kb" => node.memory_bytes = number() * 1024,
+      "disk_total_kb" => node.disk_total_bytes = number() * 1024,
+      "disk_free_kb" => node.disk_free_bytes = number() * 1024,
+      "arch" => node.arch = value.to_string(),
+      "kernel" => node.kernel = value.to_string(),
+      _ => {},
+    }
+  }
+  node
+}
+
+/// Matches `kubectl get nodes -o json` entries to nodes by IP, then by hostname.
+fn apply_allocatable(nodes: &mut [NodeInventory], kube_nodes: &Value) {
+  let Some(items) = kube_nodes.get("items").and_then(|v| v.as_array()) else {
+    return;
+  };
+  for item in items {
+    let addresses: Vec<&str> = item.pointer("/status/addresses")
+      .and_then(|v| v.as_array())
+      .map(|addresses| addresses.iter().filter_map(|a| a.get("address").and_then(|v| v.as_str())).collect())
+      .unwrap_or_default();
+    let name = item.pointer("/metadata/name").and_then(|v| v.as_str()).unwrap_or("");
+    let Some(node) = nodes.iter_mut().find(|node| addresses.contains(&node.host.as_str()) || node.hostname == name) else {
+      continue;
+    };
+    node.allocatable_cpu_millis = item.pointer("/status/allocatable/cpu").and_then(|v| v.as_str()).and_then(parse_cpu_millis);
+    node.allocatable_memory_bytes = item.pointer("/status/allocatable/memory").and_then(|v| v.as_str()).and_then(parse_memory_bytes);
+  }
+}
+
+fn gib(bytes: u64) -> String {
+  format!("{:.1}Gi", bytes as f64 / 1073741824.0)
+}
+
+fn print_inventory(inventory: &Inventory) {
+  println!("  {:<18} {:<14} {:<8} {:>5} {:>8} {:>17} {:>19}", "HOST", "ROLE", "ARCH", "CPUS", "MEMORY", "DISK (FREE)", "ALLOCATABLE");
+  for node in inventory.nodes.iter() {
+    let allocatable = match (node.allocatable_cpu_millis, node.allocatable_memory_bytes) {
+      (Some(cpu), Some(memory)) => format!("{}m / {}", cpu, gib(memory)),
+      _ => "not in cluster".to_string(),
+    };
+    println!(
+      "  {:<18} {:<14} {:<8} {:>5} {:>8} {:>17} {:>19}",
+      node.host, node.role, node.arch, node.cpus, gib(node.memory_bytes),
+      format!("{} ({})", gib(node.disk_total_bytes), gib(node.disk_free_bytes)), allocatable
+    );
+  }
+  match inventory.allocatable() {
+    Some((cpu, memory)) => println!("  Cluster can schedule {}m CPU and {} memory.", cpu, gib(memory)),
+    None => println!("  No node reported allocatable resources; is the cluster up?"),
+  }
+}
+
+#[cfg(test)]
+mod inventory_tests {
+  use serde_json::json;
+  use crate::ops::base::inventory::{Inventory, apply_allocatable, parse_cpu_millis, parse_memory_bytes, parse_node_facts};
+
+  #[test]
+  fn parses_kubernetes_quantities() {
+    assert_eq!(parse_cpu_millis("250m"), Some(250));
+    assert_eq!(parse_cpu_millis("2"), Some(2000));
+    assert_eq!(parse_cpu_millis("0.5"), Some(500));
+    assert_eq!(parse_memory_bytes("128Mi"), Some(134217728));
+    assert_eq!(parse_memory_bytes("1G"), Some(1_000_000_000));
+    assert_eq!(parse_memory_bytes("8029612Ki"), Some(8222322688));
+    assert_eq!(parse_memory_bytes("1024"), Some(1024));
+    assert_eq!(parse_memory_bytes("lots"), None);
+  }
+
+  #[test]
+  fn builds_inventory_from_node_facts_and_kubectl() {
+    let mut node = parse_node_facts("hostname=cp-1\ncpus=4\nmemory_kb=8000000\ndisk_total_kb=1000\ndisk_free_kb=500\narch=x86_64\nkernel=6.8.0\n");
+    node.host = "10.0.0.1".to_string();
+    assert_eq!(node.cpus, 4);
+    assert_eq!(node.memory_bytes, 8_192_000_000);
+    assert_eq!(node.disk_free_bytes, 512_000);
+
+    let mut nodes = vec![node];
+    let kube_nodes = json!({ "items": [{
+      "metadata": { "name": "cp-1" },
+      "status": {
+        "addresses": [{ "type": "InternalIP", "address": "10.0.0.1" }],
+        "allocatable": { "cpu": "4", "memory": "7812500Ki" }
+      }
+    }]});
+    apply_allocatable(&mut nodes, &kube_nodes);
+    let inventory = Inventory { collected_at: String::new(), nodes };
+    assert_eq!(inventory.allocatable(), Some((4000, 8_000_000_000)));
+  }
+
+  #[test]
+  fn has_no_allocatable_total_without_cluster_data() {
+    let node = parse_node_facts("hostname=cp-1\ncpus=4\nmemory_kb=8000000\n");
+    let inventory = Inventory { collected_at: String::new(), nodes: vec![node] };
+    assert_eq!(inventory.allocatable(), None);
+  }
+}
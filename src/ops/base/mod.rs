@@ -1,2 +1,7 @@
+pub mod backup;
 pub mod build;
-pub mod destroy;
\ No newline at end of file
+pub mod bundle;
+pub mod cloud_init;
+pub mod destroy;
+pub mod inventory;
+pub mod patch;
\ No newline at end of file
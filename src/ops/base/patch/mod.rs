@@ -0,0 +1,251 @@
+use std::time::{Duration, Instant};
+use colored::*;
+use openssh::Session;
+
+use crate::{core::{checks::{Check, CheckIf}, payload::Payload, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::{distro::{PackageManager, package_manager}, retry::RetryPolicy, types::{BaseNodeConfig, NodeCommandResult}, utils::{create_ssh_session, get_control_plane_config, connect_with, get_from_node, get_node_configs, run_on_node, stream_from_node, try_create_ssh_session}}};
+
+const UPGRADE_APT_SCRIPT: &str = include_str!("scripts/apt/upgrade_packages.sh");
+const UPGRADE_DNF_SCRIPT: &str = include_str!("scripts/dnf/upgrade_packages.sh");
+const UPGRADE_APK_SCRIPT: &str = include_str!("scripts/apk/upgrade_packages.sh");
+const KUBECTL: &str = "sudo kubectl --kubeconfig=/etc/rancher/k3s/k3s.yaml";
+const REBOOT_MARKER: &str = "NYA_REBOOT_REQUIRED";
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(600);
+const SERVICE_TIMEOUT: Duration = Duration::from_secs(300);
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct NyaBasePatch;
+
+impl Service for NyaBasePatch {
+  fn name(&self) -> String {"NyaBasePatch".to_string()}
+  fn register(&self) -> ServiceActions {
+    vec![
+      (String::from("onPatchBase"), handle_action(patch_base)),
+    ]
+  }
+}
+
+#[derive(Debug, PartialEq)]
+enum PatchStep<'a> {
+  Node(&'a BaseNodeConfig),
+  ControlPlane { drain: bool },
+}
+
+/// Workers go first, one at a time, while the control plane keeps the
+/// cluster running. The control plane goes last and is only drained when
+/// there are workers to take its pods.
+fn patch_plan(node_configs: &[BaseNodeConfig]) -> Vec<PatchStep<'_>> {
+  let mut plan: Vec<PatchStep> = node_configs.iter().map(PatchStep::Node).collect();
+  plan.push(PatchStep::ControlPlane { drain: !node_configs.is_empty() });
+  plan
+}
+
+async fn patch_base(nya: Nya, _: Payload) {
+  println!("Patching the base");
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let node_configs: Vec<BaseNodeConfig> = get_node_configs(nya.clone()).await;
+
+  let Some(control_plane_session) = create_ssh_session(&nya, &control_plane_config).await else {
+    return;
+  };
+  let mut control_plane_session = Some(control_plane_session);
+  for step in patch_plan(&node_configs) {
+    match step {
+      PatchStep::Node(node) => {
+        println!("{}", format!("Patching node {}", node.host).cyan());
+        let session = control_plane_session.as_ref().expect("workers are patched before the control plane");
+        if let Err(err) = patch_node(&nya, &control_plane_config.host, session, node).await {
+          eprintln!("{}", format!("Patching {} failed: {}", node.host, err).red());
+          eprintln!("Stopping here so the rest of the base stays untouched. {} may still be cordoned.", node.host);
+          nya.stop();
+          return;
+        }
+        println!("{}", format!("Node {} patched.", node.host).green());
+      },
+      PatchStep::ControlPlane { drain } => {
+        if let Some(session) = control_plane_session.take()
+          && let Err(e) = session.close().await {
+          eprintln!("patch_base: failed to close control plane session: {}", e);
+        }
+        println!("{}", format!("Patching control plane {}", control_plane_config.host).cyan());
+        if let Err(err) = patch_control_plane(&nya, &control_plane_config, drain).await {
+          eprintln!("{}", format!("Patching the control plane failed: {}", err).red());
+          nya.stop();
+          return;
+        }
+      },
+    }
+  }
+  println!("{}", "Base patched successfully!".green());
+}
+
+async fn patch_node(nya: &Nya, control_plane_host: &str, control_plane_session: &Session, node: &BaseNodeConfig) -> Result<(), String> {
+  let session: Session = try_create_ssh_session(node).await?;
+  let package_manager = package_manager(nya, &node.host, &session).await?;
+  let node_name = node_name(nya, &node.host, &session).await?;
+
+  kubectl(nya, control_plane_host, control_plane_session, &format!("cordon {}", node_name)).await?;
+  kubectl(nya, control_plane_host, control_plane_session, &format!("drain {} --ignore-daemonsets --delete-emptydir-data --timeout=300s", node_name)).await?;
+
+  let reboot_required = upgrade_packages(nya, &node.host, &session, package_manager).await?;
+  let session = if reboot_required {
+    reboot_and_reconnect(nya, session, node, package_manager).await?
+  } else {
+    session
+  };
+
+  wait_for_service(&session, CheckIf::K3sAgentIsRunning, "k3s-agent").await?;
+  kubectl(nya, control_plane_host, control_plane_session, &format!("wait --for=condition=Ready node/{} --timeout=300s", node_name)).await?;
+  kubectl(nya, control_plane_host, control_plane_session, &format!("uncordon {}", node_name)).await?;
+
+  if let Err(e) = session.close().await {
+    eprintln!("patch_node: failed to close session: {}", e);
+  }
+  Ok(())
+}
+
+/// The control plane runs kubectl against itself, so it has to be reconnected
+/// to after a reboot and only drained when other nodes can take its workloads.
+async fn patch_control_plane(nya: &Nya, control_plane_config: &BaseNodeConfig, drain: bool) -> Result<(), String> {
+  let session: Session = try_create_ssh_session(control_plane_config).await?;
+  let package_manager = package_manager(nya, &control_plane_config.host, &session).await?;
+  let node_name = node_name(nya, &control_plane_config.host, &session).await?;
+
+  if drain {
+    kubectl(nya, &control_plane_config.host, &session, &format!("cordon {}", node_name)).await?;
+    kubectl(nya, &control_plane_config.host, &session, &format!("drain {} --ignore-daemonsets --delete-emptydir-data --timeout=300s", node_name)).await?;
+  }
+
+  let reboot_required = upgrade_packages(nya, &control_plane_config.host, &session, package_manager).await?;
+  let session = if reboot_required {
+    reboot_and_reconnect(nya, session, control_plane_config, package_manager).await?
+  } else {
+    session
+  };
+
+  wait_for_service(&session, CheckIf::K3sIsRunning, "k3s").await?;
+  wait_for_api_server(&session).await?;
+  kubectl(nya, &control_plane_config.host, &session, &format!("wait --for=condition=Ready node/{} --timeout=300s", node_name)).await?;
+  if drain {
+    kubectl(nya, &control_plane_config.host, &session, &format!("uncordon {}", node_name)).await?;
+  }
+
+  if let Err(e) = session.close().await {
+    eprintln!("patch_control_plane: failed to close session: {}", e);
+  }
+  Ok(())
+}
+
+async fn node_name(nya: &Nya, host: &str, session: &Session) -> Result<String, String> {
+  Ok(get_from_node(nya, host, session, "hostname").await?.trim().to_string())
+}
+
+async fn kubectl(nya: &Nya, host: &str, session: &Session, args: &str) -> Result<String, String> {
+  stream_from_node(nya, host, session, &format!("{} {}", KUBECTL, args), &RetryPolicy::COMMAND).await
+}
+
+async fn upgrade_packages(nya: &Nya, host: &str, session: &Session, package_manager: PackageManager) -> Result<bool, String> {
+  println!("Upgrading packages with {}...", package_manager.name());
+  let script = match package_manager {
+    PackageManager::Apt => UPGRADE_APT_SCRIPT,
+    PackageManager::Dnf => UPGRADE_DNF_SCRIPT,
+    PackageManager::Apk => UPGRADE_APK_SCRIPT,
+  };
+  let output = stream_from_node(nya, host, session, script, &RetryPolicy::INSTALL).await?;
+  Ok(output.lines().any(|line| line.trim() == REBOOT_MARKER))
+}
+
+async fn reboot_and_reconnect(nya: &Nya, session: Session, node: &BaseNodeConfig, package_manager: PackageManager) -> Result<Session, String> {
+  let boot_id = get_from_node(nya, &node.host, &session, "cat /proc/sys/kernel/random/boot_id").await?.trim().to_string();
+  println!("Rebooting {}...", node.host);
+  // Schedule the reboot so the command returns before the connection drops
+  let reboot_cmd = match package_manager {
+    PackageManager::Apk => "sudo sh -c 'nohup sh -c \"sleep 3 && reboot\" > /dev/null 2>&1 &'",
+    _ => "sudo systemd-run --on-active=3 /bin/systemctl reboot",
+  };
+  if let NodeCommandResult::Failure(err) = run_on_node(nya, &node.host, &session, reboot_cmd).await {
+    return Err(format!("failed to reboot: {}", err));
+  }
+  let _ = session.close().await;
+
+  let started = Instant::now();
+  while started.elapsed() < RECONNECT_TIMEOUT {
+    tokio::time::sleep(POLL_INTERVAL).await;
+    let Ok(session) = connect_with(node, &RetryPolicy { attempts: 1, ..RetryPolicy::CONNECT }).await else {
+      continue;
+    };
+    match get_from_node(nya, &node.host, &session, "cat /proc/sys/kernel/random/boot_id").await {
+      Ok(current) if has_rebooted(&boot_id, &current) => {
+        println!("{} is back up.", node.host);
+        return Ok(session);
+      },
+      _ => {
+        let _ = session.close().await;
+      },
+    }
+  }
+  Err(format!("did not come back within {}s of rebooting", RECONNECT_TIMEOUT.as_secs()))
+}
+
+/// A new boot_id means the node really restarted, not that the connection
+/// came back before the reboot did.
+fn has_rebooted(boot_id: &str, current: &str) -> bool {
+  let current = current.trim();
+  !current.is_empty() && current != boot_id.trim()
+}
+
+async fn wait_for_service(session: &Session, check: CheckIf, service: &str) -> Result<(), String> {
+  let started = Instant::now();
+  while started.elapsed() < SERVICE_TIMEOUT {
+    if Check::run(check, session).await {
+      return Ok(());
+    }
+    tokio::time::sleep(POLL_INTERVAL).await;
+  }
+  Err(format!("{} was not active within {}s", service, SERVICE_TIMEOUT.as_secs()))
+}
+
+async fn wait_for_api_server(session: &Session) -> Result<(), String> {
+  let started = Instant::now();
+  let check = format!("{} get --raw /readyz > /dev/null 2>&1", KUBECTL);
+  while started.elapsed() < SERVICE_TIMEOUT {
+    let ready = session.command("bash").arg("-c").arg(&check).status().await;
+    if ready.is_ok_and(|status| status.success()) {
+      return Ok(());
+    }
+    tokio::time::sleep(POLL_INTERVAL).await;
+  }
+  Err(format!("the Kubernetes API was not ready within {}s", SERVICE_TIMEOUT.as_secs()))
+}
+
+#[cfg(test)]
+mod patch_tests {
+  use serde_json::json;
+  use crate::ops::{base::patch::{PatchStep, has_rebooted, patch_plan}, types::BaseNodeConfig};
+
+  fn node(host: &str) -> BaseNodeConfig {
+    BaseNodeConfig::new(json!({ "host": host, "user": "nya", "ssh_private_key_file": "~/.ssh/id_ed25519" }))
+  }
+
+  #[test]
+  fn patches_the_control_plane_last() {
+    let nodes = vec![node("10.0.0.8"), node("10.0.0.9")];
+    assert_eq!(patch_plan(&nodes), vec![
+      PatchStep::Node(&nodes[0]),
+      PatchStep::Node(&nodes[1]),
+      PatchStep::ControlPlane { drain: true },
+    ]);
+  }
+
+  #[test]
+  fn only_drains_the_control_plane_when_there_are_workers() {
+    assert_eq!(patch_plan(&[]), vec![PatchStep::ControlPlane { drain: false }]);
+  }
+
+  #[test]
+  fn waits_for_a_new_boot_id() {
+    let boot_id = "5f1c3a9e-7b2d-4c1e-9a8f-0d6b2e4c7a11";
+    assert!(!has_rebooted(boot_id, &format!("{}\n", boot_id)));
+    assert!(!has_rebooted(boot_id, ""));
+    assert!(has_rebooted(boot_id, "a3e8d2c4-1f6b-4e9a-8c7d-2b5f0e1a9c33\n"));
+  }
+}
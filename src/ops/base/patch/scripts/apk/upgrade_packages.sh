@@ -0,0 +1,9 @@
+#!/bin/bash
+set -euo pipefail
+
+sudo apk update
+sudo apk upgrade --available
+# A kernel upgrade removes the running kernel's modules
+if [ ! -d "/lib/modules/$(uname -r)" ]; then
+  echo "NYA_REBOOT_REQUIRED"
+fi
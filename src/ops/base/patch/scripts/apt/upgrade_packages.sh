@@ -0,0 +1,12 @@
+#!/bin/bash
+set -euo pipefail
+
+sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 update -y
+sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 -y \
+  -o Dpkg::Options::=--force-confdef \
+  -o Dpkg::Options::=--force-confold \
+  dist-upgrade
+sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 autoremove -y
+if [ -f /var/run/reboot-required ]; then
+  echo "NYA_REBOOT_REQUIRED"
+fi
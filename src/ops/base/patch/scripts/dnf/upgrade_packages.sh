@@ -0,0 +1,7 @@
+#!/bin/bash
+set -euo pipefail
+
+sudo dnf -y upgrade --refresh
+if ! sudo dnf needs-restarting -r > /dev/null 2>&1; then
+  echo "NYA_REBOOT_REQUIRED"
+fi
@@ -0,0 +1,250 @@
+use std::path::Path;
+use colored::*;
+use inquire::Confirm;
+use serde_json::Value;
+
+use crate::{core::{payload::Payload, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::{preview::{delete_namespace_command, fetch_previews, forget_preview}, release::clear_live_tags, retry::RetryPolicy, ship::ship_state_path, types::{BaseNodeConfig, CapsuleDeleteOptions}, utils::{KUBECONFIG, create_ssh_session, get_control_plane_config, get_from_node, stream_from_node}}};
+
+/// Namespaces that hold more than one capsule's releases, or the cluster's
+/// own. Deleting a capsule in one of them leaves the namespace in place.
+const SHARED_NAMESPACES: [&str; 6] = ["default", "kube-system", "kube-public", "kube-node-lease", "ingress-nginx", "metallb-system"];
+
+pub struct NyaCapsule;
+
+impl Service for NyaCapsule {
+  fn name(&self) -> String {"NyaCapsule".to_string()}
+  fn register(&self) -> ServiceActions {
+    vec![
+      (String::from("onDeleteCapsule"), handle_action(delete_capsule)),
+    ]
+  }
+}
+
+/// Turns a name into a valid namespace: lowercase letters, digits and
+/// dashes, at most 63 characters.
+pub(crate) fn to_dns_label(name: &str) -> String {
+  let mut label = String::new();
+  for c in name.to_lowercase().chars() {
+    if c.is_ascii_alphanumeric() {
+      label.push(c);
+    } else if !label.ends_with('-') {
+      label.push('-');
+    }
+  }
+  let label: String = label.trim_matches('-').chars().take(63).collect();
+  match label.trim_end_matches('-') {
+    "" => "nya".to_string(),
+    label => label.to_string(),
+  }
+}
+
+/// The namespace a capsule's packs are deployed to: `namespace` when the
+/// capsule sets one, otherwise its name. Either is made a valid label, since
+/// commands that don't validate the capsule put it into shell commands too.
+pub(crate) fn capsule_namespace(capsule: &Value) -> String {
+  match capsule.get("namespace").and_then(|v| v.as_str()).filter(|ns| !ns.trim().is_empty()) {
+    Some(namespace) => to_dns_label(namespace),
+    None => to_dns_label(capsule.get("name").and_then(|v| v.as_str()).unwrap_or("")),
+  }
+}
+
+/// The namespace of the capsule this run was given, if it was given one.
+pub(crate) async fn namespace(nya: &Nya) -> Option<String> {
+  let capsule = nya.get("capsule").await;
+  if capsule.is_null() {
+    return None;
+  }
+  Some(capsule_namespace(&capsule))
+}
+
+/// Creates the namespace if it's missing and copies the ingress TLS secret
+/// into it, since an Ingress can only use secrets from its own namespace.
+pub(crate) fn prepare_namespace_command(namespace: &str, secret_name: &str) -> String {
+  format!(
+    "kubectl create namespace {0} --dry-run=client -o yaml | kubectl apply -f - {2} && \
+    if kubectl get secret {1} -n ingress-nginx {2} > /dev/null 2>&1; then \
+    kubectl get secret {1} -n ingress-nginx -o yaml {2} \
+    | sed -e '/^  namespace:/d' -e '/^  uid:/d' -e '/^  resourceVersion:/d' -e '/^  creationTimestamp:/d' \
+    | kubectl apply -n {0} -f - {2}; fi",
+    namespace, secret_name, KUBECONFIG,
+  )
+}
+
+/// The packs about to be shipped that still have a release in `default`,
+/// from before capsules got their own namespace. Deploying them again next
+/// to it would claim the same ingress hosts, which ingress-nginx rejects.
+pub(crate) async fn releases_left_in_default(nya: &Nya, host: &str, session: &openssh::Session, packs: &[String]) -> Result<Vec<String>, String> {
+  let output = get_from_node(nya, host, session, &format!("helm list -a -q -n default {}", KUBECONFIG)).await
+    .map_err(|e| format!("could not list the releases in default: {}", e.trim()))?;
+  Ok(releases_in(&output, packs))
+}
+
+fn releases_in(helm_list: &str, packs: &[String]) -> Vec<String> {
+  let releases: Vec<&str> = helm_list.lines().map(str::trim).collect();
+  packs.iter().filter(|pack| releases.contains(&pack.as_str())).cloned().collect()
+}
+
+/// How to move packs out of `default`, or keep them there.
+pub(crate) fn move_out_of_default_message(packs: &[String], namespace: &str, host: &str) -> String {
+  let (them, are) = if packs.len() == 1 { ("it", "is") } else { ("them", "are") };
+  format!(
+    "{packs} {are} still deployed in the default namespace, from before each capsule got its own namespace. \
+    Shipping {them} into {namespace} would claim the same ingress hosts.\n\
+    To move {them}, run `helm uninstall {releases} -n default {kubeconfig}` on the control plane ({host}) and ship again; \
+    {them} {are} unavailable until the ship finishes.\n\
+    To keep {them} in default, set \"namespace\": \"default\" in .nya/nya.json.",
+    packs = packs.join(", "), releases = packs.join(" "), kubeconfig = KUBECONFIG,
+  )
+}
+
+async fn delete_options(nya: &Nya) -> Option<CapsuleDeleteOptions> {
+  match serde_json::from_value(nya.get("capsule_delete_options").await) {
+    Ok(options) => Some(options),
+    Err(e) => {
+      eprintln!("Invalid capsule options: {}", e);
+      None
+    },
+  }
+}
+
+/// Which releases to uninstall. In a shared namespace only the capsule's own
+/// packs are removed, since the rest may belong to something else.
+fn releases_to_delete(installed: &str, packs: &[String], shared: bool) -> Vec<String> {
+  installed
+    .lines()
+    .map(|line| line.trim().to_string())
+    .filter(|release| !release.is_empty() && (!shared || packs.contains(release)))
+    .collect()
+}
+
+async fn delete_capsule(nya: Nya, _: Payload) {
+  let Some(options) = delete_options(&nya).await else {
+    return;
+  };
+  let capsule = nya.get("capsule").await;
+  let name = capsule.get("name").and_then(|v| v.as_str()).unwrap_or("").to_string();
+  let namespace = capsule_namespace(&capsule);
+  let shared = SHARED_NAMESPACES.contains(&namespace.as_str());
+  let packs: Vec<String> = capsule.get("packs").and_then(|p| p.as_array()).cloned().unwrap_or_default()
+    .iter()
+    .filter_map(|pack| pack.get("name").and_then(|v| v.as_str()).map(String::from))
+    .collect();
+
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let host = control_plane_config.host.clone();
+  let Some(session) = create_ssh_session(&nya, &control_plane_config).await else {
+    return;
+  };
+  let installed = match get_from_node(&nya, &host, &session, &format!("helm list -n {} -q {}", namespace, KUBECONFIG)).await {
+    Ok(output) => output,
+    Err(e) => {
+      eprintln!("{}", format!("Could not list the releases in {}: {}", namespace, e.trim()).red());
+      let _ = session.close().await;
+      return;
+    },
+  };
+  let releases = releases_to_delete(&installed, &packs, shared);
+  let previews = match fetch_previews(&nya, &host, &session, &namespace).await {
+    Ok(previews) => previews,
+    Err(err) => {
+      eprintln!("{}", err.red());
+      let _ = session.close().await;
+      return;
+    },
+  };
+
+  if releases.is_empty() && shared && previews.is_empty() {
+    println!("{}", format!("Capsule {} has nothing deployed in {}.", name, namespace).yellow());
+    let _ = session.close().await;
+    return;
+  }
+  println!("The following will be removed from {}:", host);
+  for release in releases.iter() {
+    println!("  release {} in {}", release, namespace);
+  }
+  if !shared {
+    println!("  namespace {} and everything in it", namespace);
+  }
+  for preview in previews.iter() {
+    println!("  preview {} of branch {}", preview.namespace, preview.branch);
+  }
+
+  if !options.yes {
+    let confirmed = Confirm::new("Do you want to continue?")
+      .with_default(false)
+      .prompt();
+    if !matches!(confirmed, Ok(true)) {
+      println!("{}", "Delete cancelled".yellow());
+      let _ = session.close().await;
+      return;
+    }
+  }
+
+  let mut commands: Vec<String> = Vec::new();
+  if !releases.is_empty() {
+    commands.push(format!("helm uninstall {} -n {} --wait {}", releases.join(" "), namespace, KUBECONFIG));
+  }
+  if !shared {
+    commands.push(format!("kubectl delete namespace {} --ignore-not-found --timeout=300s {}", namespace, KUBECONFIG));
+  }
+  for preview in previews.iter() {
+    commands.push(delete_namespace_command(&preview.namespace));
+  }
+  let result = stream_from_node(&nya, &host, &session, &commands.join(" && "), &RetryPolicy::COMMAND).await;
+  let _ = session.close().await;
+  if let Err(e) = result {
+    eprintln!("{}", format!("Failed to delete capsule {}: {}", name, e).red());
+    nya.stop();
+    return;
+  }
+
+  if let Some(capsule_path) = nya.capsule_path() {
+    forget_deployment(capsule_path);
+    for preview in previews.iter() {
+      forget_preview(capsule_path, &preview.namespace);
+    }
+  }
+  println!("{}", format!("Deleted capsule {} from {}.", name, namespace).green());
+}
+
+/// The capsule isn't live anywhere anymore, so its next ship starts fresh.
+fn forget_deployment(capsule_path: &Path) {
+  let state_path = ship_state_path(capsule_path);
+  if state_path.exists()
+    && let Err(e) = std::fs::remove_file(&state_path) {
+    eprintln!("Failed to remove {}: {}", state_path.display(), e);
+  }
+  if let Err(e) = clear_live_tags(capsule_path) {
+    eprintln!("Failed to clear the live image tags in {}: {}", capsule_path.display(), e);
+  }
+}
+
+#[cfg(test)]
+mod capsule_tests {
+  use serde_json::json;
+  use crate::ops::capsule::{capsule_namespace, move_out_of_default_message, releases_in, releases_to_delete, to_dns_label};
+
+  #[test]
+  fn derives_the_namespace_from_the_capsule() {
+    assert_eq!(capsule_namespace(&json!({ "name": "My Shop_v2" })), "my-shop-v2");
+    assert_eq!(capsule_namespace(&json!({ "name": "shop", "namespace": "team-a" })), "team-a");
+    assert_eq!(capsule_namespace(&json!({ "name": "shop", "namespace": "team a; rm -rf /" })), "team-a-rm-rf");
+    assert_eq!(to_dns_label("--"), "nya");
+    assert_eq!(to_dns_label(&"a".repeat(70)).len(), 63);
+  }
+
+  #[test]
+  fn only_deletes_the_capsules_packs_from_a_shared_namespace() {
+    let packs = vec!["api".to_string(), "web".to_string()];
+    assert_eq!(releases_to_delete("api\nother\nweb\n", &packs, true), ["api", "web"]);
+    assert_eq!(releases_to_delete("api\nother\n", &packs, false), ["api", "other"]);
+  }
+
+  #[test]
+  fn finds_the_packs_still_released_in_default() {
+    let packs = vec!["api".to_string(), "web".to_string()];
+    assert_eq!(releases_in("api\nother-api\n", &packs), ["api"]);
+    assert!(releases_in("", &packs).is_empty());
+    assert!(move_out_of_default_message(&packs, "shop", "10.0.0.1").contains("run `helm uninstall api web -n default --kubeconfig"));
+  }
+}
@@ -0,0 +1,342 @@
+use colored::*;
+use futures::future::join_all;
+use openssh::Session;
+use serde::{Deserialize, Serialize};
+
+use crate::{core::{log::host_prefix, payload::Payload, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::{retry::{RetryPolicy, needs_package_repair}, types::{BaseNodeConfig, NodeCommandResult}, utils::{get_base_nodes, is_offline, run_on_node_with, try_create_ssh_session}}};
+
+/// The package managers Nya knows how to drive.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageManager {
+  Apt,
+  Dnf,
+  Apk,
+}
+
+/// Components whose package and service names differ between distros.
+#[derive(Clone, Copy)]
+pub enum Component {
+  Bind9,
+  Iptables,
+  Wireguard,
+}
+
+/// What a node reports in `/etc/os-release`, plus the package manager picked from it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OsFacts {
+  pub id: String,
+  pub id_like: Vec<String>,
+  pub version_id: String,
+  pub pretty_name: String,
+  pub package_manager: Option<PackageManager>,
+}
+
+pub struct NyaDistro;
+
+impl Service for NyaDistro {
+  fn name(&self) -> String {"NyaDistro".to_string()}
+  fn register(&self) -> ServiceActions {
+    vec![
+      (String::from("onDetectDistro"), handle_action(detect_distros)),
+    ]
+  }
+}
+
+impl OsFacts {
+  pub fn parse(os_release: &str) -> Self {
+    let mut facts = OsFacts {
+      id: String::new(),
+      id_like: Vec::new(),
+      version_id: String::new(),
+      pretty_name: String::new(),
+      package_manager: None,
+    };
+    for line in os_release.lines() {
+      let Some((key, value)) = line.split_once('=') else {
+        continue;
+      };
+      let value = value.trim().trim_matches('"').trim_matches('\'').to_string();
+      match key.trim() {
+        "ID" => facts.id = value.to_lowercase(),
+        "ID_LIKE" => facts.id_like = value.to_lowercase().split_whitespace().map(|s| s.to_string()).collect(),
+        "VERSION_ID" => facts.version_id = value,
+        "PRETTY_NAME" => facts.pretty_name = value,
+        _ => {},
+      }
+    }
+
+    let family: Vec<&str> = std::iter::once(facts.id.as_str())
+      .chain(facts.id_like.iter().map(|s| s.as_str()))
+      .collect();
+    facts.package_manager = if family.iter().any(|f| ["debian", "ubuntu"].contains(f)) {
+      Some(PackageManager::Apt)
+    } else if family.iter().any(|f| ["fedora", "rhel", "centos", "rocky", "almalinux"].contains(f)) {
+      Some(PackageManager::Dnf)
+    } else if family.contains(&"alpine") {
+      Some(PackageManager::Apk)
+    } else {
+      None
+    };
+    facts
+  }
+
+  pub fn name(&self) -> String {
+    if self.pretty_name.is_empty() { self.id.clone() } else { self.pretty_name.clone() }
+  }
+}
+
+impl PackageManager {
+  pub fn name(&self) -> &'static str {
+    match self {
+      PackageManager::Apt => "apt",
+      PackageManager::Dnf => "dnf",
+      PackageManager::Apk => "apk",
+    }
+  }
+
+  /// Finishes an interrupted install. apt refuses to do anything else until it has run.
+  pub fn repair(&self) -> Option<&'static str> {
+    match self {
+      PackageManager::Apt => Some("sudo DEBIAN_FRONTEND=noninteractive dpkg --configure -a && sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 install -f -y"),
+      PackageManager::Dnf | PackageManager::Apk => None,
+    }
+  }
+
+  pub fn install(&self, packages: &[&str]) -> String {
+    let packages = packages.join(" ");
+    match self {
+      PackageManager::Apt => format!("sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 update -y && sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 install -y {}", packages),
+      PackageManager::Dnf => format!("sudo dnf install -y {}", packages),
+      PackageManager::Apk => format!("sudo apk add --no-cache {}", packages),
+    }
+  }
+
+  /// The extension of the package files this manager installs.
+  pub fn package_ext(&self) -> &'static str {
+    match self {
+      PackageManager::Apt => "deb",
+      PackageManager::Dnf => "rpm",
+      PackageManager::Apk => "apk",
+    }
+  }
+
+  /// Installs the package files in a directory without reaching any repo.
+  pub fn install_local(&self, dir: &str) -> String {
+    match self {
+      PackageManager::Apt => format!("sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 install -y --no-download {}/*.deb", dir),
+      PackageManager::Dnf => format!("sudo dnf install -y --disablerepo='*' {}/*.rpm", dir),
+      PackageManager::Apk => format!("sudo apk add --no-network --allow-untrusted {}/*.apk", dir),
+    }
+  }
+
+  /// Downloads packages and their missing dependencies into a directory,
+  /// run inside a container of the target distro when creating a bundle.
+  pub fn download(&self, packages: &[&str], dir: &str) -> String {
+    let packages = packages.join(" ");
+    match self {
+      PackageManager::Apt => format!("apt-get update && apt-get install -y --download-only --no-install-recommends {} && cp /var/cache/apt/archives/*.deb {}/", packages, dir),
+      PackageManager::Dnf => format!("dnf install -y --downloadonly --downloaddir={} {}", dir, packages),
+      PackageManager::Apk => format!("apk fetch --recursive --output {} {}", dir, packages),
+    }
+  }
+
+  pub fn remove(&self, packages: &[&str]) -> String {
+    let packages = packages.join(" ");
+    match self {
+      PackageManager::Apt => format!("sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 remove --purge -y {} && sudo DEBIAN_FRONTEND=noninteractive apt-get -o DPkg::Lock::Timeout=300 autoremove -y", packages),
+      PackageManager::Dnf => format!("sudo dnf remove -y {}", packages),
+      PackageManager::Apk => format!("sudo apk del {}", packages),
+    }
+  }
+
+  pub fn packages(&self, component: Component) -> &'static [&'static str] {
+    match (component, self) {
+      (Component::Bind9, PackageManager::Apt) => &["bind9", "bind9utils"],
+      (Component::Bind9, PackageManager::Dnf) => &["bind", "bind-utils"],
+      (Component::Bind9, PackageManager::Apk) => &["bind", "bind-tools"],
+      (Component::Iptables, _) => &["iptables"],
+      (Component::Wireguard, PackageManager::Apk) => &["wireguard-tools", "wireguard-tools-openrc"],
+      (Component::Wireguard, _) => &["wireguard-tools"],
+    }
+  }
+
+  pub fn service(&self, component: Component) -> &'static str {
+    match (component, self) {
+      (Component::Bind9, PackageManager::Apt) => "bind9",
+      (Component::Bind9, _) => "named",
+      (Component::Iptables, _) => "iptables",
+      (Component::Wireguard, PackageManager::Apk) => "wg-quick.wg0",
+      (Component::Wireguard, _) => "wg-quick@wg0",
+    }
+  }
+
+  /// The user bind runs as, which has to own its working directory.
+  pub fn bind_user(&self) -> &'static str {
+    match self {
+      PackageManager::Apt => "bind",
+      PackageManager::Dnf | PackageManager::Apk => "named",
+    }
+  }
+
+  /// Debian's `named.conf` already includes our files from `/etc/bind`;
+  /// elsewhere the main config is replaced with one that does.
+  pub fn bind_main_conf(&self) -> Option<&'static str> {
+    match self {
+      PackageManager::Apt => None,
+      PackageManager::Dnf => Some("/etc/named.conf"),
+      PackageManager::Apk => Some("/etc/bind/named.conf"),
+    }
+  }
+
+  /// Alpine runs OpenRC, everything else here runs systemd.
+  pub fn enable_service(&self, service: &str) -> String {
+    match self {
+      PackageManager::Apk => format!("sudo rc-update add {0} default && sudo rc-service {0} start", service),
+      _ => format!("sudo systemctl enable --now {}", service),
+    }
+  }
+
+  pub fn restart_service(&self, service: &str) -> String {
+    match self {
+      PackageManager::Apk => format!("sudo rc-service {} restart", service),
+      _ => format!("sudo systemctl restart {}", service),
+    }
+  }
+
+  pub fn disable_service(&self, service: &str) -> String {
+    match self {
+      PackageManager::Apk => format!("sudo rc-service {0} stop; sudo rc-update del {0} default", service),
+      _ => format!("sudo systemctl disable --now {}", service),
+    }
+  }
+
+  pub fn stop_service(&self, service: &str) -> String {
+    match self {
+      PackageManager::Apk => format!("sudo rc-service {} stop", service),
+      _ => format!("sudo systemctl stop {}", service),
+    }
+  }
+}
+
+/// Reads `/etc/os-release` from a node. Runs without bash, which minimal
+/// Alpine installs don't have.
+pub async fn detect_os(session: &Session) -> Result<OsFacts, String> {
+  let output = session.command("cat").arg("/etc/os-release").output().await.map_err(|e| e.to_string())?;
+  if !output.status.success() {
+    return Err(String::from_utf8_lossy(&output.stderr).to_string());
+  }
+  Ok(OsFacts::parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Every remote command runs through `bash -c` with `sudo`.
+async fn missing_tools(session: &Session) -> Vec<&'static str> {
+  let mut missing = Vec::new();
+  for tool in ["bash", "sudo"] {
+    let found = session.command("sh").arg("-c").arg(format!("command -v {} > /dev/null 2>&1", tool)).status().await;
+    if !found.is_ok_and(|status| status.success()) {
+      missing.push(tool);
+    }
+  }
+  missing
+}
+
+/// The package manager for a node, from the preflight facts when they were
+/// gathered for this run and from the node itself otherwise.
+pub async fn package_manager(nya: &Nya, host: &str, session: &Session) -> Result<PackageManager, String> {
+  let facts: OsFacts = match serde_json::from_value(nya.get(&format!("distro.{}", host)).await) {
+    Ok(facts) => facts,
+    Err(_) => {
+      let facts = detect_os(session).await?;
+      nya.set(&format!("distro.{}", host), &facts).await;
+      facts
+    },
+  };
+  facts.package_manager.ok_or_else(|| format!("{} is not a supported distro", facts.name()))
+}
+
+/// Runs an install or removal with the install retry policy. apt waits for the
+/// dpkg lock on its own, and an earlier interrupted install is repaired once
+/// before the command is tried again.
+pub async fn run_package_command(nya: &Nya, host: &str, session: &Session, package_manager: PackageManager, command: &str) -> NodeCommandResult {
+  let result = run_on_node_with(nya, host, session, command, &RetryPolicy::INSTALL).await;
+  match (result, package_manager.repair()) {
+    (NodeCommandResult::Failure(err), Some(repair)) if needs_package_repair(&err) => {
+      println!("{} Repairing an interrupted {} install", host_prefix(host), package_manager.name());
+      if let NodeCommandResult::Failure(err) = run_on_node_with(nya, host, session, repair, &RetryPolicy::INSTALL).await {
+        return NodeCommandResult::Failure(err);
+      }
+      run_on_node_with(nya, host, session, command, &RetryPolicy::INSTALL).await
+    },
+    (result, _) => result,
+  }
+}
+
+/// Preflight: every node must run a distro we have a package manager for,
+/// checked before anything is installed.
+async fn detect_distros(nya: Nya, _: Payload) {
+  let offline = is_offline(nya.clone()).await;
+  let node_configs: Vec<BaseNodeConfig> = get_base_nodes(nya.clone()).await;
+  let detections = node_configs.iter().map(|node| async move {
+    let session = try_create_ssh_session(node).await
+      .map_err(|e| format!("{}: could not connect: {}", node.host, e))?;
+    let facts = detect_os(&session).await;
+    let missing = missing_tools(&session).await;
+    let _ = session.close().await;
+    facts
+      .map(|facts| (facts, missing))
+      .map_err(|e| format!("{}: could not read /etc/os-release: {}", node.host, e.trim()))
+  });
+  let results = join_all(detections).await;
+
+  let mut errors: Vec<String> = Vec::new();
+  for (node, result) in node_configs.iter().zip(results) {
+    match result {
+      Ok((facts, missing)) => {
+        if !missing.is_empty() {
+          errors.push(format!("{}: {} must be installed", node.host, missing.join(" and ")));
+        }
+        match facts.package_manager {
+          None => errors.push(format!("{}: {} is not supported (supported: Debian, Ubuntu, Fedora, Rocky, Alma, Alpine)", node.host, facts.name())),
+          Some(PackageManager::Apk) if offline => errors.push(format!("{}: offline builds need systemd, which {} doesn't use", node.host, facts.name())),
+          Some(package_manager) => println!("{} runs {} ({})", node.host, facts.name(), package_manager.name()),
+        }
+        nya.set(&format!("distro.{}", node.host), &facts).await;
+      },
+      Err(err) => errors.push(err),
+    }
+  }
+
+  if errors.is_empty() {
+    return;
+  }
+  eprintln!("{}", format!("Preflight found {} problem(s) with the nodes:", errors.len()).red());
+  for error in errors.iter() {
+    eprintln!("  - {}", error);
+  }
+  eprintln!("No nodes were changed.");
+  nya.stop();
+}
+
+#[cfg(test)]
+mod distro_tests {
+  use crate::ops::distro::{OsFacts, PackageManager};
+
+  #[test]
+  fn picks_package_manager_from_os_release() {
+    let ubuntu = OsFacts::parse("NAME=\"Ubuntu\"\nID=ubuntu\nID_LIKE=debian\nVERSION_ID=\"24.04\"\nPRETTY_NAME=\"Ubuntu 24.04.1 LTS\"\n");
+    assert_eq!(ubuntu.package_manager, Some(PackageManager::Apt));
+    assert_eq!(ubuntu.version_id, "24.04");
+
+    let rocky = OsFacts::parse("ID=\"rocky\"\nID_LIKE=\"rhel centos fedora\"\nVERSION_ID=\"9.4\"\n");
+    assert_eq!(rocky.package_manager, Some(PackageManager::Dnf));
+    assert_eq!(rocky.id_like, vec!["rhel", "centos", "fedora"]);
+
+    let alpine = OsFacts::parse("ID=alpine\nVERSION_ID=3.20.3\nPRETTY_NAME=\"Alpine Linux v3.20\"\n");
+    assert_eq!(alpine.package_manager, Some(PackageManager::Apk));
+
+    let arch = OsFacts::parse("ID=arch\nPRETTY_NAME=\"Arch Linux\"\n");
+    assert_eq!(arch.package_manager, None);
+    assert_eq!(arch.name(), "Arch Linux");
+  }
+}
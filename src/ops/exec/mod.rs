@@ -0,0 +1,238 @@
+use std::{fs, path::{Path, PathBuf}, time::Instant};
+use colored::*;
+use futures::{StreamExt, stream};
+use openssh::Stdio;
+use tokio::io::AsyncWriteExt;
+
+use crate::{core::{log::{HostLine, Stream, append_host_line, host_prefix}, payload::Payload, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::{retry::RetryPolicy, types::{BaseNodeConfig, ExecOptions}, utils::{get_control_plane_config, get_node_configs, try_create_ssh_session}}};
+
+/// The roles a node can be selected by, matching the ones used for cloud-init.
+pub const EXEC_ROLES: [&str; 2] = ["control_plane", "node"];
+
+pub struct NyaExec;
+
+impl Service for NyaExec {
+  fn name(&self) -> String {"NyaExec".to_string()}
+  fn register(&self) -> ServiceActions {
+    vec![
+      (String::from("onExec"), handle_action(exec_action)),
+    ]
+  }
+}
+
+/// What one node returned.
+struct ExecResult {
+  host: String,
+  exit_code: Option<i32>,
+  stdout: String,
+  stderr: String,
+  elapsed: f32,
+}
+
+async fn exec_action(nya: Nya, _: Payload) {
+  let options: ExecOptions = match serde_json::from_value(nya.get("exec_options").await) {
+    Ok(options) => options,
+    Err(e) => {
+      eprintln!("Invalid exec options: {}", e);
+      return;
+    },
+  };
+
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let node_configs: Vec<BaseNodeConfig> = get_node_configs(nya.clone()).await;
+  let targets = match select_nodes(control_plane_config, node_configs, &options) {
+    Ok(targets) => targets,
+    Err(err) => {
+      eprintln!("{}", err.red());
+      return;
+    },
+  };
+
+  let script: Option<String> = match &options.script {
+    Some(script) => match read_script(script) {
+      Ok(content) => Some(content),
+      Err(err) => {
+        eprintln!("{}", err.red());
+        return;
+      },
+    },
+    None => None,
+  };
+  let command: Vec<String> = remote_command(&options, script.is_some());
+
+  let concurrency = options.concurrency.max(1);
+  let results: Vec<ExecResult> = stream::iter(targets)
+    .map(|node| {
+      let (nya, command, script) = (nya.clone(), command.clone(), script.clone());
+      async move { exec_on(&nya, &node, &command, script.as_deref()).await }
+    })
+    .buffer_unordered(concurrency)
+    .inspect(print_result)
+    .collect()
+    .await;
+
+  let failed: Vec<&ExecResult> = results.iter().filter(|result| result.exit_code != Some(0)).collect();
+  if failed.is_empty() {
+    println!("{}", format!("Succeeded on {} of {} node(s).", results.len(), results.len()).green());
+  } else {
+    let hosts: Vec<&str> = failed.iter().map(|result| result.host.as_str()).collect();
+    println!("{}", format!("Failed on {} of {} node(s): {}", failed.len(), results.len(), hosts.join(", ")).red());
+  }
+}
+
+/// Picks the nodes for `--node`, `--role` or `--all`.
+fn select_nodes(control_plane: BaseNodeConfig, nodes: Vec<BaseNodeConfig>, options: &ExecOptions) -> Result<Vec<BaseNodeConfig>, String> {
+  let all: Vec<(BaseNodeConfig, &str)> = std::iter::once((control_plane, "control_plane"))
+    .chain(nodes.into_iter().map(|node| (node, "node")))
+    .collect();
+
+  let selected: Vec<BaseNodeConfig> = if let Some(host) = &options.node {
+    all.into_iter().filter(|(node, _)| node.host == *host).map(|(node, _)| node).collect()
+  } else if let Some(role) = &options.role {
+    if !EXEC_ROLES.contains(&role.as_str()) {
+      return Err(format!("Unknown role {}. Roles are: {}", role, EXEC_ROLES.join(", ")));
+    }
+    all.into_iter().filter(|(_, node_role)| node_role == role).map(|(node, _)| node).collect()
+  } else {
+    all.into_iter().map(|(node, _)| node).collect()
+  };
+
+  if selected.is_empty() {
+    return Err(match &options.node {
+      Some(host) => format!("No node with host {} found in the base config.", host),
+      None => "No nodes matched.".to_string(),
+    });
+  }
+  Ok(selected)
+}
+
+/// A script is piped to `bash -s`, with the trailing arguments passed to it.
+fn remote_command(options: &ExecOptions, has_script: bool) -> Vec<String> {
+  let mut command: Vec<String> = Vec::new();
+  if options.sudo {
+    command.push("sudo".to_string());
+  }
+  command.push("bash".to_string());
+  if has_script {
+    command.push("-s".to_string());
+    command.push("--".to_string());
+    command.extend(options.command.iter().cloned());
+  } else {
+    command.push("-c".to_string());
+    command.push(options.command.join(" "));
+  }
+  command
+}
+
+/// Reads a script by path, or by name from `~/.nya/scripts/`.
+fn read_script(script: &Path) -> Result<String, String> {
+  let candidates: Vec<PathBuf> = if script.exists() {
+    vec![script.to_path_buf()]
+  } else {
+    let scripts_dir = dirs::home_dir().unwrap_or_default().join(".nya").join("scripts");
+    vec![scripts_dir.join(script), scripts_dir.join(script).with_extension("sh")]
+  };
+  candidates
+    .iter()
+    .find(|path| path.is_file())
+    .ok_or_else(|| format!("No script found at {} or in ~/.nya/scripts/", script.display()))
+    .and_then(|path| fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e)))
+}
+
+async fn exec_on(nya: &Nya, node: &BaseNodeConfig, command: &[String], script: Option<&str>) -> ExecResult {
+  let started = Instant::now();
+  let mut result = ExecResult { host: node.host.clone(), exit_code: None, stdout: String::new(), stderr: String::new(), elapsed: 0.0 };
+
+  match try_create_ssh_session(node).await {
+    Ok(session) => {
+      let run = async {
+        let mut child = session.command(&command[0])
+          .args(&command[1..])
+          .stdin(if script.is_some() { Stdio::piped() } else { Stdio::null() })
+          .stdout(Stdio::piped())
+          .stderr(Stdio::piped())
+          .spawn()
+          .await
+          .map_err(|e| e.to_string())?;
+        if let Some(script) = script
+          && let Some(mut stdin) = child.stdin().take() {
+          stdin.write_all(script.as_bytes()).await.map_err(|e| e.to_string())?;
+          stdin.shutdown().await.map_err(|e| e.to_string())?;
+        }
+        child.wait_with_output().await.map_err(|e| e.to_string())
+      };
+      match tokio::time::timeout(RetryPolicy::COMMAND.timeout, run).await {
+        Ok(Ok(output)) => {
+          result.exit_code = output.status.code();
+          result.stdout = String::from_utf8_lossy(&output.stdout).to_string();
+          result.stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        },
+        Ok(Err(err)) => result.stderr = err,
+        Err(_) => result.stderr = format!("timed out after {}s", RetryPolicy::COMMAND.timeout.as_secs()),
+      }
+      let _ = session.close().await;
+    },
+    Err(err) => result.stderr = format!("could not connect: {}", err),
+  }
+
+  for (stream, output) in [(Stream::Stdout, &result.stdout), (Stream::Stderr, &result.stderr)] {
+    for line in output.lines() {
+      append_host_line(nya, &HostLine { host: result.host.clone(), line: line.to_string(), stream });
+    }
+  }
+  result.elapsed = started.elapsed().as_secs_f32();
+  result
+}
+
+fn print_result(result: &ExecResult) {
+  let status = match result.exit_code {
+    Some(0) => "exit 0".green(),
+    Some(code) => format!("exit {}", code).red(),
+    None => "failed".red(),
+  };
+  println!("{} {} ({:.1}s)", host_prefix(&result.host), status, result.elapsed);
+  for line in result.stdout.lines() {
+    println!("  {}", line);
+  }
+  for line in result.stderr.lines() {
+    eprintln!("  {}", line.dimmed());
+  }
+}
+
+#[cfg(test)]
+mod exec_tests {
+  use serde_json::json;
+  use crate::ops::{exec::{remote_command, select_nodes}, types::{BaseNodeConfig, ExecOptions}};
+
+  fn node(host: &str) -> BaseNodeConfig {
+    BaseNodeConfig::new(json!({ "host": host, "user": "nya", "ssh_private_key_file": "~/.ssh/id_ed25519" }))
+  }
+
+  #[test]
+  fn selects_nodes_by_host_role_or_all() {
+    let nodes = || vec![node("10.0.0.2"), node("10.0.0.3")];
+    let hosts = |selected: Vec<BaseNodeConfig>| selected.into_iter().map(|n| n.host).collect::<Vec<_>>();
+
+    let all = ExecOptions { all: true, ..Default::default() };
+    assert_eq!(hosts(select_nodes(node("10.0.0.1"), nodes(), &all).unwrap()), vec!["10.0.0.1", "10.0.0.2", "10.0.0.3"]);
+
+    let workers = ExecOptions { role: Some("node".to_string()), ..Default::default() };
+    assert_eq!(hosts(select_nodes(node("10.0.0.1"), nodes(), &workers).unwrap()), vec!["10.0.0.2", "10.0.0.3"]);
+
+    let one = ExecOptions { node: Some("10.0.0.3".to_string()), ..Default::default() };
+    assert_eq!(hosts(select_nodes(node("10.0.0.1"), nodes(), &one).unwrap()), vec!["10.0.0.3"]);
+
+    let missing = ExecOptions { node: Some("10.0.0.9".to_string()), ..Default::default() };
+    assert!(select_nodes(node("10.0.0.1"), nodes(), &missing).is_err());
+    let bad_role = ExecOptions { role: Some("db".to_string()), ..Default::default() };
+    assert!(select_nodes(node("10.0.0.1"), nodes(), &bad_role).is_err());
+  }
+
+  #[test]
+  fn wraps_commands_and_scripts_for_bash() {
+    let options = ExecOptions { command: vec!["df".to_string(), "-h".to_string()], sudo: true, ..Default::default() };
+    assert_eq!(remote_command(&options, false), vec!["sudo", "bash", "-c", "df -h"]);
+    let options = ExecOptions { command: vec!["--verbose".to_string()], ..Default::default() };
+    assert_eq!(remote_command(&options, true), vec!["bash", "-s", "--", "--verbose"]);
+  }
+}
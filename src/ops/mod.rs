@@ -1,4 +1,12 @@
 pub mod base;
+pub mod capsule;
+pub(crate) mod distro;
+pub mod exec;
+pub mod preview;
+pub mod release;
+pub(crate) mod retry;
 pub mod ship;
 pub(crate) mod types;
-pub(crate) mod utils;
\ No newline at end of file
+pub(crate) mod upload;
+pub(crate) mod utils;
+pub(crate) mod validate;
@@ -0,0 +1,429 @@
+use std::{collections::HashSet, path::{Path, PathBuf}};
+use colored::*;
+use inquire::Confirm;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{core::{payload::Payload, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::{capsule::{capsule_namespace, to_dns_label}, retry::RetryPolicy, ship::preview_state_path, types::{BaseNodeConfig, PreviewOptions}, upload::sha256_hex, utils::{KUBECONFIG, create_ssh_session, get_control_plane_config, get_from_node, stream_from_node}}};
+
+const CAPSULE_LABEL: &str = "nya.dev/capsule";
+const PREVIEW_LABEL: &str = "nya.dev/preview";
+/// An annotation, since branch names can hold characters labels can't.
+const BRANCH_ANNOTATION: &str = "nya.dev/branch";
+/// The longest a namespace or an ingress host label can be.
+const MAX_LABEL_LEN: usize = 63;
+/// How much of the branch's hash a shortened name ends with.
+const BRANCH_HASH_LEN: usize = 6;
+
+pub struct NyaPreview;
+
+impl Service for NyaPreview {
+  fn name(&self) -> String {"NyaPreview".to_string()}
+  fn register(&self) -> ServiceActions {
+    vec![
+      (String::from("onListPreviews"), handle_action(list_previews)),
+      (String::from("onPrunePreviews"), handle_action(prune_previews)),
+    ]
+  }
+}
+
+/// Where `nya ship --preview` deploys a branch.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct Preview {
+  pub branch: String,
+  pub namespace: String,
+}
+
+impl Preview {
+  /// The namespace is `<capsule>--<branch>`. A capsule's namespace never has
+  /// two dashes in a row, so no preview can take another capsule's.
+  pub fn new(capsule_namespace: &str, branch: &str) -> Result<Self, String> {
+    let room = MAX_LABEL_LEN.saturating_sub(capsule_namespace.len() + 2);
+    let branch_label = shorten(&to_dns_label(branch), room, branch)
+      .ok_or_else(|| format!("the namespace {} leaves no room for the branch in a preview's namespace, set a shorter `namespace` in .nya/nya.json", capsule_namespace))?;
+    Ok(Preview {
+      branch: branch.to_string(),
+      namespace: format!("{}--{}", capsule_namespace, branch_label),
+    })
+  }
+
+  /// Appended to every ingress host, e.g. `api-feature-x.<domain>`, and
+  /// shortened so the longest of the pack's host labels stays a valid label.
+  pub fn host_suffix(&self, hosts: &[String]) -> Result<String, String> {
+    let Some(longest) = hosts.iter().max_by_key(|host| host.len()) else {
+      return Ok(format!("-{}", to_dns_label(&self.branch)));
+    };
+    let room = MAX_LABEL_LEN.saturating_sub(longest.len() + 1);
+    shorten(&to_dns_label(&self.branch), room, &self.branch)
+      .map(|label| format!("-{}", label))
+      .ok_or_else(|| format!("the ingress host {} leaves no room for the branch, shorten it to preview the pack", longest))
+  }
+
+  /// Labels the namespace as a preview of the capsule, so list and prune can find it.
+  pub fn label_command(&self, capsule_namespace: &str) -> String {
+    format!(
+      "kubectl label namespace {0} {1}={2} {3}=true --overwrite {5} && \
+      kubectl annotate namespace {0} {4}='{6}' --overwrite {5}",
+      self.namespace, CAPSULE_LABEL, capsule_namespace, PREVIEW_LABEL, BRANCH_ANNOTATION, KUBECONFIG,
+      self.branch.replace('\'', "'\\''"),
+    )
+  }
+}
+
+/// Cuts a label down to `max` characters. A cut label ends with a hash of
+/// the branch, so two long branches that start alike still get two names.
+fn shorten(label: &str, max: usize, branch: &str) -> Option<String> {
+  if label.len() <= max {
+    return Some(label.to_string());
+  }
+  if max < BRANCH_HASH_LEN {
+    return None;
+  }
+  let hash = &sha256_hex(branch.as_bytes())[..BRANCH_HASH_LEN];
+  let kept: String = label.chars().take(max.saturating_sub(BRANCH_HASH_LEN + 1)).collect();
+  match kept.trim_end_matches('-') {
+    "" => Some(hash.to_string()),
+    kept => Some(format!("{}-{}", kept, hash)),
+  }
+}
+
+/// Fails when the preview's namespace exists but isn't this branch's
+/// preview of the capsule, since shipping would relabel it as one and
+/// prune or capsule delete would later delete it.
+pub(crate) async fn check_preview_namespace(nya: &Nya, host: &str, session: &openssh::Session, preview: &Preview, capsule_namespace: &str) -> Result<(), String> {
+  let command = format!("kubectl get namespace {} -o json --ignore-not-found {}", preview.namespace, KUBECONFIG);
+  let output = get_from_node(nya, host, session, &command).await
+    .map_err(|e| format!("could not look up the namespace {}: {}", preview.namespace, e.trim()))?;
+  match namespace_conflict(&output, preview, capsule_namespace) {
+    Some(conflict) => Err(conflict),
+    None => Ok(()),
+  }
+}
+
+fn namespace_conflict(output: &str, preview: &Preview, capsule_namespace: &str) -> Option<String> {
+  if output.trim().is_empty() {
+    return None;
+  }
+  let namespace: Value = serde_json::from_str(output.trim()).unwrap_or_default();
+  let metadata = |kind: &str, key: &str| namespace.pointer(&format!("/metadata/{}/{}", kind, key.replace('/', "~1"))).and_then(|v| v.as_str()).map(String::from);
+  if metadata("labels", CAPSULE_LABEL).as_deref() != Some(capsule_namespace) || metadata("labels", PREVIEW_LABEL).as_deref() != Some("true") {
+    return Some(format!("The namespace {} already exists and isn't a preview of {}, so the preview of {} can't use it.", preview.namespace, capsule_namespace, preview.branch));
+  }
+  match metadata("annotations", BRANCH_ANNOTATION) {
+    Some(branch) if branch != preview.branch => Some(format!(
+      "{} already holds the preview of {}, which gets the same name as {}. Delete that preview with `nya preview prune` or rename one of the branches.",
+      preview.namespace, branch, preview.branch,
+    )),
+    _ => None,
+  }
+}
+
+/// The branch checked out in the capsule's directory.
+pub(crate) fn current_branch(dir: &Path) -> Result<String, String> {
+  let output = std::process::Command::new("git")
+    .args(["rev-parse", "--abbrev-ref", "HEAD"])
+    .current_dir(dir)
+    .output()
+    .map_err(|e| format!("could not run git: {}", e))?;
+  let branch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+  match (output.status.success(), branch.as_str()) {
+    (false, _) => Err(format!("{} is not in a git repository, previews are named after the branch", dir.display())),
+    (true, "HEAD") => Err("HEAD is detached, check out a branch to ship a preview".to_string()),
+    (true, _) => Ok(branch),
+  }
+}
+
+/// Brings the remote-tracking branches up to date, dropping the ones
+/// deleted upstream, so stale refs don't keep their previews alive.
+fn fetch_branches(dir: &Path) -> Result<(), String> {
+  let output = std::process::Command::new("git")
+    .args(["fetch", "--all", "--prune", "--quiet"])
+    .env("GIT_TERMINAL_PROMPT", "0")
+    .current_dir(dir)
+    .output()
+    .map_err(|e| format!("could not run git: {}", e))?;
+  match output.status.success() {
+    true => Ok(()),
+    false => Err(format!("could not fetch the branches in {}: {}", dir.display(), String::from_utf8_lossy(&output.stderr).trim())),
+  }
+}
+
+/// The branches of every remote, without the remote's name, plus the local
+/// branches whose upstream wasn't deleted. Run after `fetch_branches`.
+fn existing_branches(dir: &Path) -> Result<HashSet<String>, String> {
+  let output = std::process::Command::new("git")
+    .args(["for-each-ref", "--format=%(refname)%09%(upstream:track)", "refs/heads", "refs/remotes"])
+    .current_dir(dir)
+    .output()
+    .map_err(|e| format!("could not run git: {}", e))?;
+  if !output.status.success() {
+    return Err(format!("could not list the branches in {}: {}", dir.display(), String::from_utf8_lossy(&output.stderr).trim()));
+  }
+  Ok(parse_branches(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_branches(refs: &str) -> HashSet<String> {
+  refs.lines().filter_map(|line| {
+    let (refname, track) = line.trim().split_once('\t').unwrap_or((line.trim(), ""));
+    if let Some(branch) = refname.strip_prefix("refs/heads/") {
+      // A local branch left over after its remote branch was deleted
+      return (!track.contains("gone")).then(|| branch.to_string());
+    }
+    let (_, branch) = refname.strip_prefix("refs/remotes/")?.split_once('/')?;
+    (branch != "HEAD").then(|| branch.to_string())
+  }).collect()
+}
+
+/// A preview namespace found on the cluster.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LivePreview {
+  pub namespace: String,
+  pub branch: String,
+  pub created: String,
+}
+
+fn parse_previews(output: &str) -> Result<Vec<LivePreview>, String> {
+  let list: Value = serde_json::from_str(output.trim()).map_err(|e| format!("could not read the namespaces: {}", e))?;
+  let items = list.get("items").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+  Ok(items.iter().filter_map(|item| {
+    let metadata = item.get("metadata")?;
+    Some(LivePreview {
+      namespace: metadata.get("name")?.as_str()?.to_string(),
+      branch: metadata.pointer(&format!("/annotations/{}", BRANCH_ANNOTATION.replace('/', "~1"))).and_then(|v| v.as_str()).unwrap_or("").to_string(),
+      created: metadata.get("creationTimestamp").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+    })
+  }).collect())
+}
+
+pub(crate) async fn fetch_previews(nya: &Nya, host: &str, session: &openssh::Session, capsule_namespace: &str) -> Result<Vec<LivePreview>, String> {
+  let command = format!("kubectl get namespaces -l {}={},{}=true -o json {}", CAPSULE_LABEL, capsule_namespace, PREVIEW_LABEL, KUBECONFIG);
+  let output = get_from_node(nya, host, session, &command).await.map_err(|e| format!("could not list the previews: {}", e.trim()))?;
+  parse_previews(&output)
+}
+
+async fn preview_options(nya: &Nya) -> Option<PreviewOptions> {
+  match serde_json::from_value(nya.get("preview_options").await) {
+    Ok(options) => Some(options),
+    Err(e) => {
+      eprintln!("Invalid preview options: {}", e);
+      None
+    },
+  }
+}
+
+fn capsule_dir(nya: &Nya) -> PathBuf {
+  let mut dir = nya.capsule_path().map(PathBuf::from).unwrap_or_default();
+  dir.pop();
+  dir.pop();
+  dir
+}
+
+async fn list_previews(nya: Nya, _: Payload) {
+  let capsule_namespace = capsule_namespace(&nya.get("capsule").await);
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let Some(session) = create_ssh_session(&nya, &control_plane_config).await else {
+    return;
+  };
+  let previews = fetch_previews(&nya, &control_plane_config.host, &session, &capsule_namespace).await;
+  let _ = session.close().await;
+  let previews = match previews {
+    Ok(previews) => previews,
+    Err(err) => {
+      eprintln!("{}", err.red());
+      return;
+    },
+  };
+  if previews.is_empty() {
+    println!("{}", format!("No previews of {}. Ship one with `nya ship --preview`.", capsule_namespace).yellow());
+    return;
+  }
+  let dir = capsule_dir(&nya);
+  if let Err(err) = fetch_branches(&dir) {
+    eprintln!("{}", format!("{}, the branch status may be out of date", err).yellow());
+  }
+  let branches = match existing_branches(&dir) {
+    Ok(branches) => Some(branches),
+    Err(err) => {
+      eprintln!("{}", err.yellow());
+      None
+    },
+  };
+  println!("{}", format!("Previews of {}", capsule_namespace).bold());
+  println!("  {:<30} {:<40} {:<22} STATUS", "BRANCH", "NAMESPACE", "CREATED");
+  for preview in previews.iter() {
+    let status = match branches.as_ref().map(|branches| branches.contains(&preview.branch)) {
+      Some(true) => "live".green(),
+      Some(false) => "branch gone".yellow(),
+      None => "unknown".dimmed(),
+    };
+    println!("  {:<30} {:<40} {:<22} {}", preview.branch, preview.namespace, preview.created, status);
+  }
+}
+
+async fn prune_previews(nya: Nya, _: Payload) {
+  let Some(options) = preview_options(&nya).await else {
+    return;
+  };
+  let capsule_namespace = capsule_namespace(&nya.get("capsule").await);
+  let dir = capsule_dir(&nya);
+  // Without a fetch a deleted branch can't be told apart from a stale ref
+  if let Err(err) = fetch_branches(&dir) {
+    eprintln!("{}", err.red());
+    nya.stop();
+    return;
+  }
+  let branches = match existing_branches(&dir) {
+    Ok(branches) => branches,
+    Err(err) => {
+      eprintln!("{}", err.red());
+      return;
+    },
+  };
+
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let host = control_plane_config.host.clone();
+  let Some(session) = create_ssh_session(&nya, &control_plane_config).await else {
+    return;
+  };
+  let stale: Vec<LivePreview> = match fetch_previews(&nya, &host, &session, &capsule_namespace).await {
+    Ok(previews) => previews.into_iter().filter(|preview| !branches.contains(&preview.branch)).collect(),
+    Err(err) => {
+      eprintln!("{}", err.red());
+      let _ = session.close().await;
+      return;
+    },
+  };
+  if stale.is_empty() {
+    println!("{}", "Every preview's branch still exists, nothing to prune.".green());
+    let _ = session.close().await;
+    return;
+  }
+
+  println!("The following previews will be deleted from {}:", host);
+  for preview in stale.iter() {
+    println!("  {} (branch {})", preview.namespace, preview.branch);
+  }
+  if !options.yes {
+    let confirmed = Confirm::new("Do you want to continue?")
+      .with_default(false)
+      .prompt();
+    if !matches!(confirmed, Ok(true)) {
+      println!("{}", "Prune cancelled".yellow());
+      let _ = session.close().await;
+      return;
+    }
+  }
+
+  let mut failed = false;
+  for preview in stale.iter() {
+    match stream_from_node(&nya, &host, &session, &delete_namespace_command(&preview.namespace), &RetryPolicy::COMMAND).await {
+      Ok(_) => {
+        if let Some(capsule_path) = nya.capsule_path() {
+          forget_preview(capsule_path, &preview.namespace);
+        }
+        println!("{}", format!("Deleted the preview of {}.", preview.branch).green());
+      },
+      Err(e) => {
+        eprintln!("{}", format!("Failed to delete {}: {}", preview.namespace, e).red());
+        failed = true;
+      },
+    }
+  }
+  let _ = session.close().await;
+  if failed {
+    nya.stop();
+  }
+}
+
+/// Removes the ship state of a preview that was deleted.
+pub(crate) fn forget_preview(capsule_path: &Path, namespace: &str) {
+  let state_path = preview_state_path(capsule_path, namespace);
+  if state_path.exists()
+    && let Err(e) = std::fs::remove_file(&state_path) {
+    eprintln!("Failed to remove {}: {}", state_path.display(), e);
+  }
+}
+
+/// Uninstalls every release in a namespace, then deletes the namespace.
+pub(crate) fn delete_namespace_command(namespace: &str) -> String {
+  format!(
+    "releases=$(helm list -n {0} -q {1}) && \
+    {{ [ -z \"$releases\" ] || helm uninstall $releases -n {0} --wait {1}; }} && \
+    kubectl delete namespace {0} --ignore-not-found --timeout=300s {1}",
+    namespace, KUBECONFIG,
+  )
+}
+
+#[cfg(test)]
+mod preview_tests {
+  use crate::ops::preview::{LivePreview, Preview, namespace_conflict, parse_branches, parse_previews};
+
+  #[test]
+  fn names_the_preview_after_the_branch() {
+    let preview = Preview::new("shop", "feature/Checkout_v2").unwrap();
+    assert_eq!(preview.namespace, "shop--feature-checkout-v2");
+    assert_eq!(preview.host_suffix(&["shop-api".to_string()]).unwrap(), "-feature-checkout-v2");
+    assert!(preview.label_command("shop").contains("nya.dev/branch='feature/Checkout_v2'"));
+    // Capsule `shop` with branch `api` can't take capsule `shop-api`'s namespace
+    assert_ne!(Preview::new("shop", "api").unwrap().namespace, "shop-api");
+  }
+
+  #[test]
+  fn shortens_long_branches_with_a_hash() {
+    let branch = format!("feature/{}", "very-long-branch-name-".repeat(4));
+    let preview = Preview::new("shop", &format!("{}one", branch)).unwrap();
+    let other = Preview::new("shop", &format!("{}two", branch)).unwrap();
+    assert_eq!(preview.namespace.len(), 63);
+    assert!(preview.namespace.starts_with("shop--feature-very-long-branch-name"));
+    assert_ne!(preview.namespace, other.namespace);
+    assert!(!preview.namespace.ends_with('-'));
+
+    let host = "a-rather-long-ingress-host-name-api".to_string();
+    let suffix = preview.host_suffix(std::slice::from_ref(&host)).unwrap();
+    assert_eq!(host.len() + suffix.len(), 63);
+    assert_ne!(suffix, other.host_suffix(&[host]).unwrap());
+    assert!(preview.host_suffix(&["h".repeat(60)]).is_err());
+  }
+
+  #[test]
+  fn refuses_namespaces_that_are_not_this_branchs_preview() {
+    let preview = Preview::new("shop", "feature/x").unwrap();
+    let namespace = |labels: &str, branch: &str| format!(
+      r#"{{"metadata": {{"name": "shop--feature-x", "labels": {{{}}}, "annotations": {{"nya.dev/branch": "{}"}}}}}}"#, labels, branch,
+    );
+    let labels = r#""nya.dev/capsule": "shop", "nya.dev/preview": "true""#;
+    assert_eq!(namespace_conflict("", &preview, "shop"), None);
+    assert_eq!(namespace_conflict(&namespace(labels, "feature/x"), &preview, "shop"), None);
+    assert!(namespace_conflict(&namespace("", "feature/x"), &preview, "shop").is_some());
+    assert!(namespace_conflict(&namespace(labels, "feature-x"), &preview, "shop").is_some());
+    assert!(namespace_conflict(&namespace(labels, "feature/x"), &preview, "cart").is_some());
+  }
+
+  #[test]
+  fn finds_branches_on_every_remote() {
+    let branches = parse_branches("refs/heads/main\t\nrefs/remotes/origin/HEAD\t\nrefs/remotes/origin/feature/x\t\nrefs/remotes/fork/fix-y\t\n");
+    assert!(branches.contains("main"));
+    assert!(branches.contains("feature/x"));
+    assert!(branches.contains("fix-y"));
+    assert!(!branches.contains("HEAD"));
+  }
+
+  #[test]
+  fn drops_local_branches_whose_upstream_was_deleted() {
+    let branches = parse_branches("refs/heads/feature/merged\t[gone]\nrefs/heads/feature/wip\t\nrefs/heads/main\t[ahead 1]\nrefs/remotes/origin/main\t\n");
+    assert!(!branches.contains("feature/merged"));
+    assert!(branches.contains("feature/wip"));
+    assert!(branches.contains("main"));
+  }
+
+  #[test]
+  fn reads_previews_from_the_namespace_list() {
+    let output = r#"{"items": [{"metadata": {"name": "shop--feature-x", "creationTimestamp": "2026-10-19T09:00:00Z",
+      "labels": {"nya.dev/capsule": "shop", "nya.dev/preview": "true"}, "annotations": {"nya.dev/branch": "feature/x"}}}]}"#;
+    assert_eq!(parse_previews(output).unwrap(), vec![LivePreview {
+      namespace: "shop--feature-x".to_string(),
+      branch: "feature/x".to_string(),
+      created: "2026-10-19T09:00:00Z".to_string(),
+    }]);
+  }
+}
@@ -0,0 +1,338 @@
+use std::{collections::HashMap, path::Path};
+use colored::*;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::{core::{payload::Payload, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::{capsule::namespace, retry::RetryPolicy, types::{BaseNodeConfig, ReleaseOptions}, utils::{KUBECONFIG, create_ssh_session, get_control_plane_config, get_from_node, prepare_base_context, stream_from_node}}};
+
+pub struct NyaRelease;
+
+impl Service for NyaRelease {
+  fn name(&self) -> String {"NyaRelease".to_string()}
+  fn register(&self) -> ServiceActions {
+    vec![
+      (String::from("onPackHistory"), handle_action(history_action)),
+      (String::from("onRollbackPack"), handle_action(rollback_action)),
+    ]
+  }
+}
+
+/// The image tag a pack is running, as recorded in the capsule file.
+pub(crate) struct LiveTag {
+  pub pack: String,
+  pub tag: String,
+  pub image: String,
+}
+
+/// One entry of `helm history -o json`.
+#[derive(Deserialize, Clone, Debug)]
+struct HelmRevision {
+  revision: u32,
+  updated: String,
+  status: String,
+  #[serde(default)]
+  description: String,
+}
+
+/// A Helm revision with what `nya ship` wrote into its description.
+#[derive(Clone, Debug, PartialEq)]
+struct Revision {
+  number: u32,
+  updated: String,
+  status: String,
+  tag: Option<String>,
+  shipped_by: Option<String>,
+  description: String,
+}
+
+/// The release description `nya ship` passes to Helm, which is where history
+/// reads the tag and the person who shipped it back from.
+pub(crate) fn ship_description(tag: &str) -> String {
+  let who = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_else(|_| "unknown".to_string());
+  format!("Shipped {} by {}", tag, who)
+}
+
+fn parse_description(description: &str) -> Option<(String, String)> {
+  let rest = description.strip_prefix("Shipped ")?;
+  let (tag, who) = rest.split_once(" by ")?;
+  Some((tag.to_string(), who.to_string()))
+}
+
+/// Rollback revisions are described as "Rollback to N" and run the tag of revision N.
+fn parse_history(output: &str) -> Result<Vec<Revision>, String> {
+  let entries: Vec<HelmRevision> = serde_json::from_str(output.trim()).map_err(|e| format!("could not read helm history: {}", e))?;
+  let mut revisions: Vec<Revision> = Vec::new();
+  for entry in entries {
+    let (tag, shipped_by) = match parse_description(&entry.description) {
+      Some((tag, who)) => (Some(tag), Some(who)),
+      None => {
+        let target = entry.description.strip_prefix("Rollback to ").and_then(|n| n.trim().parse::<u32>().ok());
+        let tag = target.and_then(|n| revisions.iter().find(|r| r.number == n)).and_then(|r| r.tag.clone());
+        (tag, None)
+      },
+    };
+    revisions.push(Revision {
+      number: entry.revision,
+      updated: entry.updated,
+      status: entry.status,
+      tag,
+      shipped_by,
+      description: entry.description,
+    });
+  }
+  Ok(revisions)
+}
+
+/// Writes the tag each pack now runs into the capsule file, so the capsule
+/// always says what's live.
+pub(crate) fn record_live_tags(capsule_path: &Path, live: &[LiveTag]) -> Result<(), String> {
+  if live.is_empty() {
+    return Ok(());
+  }
+  let content = std::fs::read_to_string(capsule_path).map_err(|e| e.to_string())?;
+  let mut capsule: Value = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+  set_live_tags(&mut capsule, live, &chrono::Utc::now().to_rfc3339());
+  std::fs::write(capsule_path, serde_json::to_string_pretty(&capsule).unwrap()).map_err(|e| e.to_string())
+}
+
+/// Drops the live tags, for a capsule that was deleted from the cluster.
+pub(crate) fn clear_live_tags(capsule_path: &Path) -> Result<(), String> {
+  let content = std::fs::read_to_string(capsule_path).map_err(|e| e.to_string())?;
+  let mut capsule: Value = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+  if let Some(packs) = capsule["capsule"]["packs"].as_array_mut() {
+    for pack in packs.iter_mut().filter_map(|pack| pack.as_object_mut()) {
+      pack.remove("live");
+    }
+  }
+  std::fs::write(capsule_path, serde_json::to_string_pretty(&capsule).unwrap()).map_err(|e| e.to_string())
+}
+
+fn set_live_tags(capsule: &mut Value, live: &[LiveTag], shipped_at: &str) {
+  let Some(packs) = capsule["capsule"]["packs"].as_array_mut() else {
+    return;
+  };
+  for pack in packs.iter_mut() {
+    let Some(entry) = live.iter().find(|entry| pack["name"].as_str() == Some(entry.pack.as_str())) else {
+      continue;
+    };
+    pack["live"] = json!({
+      "tag": entry.tag,
+      "image": entry.image,
+      "shipped_at": shipped_at,
+    });
+  }
+}
+
+async fn release_options(nya: &Nya) -> Option<ReleaseOptions> {
+  match serde_json::from_value(nya.get("release_options").await) {
+    Ok(options) => Some(options),
+    Err(e) => {
+      eprintln!("Invalid release options: {}", e);
+      None
+    },
+  }
+}
+
+async fn fetch_history(nya: &Nya, host: &str, session: &openssh::Session, namespace: &str, pack: &str) -> Result<Vec<Revision>, String> {
+  let output = get_from_node(nya, host, session, &format!("helm history {} -n {} -o json {}", pack, namespace, KUBECONFIG)).await
+    .map_err(|e| format!("could not read the history of {}: {}", pack, e.trim()))?;
+  parse_history(&output)
+}
+
+/// The image tag each release currently runs, read from the values Helm
+/// deployed it with. Packs without a release are left out.
+pub(crate) async fn live_tags(nya: &Nya, host: &str, session: &openssh::Session, namespace: &str, packs: &[String]) -> HashMap<String, String> {
+  if packs.is_empty() {
+    return HashMap::new();
+  }
+  let command = format!(
+    "for pack in {}; do printf '%s ' \"$pack\"; helm get values \"$pack\" -n {} -o json {} 2>/dev/null || echo null; done",
+    packs.join(" "), namespace, KUBECONFIG,
+  );
+  match get_from_node(nya, host, session, &command).await {
+    Ok(output) => parse_live_tags(&output),
+    Err(_) => HashMap::new(),
+  }
+}
+
+fn parse_live_tags(output: &str) -> HashMap<String, String> {
+  output
+    .lines()
+    .filter_map(|line| {
+      let (pack, values) = line.split_once(' ')?;
+      let values: Value = serde_json::from_str(values).ok()?;
+      let tag = values.pointer("/image/tag")?.as_str()?.to_string();
+      Some((pack.to_string(), tag))
+    })
+    .collect()
+}
+
+fn print_history(pack: &str, revisions: &[Revision]) {
+  println!("{}", format!("History of {}", pack).bold());
+  println!("  {:<9} {:<26} {:<12} {:<16} {:<14} DESCRIPTION", "REVISION", "UPDATED", "STATUS", "TAG", "SHIPPED BY");
+  for revision in revisions.iter() {
+    let updated = chrono::DateTime::parse_from_rfc3339(&revision.updated)
+      .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+      .unwrap_or_else(|_| revision.updated.clone());
+    let status = match revision.status.as_str() {
+      "deployed" => revision.status.green(),
+      "failed" => revision.status.red(),
+      _ => revision.status.normal(),
+    };
+    println!(
+      "  {:<9} {:<26} {:<12} {:<16} {:<14} {}",
+      revision.number,
+      updated,
+      status,
+      revision.tag.as_deref().unwrap_or("-"),
+      revision.shipped_by.as_deref().unwrap_or("-"),
+      revision.description,
+    );
+  }
+}
+
+/// Releases live in the capsule's namespace, so history and rollback need the capsule.
+async fn release_namespace(nya: &Nya) -> Option<String> {
+  let namespace = namespace(nya).await;
+  if namespace.is_none() {
+    eprintln!("{}", "No capsule found. Run this from the capsule's directory or pass --location.".red());
+  }
+  namespace
+}
+
+async fn history_action(nya: Nya, _: Payload) {
+  let Some(options) = release_options(&nya).await else {
+    return;
+  };
+  let Some(namespace) = release_namespace(&nya).await else {
+    return;
+  };
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let Some(session) = create_ssh_session(&nya, &control_plane_config).await else {
+    return;
+  };
+  match fetch_history(&nya, &control_plane_config.host, &session, &namespace, &options.pack).await {
+    Ok(revisions) => print_history(&options.pack, &revisions),
+    Err(err) => eprintln!("{}", err.red()),
+  }
+  let _ = session.close().await;
+}
+
+/// Helm waits for the restored revision's own workloads, which are named
+/// after its components rather than the pack.
+fn rollback_command(pack: &str, revision: u32, namespace: &str) -> String {
+  format!("helm rollback {} {} -n {} --wait --timeout 120s {}", pack, revision, namespace, KUBECONFIG)
+}
+
+// Every failure stops the run, so `nya rollback` exits non-zero
+async fn rollback_action(nya: Nya, _: Payload) {
+  let Some(options) = release_options(&nya).await else {
+    nya.stop();
+    return;
+  };
+  let Some(namespace) = release_namespace(&nya).await else {
+    nya.stop();
+    return;
+  };
+  prepare_base_context(nya.clone()).await;
+  let control_plane_config: BaseNodeConfig = get_control_plane_config(nya.clone()).await;
+  let host = control_plane_config.host.clone();
+  let Some(session) = create_ssh_session(&nya, &control_plane_config).await else {
+    nya.stop();
+    return;
+  };
+
+  let revisions = match fetch_history(&nya, &host, &session, &namespace, &options.pack).await {
+    Ok(revisions) => revisions,
+    Err(err) => {
+      eprintln!("{}", err.red());
+      let _ = session.close().await;
+      nya.stop();
+      return;
+    },
+  };
+  let current = revisions.iter().rev().find(|r| r.status == "deployed").map(|r| r.number);
+  let target = match options.to {
+    Some(to) => revisions.iter().find(|r| r.number == to),
+    None => revisions.iter().rev().find(|r| Some(r.number) < current && r.status == "superseded"),
+  };
+  let Some(target) = target.cloned() else {
+    match options.to {
+      Some(to) => eprintln!("{}", format!("{} has no revision {}. Run `nya history {}` to list them.", options.pack, to, options.pack).red()),
+      None => eprintln!("{}", format!("{} has no earlier revision to roll back to.", options.pack).red()),
+    }
+    let _ = session.close().await;
+    nya.stop();
+    return;
+  };
+  if Some(target.number) == current {
+    println!("{}", format!("{} is already on revision {}.", options.pack, target.number).yellow());
+    let _ = session.close().await;
+    return;
+  }
+
+  println!("Rolling {} back to revision {} ({})", options.pack, target.number, target.tag.as_deref().unwrap_or("unknown tag"));
+  let rollback = rollback_command(&options.pack, target.number, &namespace);
+  if let Err(err) = stream_from_node(&nya, &host, &session, &rollback, &RetryPolicy::COMMAND).await {
+    eprintln!("{}", format!("Rollback of {} failed: {}", options.pack, err).red());
+    let _ = session.close().await;
+    nya.stop();
+    return;
+  }
+  println!("{}", format!("{} is back on revision {}.", options.pack, target.number).green());
+
+  if let (Some(capsule_path), Some(tag)) = (nya.capsule_path(), target.tag) {
+    let registry_host = nya.get("nya.registry_host").await.as_str().unwrap_or("").to_string();
+    let live = LiveTag { image: format!("{}/{}:{}", registry_host, options.pack, tag), pack: options.pack.clone(), tag };
+    if let Err(e) = record_live_tags(capsule_path, &[live]) {
+      eprintln!("Failed to record the live image tag in {}: {}", capsule_path.display(), e);
+    }
+  }
+  let _ = session.close().await;
+}
+
+#[cfg(test)]
+mod release_tests {
+  use serde_json::json;
+  use crate::ops::release::{LiveTag, parse_history, parse_live_tags, rollback_command, set_live_tags};
+
+  #[test]
+  fn reads_tags_and_authors_from_helm_history() {
+    let output = r#"[
+      {"revision":1,"updated":"2026-10-01T10:00:00.0+00:00","status":"superseded","chart":"nya-0.1.0","app_version":"1.0.0","description":"Install complete"},
+      {"revision":2,"updated":"2026-10-02T10:00:00.0+00:00","status":"superseded","chart":"nya-0.1.0","app_version":"1.0.0","description":"Shipped 1a2b3c4 by alice"},
+      {"revision":3,"updated":"2026-10-03T10:00:00.0+00:00","status":"superseded","chart":"nya-0.1.0","app_version":"1.0.0","description":"Shipped 5d6e7f8-dirty by bob"},
+      {"revision":4,"updated":"2026-10-04T10:00:00.0+00:00","status":"deployed","chart":"nya-0.1.0","app_version":"1.0.0","description":"Rollback to 2"}
+    ]"#;
+    let revisions = parse_history(output).unwrap();
+    assert_eq!(revisions[0].tag, None);
+    assert_eq!(revisions[1].tag.as_deref(), Some("1a2b3c4"));
+    assert_eq!(revisions[2].shipped_by.as_deref(), Some("bob"));
+    assert_eq!(revisions[3].tag.as_deref(), Some("1a2b3c4"));
+    assert_eq!(revisions[3].shipped_by, None);
+  }
+
+  #[test]
+  fn reads_live_tags_from_helm_values() {
+    let output = "web {\"image\":{\"name\":\"web\",\"tag\":\"1a2b3c4\"},\"domain\":\"nya.local\"}\napi null\n";
+    let tags = parse_live_tags(output);
+    assert_eq!(tags.get("web").map(|t| t.as_str()), Some("1a2b3c4"));
+    assert!(!tags.contains_key("api"));
+  }
+
+  #[test]
+  fn records_the_live_tag_on_the_pack() {
+    let mut capsule = json!({"capsule": {"packs": [{"name": "web"}, {"name": "api"}]}});
+    let live = vec![LiveTag { pack: "web".to_string(), tag: "1a2b3c4".to_string(), image: "10.0.0.7:5000/web:1a2b3c4".to_string() }];
+    set_live_tags(&mut capsule, &live, "2026-10-19T12:00:00Z");
+    assert_eq!(capsule["capsule"]["packs"][0]["live"]["tag"], "1a2b3c4");
+    assert_eq!(capsule["capsule"]["packs"][0]["live"]["shipped_at"], "2026-10-19T12:00:00Z");
+    assert!(capsule["capsule"]["packs"][1].get("live").is_none());
+  }
+
+  #[test]
+  fn rollback_waits_for_the_restored_revision() {
+    let command = rollback_command("api", 3, "shop");
+    assert_eq!(command, "helm rollback api 3 -n shop --wait --timeout 120s --kubeconfig=/etc/rancher/k3s/k3s.yaml");
+  }
+}
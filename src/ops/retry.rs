@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+/// How long a remote operation may take and how often it is retried when it
+/// fails for a reason that is likely to go away on its own.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+  pub attempts: u32,
+  pub timeout: Duration,
+  /// Delay before the second attempt, doubled for every attempt after that.
+  pub backoff: Duration,
+}
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+impl RetryPolicy {
+  /// Opening an SSH connection.
+  pub const CONNECT: RetryPolicy = RetryPolicy { attempts: 5, timeout: Duration::from_secs(30), backoff: Duration::from_secs(3) };
+  /// Short commands: reading a value, moving a file, restarting a service.
+  pub const COMMAND: RetryPolicy = RetryPolicy { attempts: 3, timeout: Duration::from_secs(10 * 60), backoff: Duration::from_secs(5) };
+  /// Package installs and install scripts that download from the internet.
+  pub const INSTALL: RetryPolicy = RetryPolicy { attempts: 5, timeout: Duration::from_secs(20 * 60), backoff: Duration::from_secs(15) };
+  /// Commands that wait on the node themselves. Not retried, only bounded.
+  pub const WAIT: RetryPolicy = RetryPolicy { attempts: 1, timeout: Duration::from_secs(6 * 60), backoff: Duration::ZERO };
+
+  /// The delay before attempt `attempt + 1`, where the first attempt is 1.
+  pub fn delay(&self, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    self.backoff.saturating_mul(factor).min(MAX_BACKOFF)
+  }
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    RetryPolicy::COMMAND
+  }
+}
+
+/// Errors that come from lock contention or the network rather than from the
+/// command itself, so running it again is expected to work.
+const TRANSIENT_ERRORS: [&str; 16] = [
+  "could not get lock",
+  "unable to acquire the dpkg frontend lock",
+  "unable to lock directory",
+  "unable to lock database",
+  "waiting for process with pid",
+  "temporary failure resolving",
+  "temporary failure in name resolution",
+  "could not resolve host",
+  "connection timed out",
+  "connection reset by peer",
+  "connection refused",
+  "network is unreachable",
+  "failed to fetch",
+  "failed to download metadata",
+  "tls handshake timeout",
+  "temporary error (try again later)",
+];
+
+pub fn is_transient(error: &str) -> bool {
+  let error = error.to_lowercase();
+  TRANSIENT_ERRORS.iter().any(|pattern| error.contains(pattern))
+}
+
+/// apt left a half-configured install behind, which `dpkg --configure -a` fixes.
+pub fn needs_package_repair(error: &str) -> bool {
+  error.to_lowercase().contains("dpkg was interrupted")
+}
+
+#[cfg(test)]
+mod retry_tests {
+  use std::time::Duration;
+  use crate::ops::retry::{RetryPolicy, is_transient, needs_package_repair};
+
+  #[test]
+  fn backs_off_exponentially_up_to_a_cap() {
+    let policy = RetryPolicy { attempts: 10, timeout: Duration::from_secs(1), backoff: Duration::from_secs(5) };
+    assert_eq!(policy.delay(1), Duration::from_secs(5));
+    assert_eq!(policy.delay(2), Duration::from_secs(10));
+    assert_eq!(policy.delay(3), Duration::from_secs(20));
+    assert_eq!(policy.delay(8), Duration::from_secs(60));
+  }
+
+  #[test]
+  fn recognises_lock_and_network_errors() {
+    assert!(is_transient("E: Could not get lock /var/lib/dpkg/lock-frontend. It is held by process 1234 (unattended-upgr)"));
+    assert!(is_transient("curl: (6) Could not resolve host: get.k3s.io"));
+    assert!(is_transient("ERROR: unable to lock database: temporary error (try again later)"));
+    assert!(!is_transient("E: Unable to locate package bind10"));
+    assert!(needs_package_repair("E: dpkg was interrupted, you must manually run 'sudo dpkg --configure -a' to correct the problem."));
+  }
+}
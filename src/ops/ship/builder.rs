@@ -0,0 +1,328 @@
+use std::{path::Path, process::Stdio};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::{core::runtime::Nya, ops::base::inventory::Inventory};
+
+/// The buildx builder multi-platform builds run on. Docker's default
+/// builder can't push manifest lists.
+pub const BUILDX_BUILDER: &str = "nya";
+
+/// Registers QEMU emulators so buildx can build for other architectures.
+pub const BINFMT_IMAGE: &str = "tonistiigi/binfmt";
+
+/// `nya.build` in the base config.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct BuildConfig {
+  /// `local` builds on this machine, `remote` on a base node.
+  #[serde(default)]
+  pub mode: Option<String>,
+  /// The node remote builds run on, the control plane when unset.
+  #[serde(default)]
+  pub host: Option<String>,
+  /// The tool local builds use, detected when unset.
+  #[serde(default)]
+  pub builder: Option<Builder>,
+  /// e.g. `["linux/arm64"]`, built instead of the nodes' architectures.
+  #[serde(default)]
+  pub platforms: Option<Vec<String>>,
+  /// The CA that signed the registry's certificate. Without it the registry
+  /// is treated as plain HTTP.
+  #[serde(default)]
+  pub registry_ca: Option<String>,
+}
+
+impl BuildConfig {
+  pub fn is_remote(&self) -> bool {
+    self.mode.as_deref() == Some("remote")
+  }
+}
+
+pub async fn build_config(nya: &Nya) -> BuildConfig {
+  serde_json::from_value(nya.get("nya.build").await).unwrap_or_default()
+}
+
+/// The tools that can build and push pack images.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Builder {
+  Docker,
+  /// `docker buildx`, which pushes as part of the build.
+  Buildx,
+  Podman,
+  Nerdctl,
+}
+
+/// Tried in this order when `nya.build.builder` isn't set.
+const DETECT_ORDER: [Builder; 3] = [Builder::Docker, Builder::Podman, Builder::Nerdctl];
+
+impl Builder {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Builder::Docker => "docker",
+      Builder::Buildx => "buildx",
+      Builder::Podman => "podman",
+      Builder::Nerdctl => "nerdctl",
+    }
+  }
+
+  pub fn program(&self) -> &'static str {
+    match self {
+      Builder::Docker | Builder::Buildx => "docker",
+      Builder::Podman => "podman",
+      Builder::Nerdctl => "nerdctl",
+    }
+  }
+
+  /// Whether the image is already in the registry once the build finishes.
+  pub fn pushes_on_build(&self) -> bool {
+    matches!(self, Builder::Buildx)
+  }
+
+  /// The builder for a pack's platforms. Only buildx builds more than one,
+  /// so Docker switches to it unless it was picked explicitly.
+  pub fn for_platforms(self, platforms: &[String], explicit: bool) -> Result<Builder, String> {
+    match self {
+      _ if platforms.len() < 2 => Ok(self),
+      Builder::Buildx => Ok(self),
+      Builder::Docker if !explicit => Ok(Builder::Buildx),
+      _ => Err(format!("building for {} needs buildx, set `nya.build.builder` to buildx or limit the pack's `platforms`", platforms.join(", "))),
+    }
+  }
+
+  pub fn build_args(&self, image_name: &str, context: &str, platforms: &[String], config: &BuildConfig) -> Vec<String> {
+    let mut args: Vec<String> = match self {
+      Builder::Buildx => vec!["buildx".into(), "build".into()],
+      _ => vec!["build".into()],
+    };
+    if *self == Builder::Buildx && platforms.len() > 1 {
+      args.extend(["--builder".into(), BUILDX_BUILDER.into()]);
+    }
+    args.extend(["-t".into(), image_name.into()]);
+    if !platforms.is_empty() {
+      args.extend(["--platform".into(), platforms.join(",")]);
+    }
+    if *self == Builder::Buildx {
+      let insecure = if config.registry_ca.is_none() { ",registry.insecure=true" } else { "" };
+      args.extend(["--output".into(), format!("type=image,name={},push=true{}", image_name, insecure)]);
+    }
+    args.push(context.into());
+    args
+  }
+
+  /// Docker and buildx take the registry's trust from the daemon and
+  /// BuildKit configs, Podman and nerdctl from flags on the push.
+  pub fn push_args(&self, image_name: &str, config: &BuildConfig) -> Vec<String> {
+    let mut args = vec!["push".to_string()];
+    match (self, config.registry_ca.as_ref()) {
+      (Builder::Podman, None) => args.push("--tls-verify=false".into()),
+      (Builder::Podman, Some(ca)) => {
+        let ca = shellexpand::tilde(ca).to_string();
+        let cert_dir = Path::new(&ca).parent().map(|dir| dir.display().to_string()).unwrap_or_default();
+        args.extend(["--cert-dir".into(), cert_dir]);
+      },
+      (Builder::Nerdctl, None) => args.push("--insecure-registry".into()),
+      _ => {},
+    }
+    args.push(image_name.into());
+    args
+  }
+
+  /// Whether the tool is installed and can build, e.g. that the Docker
+  /// daemon is running.
+  async fn is_available(&self) -> bool {
+    let probe: &[&str] = match self {
+      Builder::Docker => &["info"],
+      Builder::Buildx => &["buildx", "version"],
+      Builder::Podman | Builder::Nerdctl => &["version"],
+    };
+    Command::new(self.program())
+      .args(probe)
+      .stdin(Stdio::null())
+      .stdout(Stdio::null())
+      .stderr(Stdio::null())
+      .status()
+      .await
+      .is_ok_and(|status| status.success())
+  }
+}
+
+/// Creates the `docker-container` buildx builder unless it exists, and
+/// installs QEMU emulators for the platforms it can't build yet. It uses
+/// the host network so it can reach a registry on the LAN.
+pub async fn ensure_buildx_builder(platforms: &[String]) -> Result<(), String> {
+  if inspect_buildx_builder().await.is_err() {
+    docker(&["buildx", "create", "--name", BUILDX_BUILDER, "--driver", "docker-container", "--driver-opt", "network=host"]).await
+      .map_err(|e| format!("could not create the buildx builder: {}", e))?;
+  }
+  let missing = missing_platforms(&inspect_buildx_builder().await?, platforms);
+  if missing.is_empty() {
+    return Ok(());
+  }
+
+  let archs = binfmt_archs(&missing);
+  println!("Installing QEMU emulation for {}", missing.join(", "));
+  docker(&["run", "--privileged", "--rm", BINFMT_IMAGE, "--install", &archs]).await
+    .map_err(|e| format!("could not install QEMU emulation for {}: {}", missing.join(", "), e))?;
+  // The builder only picks up new emulators when it starts
+  docker(&["buildx", "stop", BUILDX_BUILDER]).await?;
+  let missing = missing_platforms(&inspect_buildx_builder().await?, platforms);
+  match missing.is_empty() {
+    true => Ok(()),
+    false => Err(format!(
+      "the {} buildx builder can't build for {}, install QEMU emulation with `docker run --privileged --rm {} --install {}` or limit the pack's `platforms`",
+      BUILDX_BUILDER, missing.join(", "), BINFMT_IMAGE, binfmt_archs(&missing),
+    )),
+  }
+}
+
+/// Starts the builder if needed and returns what `docker buildx inspect` prints.
+async fn inspect_buildx_builder() -> Result<String, String> {
+  docker(&["buildx", "inspect", "--bootstrap", BUILDX_BUILDER]).await
+}
+
+async fn docker(args: &[&str]) -> Result<String, String> {
+  let output = Command::new("docker")
+    .args(args)
+    .stdin(Stdio::null())
+    .output()
+    .await
+    .map_err(|e| e.to_string())?;
+  match output.status.success() {
+    true => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+    false => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+  }
+}
+
+/// The platforms the `Platforms:` line of `docker buildx inspect` doesn't list.
+pub fn missing_platforms(inspect: &str, platforms: &[String]) -> Vec<String> {
+  let supported: Vec<&str> = inspect.lines()
+    .filter_map(|line| line.trim().strip_prefix("Platforms:"))
+    .flat_map(|list| list.split(','))
+    .map(|platform| platform.trim().trim_end_matches('*'))
+    .collect();
+  platforms.iter().filter(|platform| !supported.contains(&platform.as_str())).cloned().collect()
+}
+
+/// The architectures `tonistiigi/binfmt --install` takes for some platforms.
+pub fn binfmt_archs(platforms: &[String]) -> String {
+  platforms.iter().map(|platform| node_arch(platform)).collect::<Vec<&str>>().join(",")
+}
+
+/// The platform images are built for on nodes with a `uname -m` architecture.
+pub fn platform_for_arch(arch: &str) -> Option<&'static str> {
+  match arch {
+    "x86_64" | "amd64" => Some("linux/amd64"),
+    "aarch64" | "arm64" => Some("linux/arm64"),
+    "armv7l" | "armv7" => Some("linux/arm/v7"),
+    "armv6l" => Some("linux/arm/v6"),
+    "ppc64le" => Some("linux/ppc64le"),
+    "s390x" => Some("linux/s390x"),
+    "riscv64" => Some("linux/riscv64"),
+    _ => None,
+  }
+}
+
+/// The platforms the base nodes run, from the last `nya base inventory`.
+pub fn cluster_platforms(inventory: &Inventory) -> Vec<String> {
+  let mut platforms: Vec<String> = inventory.nodes.iter()
+    .filter_map(|node| platform_for_arch(&node.arch))
+    .map(String::from)
+    .collect();
+  platforms.sort();
+  platforms.dedup();
+  platforms
+}
+
+/// What a pack builds for: the cluster's platforms, narrowed to the pack's
+/// own `platforms` when it has them. Empty leaves it to the builder.
+pub fn pack_platforms(cluster: &[String], restriction: Option<&[String]>) -> Result<Vec<String>, String> {
+  let Some(restriction) = restriction.filter(|platforms| !platforms.is_empty()) else {
+    return Ok(cluster.to_vec());
+  };
+  if cluster.is_empty() {
+    return Ok(restriction.to_vec());
+  }
+  let platforms: Vec<String> = cluster.iter().filter(|platform| restriction.contains(platform)).cloned().collect();
+  match platforms.is_empty() {
+    true => Err(format!("it's limited to {}, but the nodes run {}", restriction.join(", "), cluster.join(", "))),
+    false => Ok(platforms),
+  }
+}
+
+/// The `kubernetes.io/arch` label of the nodes that can run a platform.
+pub fn node_arch(platform: &str) -> &str {
+  platform.split('/').nth(1).unwrap_or(platform)
+}
+
+/// The configured builder, or the first one found on this machine.
+pub async fn local_builder(config: &BuildConfig) -> Result<Builder, String> {
+  if let Some(builder) = config.builder {
+    return match builder.is_available().await {
+      true => Ok(builder),
+      false => Err(format!("`nya.build.builder` is {}, but `{}` isn't available", builder.name(), builder.program())),
+    };
+  }
+  for builder in DETECT_ORDER {
+    if builder.is_available().await {
+      return Ok(builder);
+    }
+  }
+  Err("no image builder found, install Docker, Podman or nerdctl, or set `nya.build.mode` to remote".to_string())
+}
+
+#[cfg(test)]
+mod builder_tests {
+  use crate::ops::{base::inventory::{Inventory, NodeInventory}, ship::builder::{BuildConfig, Builder, binfmt_archs, cluster_platforms, missing_platforms, node_arch, pack_platforms}};
+
+  #[test]
+  fn passes_the_registry_trust_each_builder_needs() {
+    let plain = BuildConfig::default();
+    assert_eq!(Builder::Podman.push_args("reg:5000/web:1a2b", &plain), ["push", "--tls-verify=false", "reg:5000/web:1a2b"]);
+    assert_eq!(Builder::Nerdctl.push_args("reg:5000/web:1a2b", &plain), ["push", "--insecure-registry", "reg:5000/web:1a2b"]);
+    assert_eq!(Builder::Docker.push_args("reg:5000/web:1a2b", &plain), ["push", "reg:5000/web:1a2b"]);
+
+    let trusted = BuildConfig { registry_ca: Some("/etc/nya/certs/ca.crt".to_string()), ..Default::default() };
+    assert_eq!(Builder::Podman.push_args("reg/web:1a2b", &trusted), ["push", "--cert-dir", "/etc/nya/certs", "reg/web:1a2b"]);
+  }
+
+  #[test]
+  fn buildx_pushes_as_part_of_the_build() {
+    let config = BuildConfig::default();
+    let platforms = vec!["linux/amd64".to_string(), "linux/arm64".to_string()];
+    let args = Builder::Buildx.build_args("reg:5000/web:1a2b", "packs/web", &platforms, &config);
+    assert_eq!(args[..6], ["buildx", "build", "--builder", "nya", "-t", "reg:5000/web:1a2b"]);
+    assert!(args.contains(&"linux/amd64,linux/arm64".to_string()));
+    assert!(args.contains(&"type=image,name=reg:5000/web:1a2b,push=true,registry.insecure=true".to_string()));
+    assert_eq!(args.last().unwrap(), "packs/web");
+    assert!(Builder::Buildx.pushes_on_build());
+    assert!(!Builder::Podman.pushes_on_build());
+  }
+
+  #[test]
+  fn builds_for_the_node_architectures_a_pack_allows() {
+    let node = |arch: &str| NodeInventory { arch: arch.to_string(), ..Default::default() };
+    let inventory = Inventory { collected_at: String::new(), nodes: vec![node("x86_64"), node("aarch64"), node("x86_64")] };
+    let cluster = cluster_platforms(&inventory);
+    assert_eq!(cluster, ["linux/amd64", "linux/arm64"]);
+
+    assert_eq!(pack_platforms(&cluster, None).unwrap(), cluster);
+    assert_eq!(pack_platforms(&cluster, Some(&["linux/arm64".to_string()])).unwrap(), ["linux/arm64"]);
+    assert!(pack_platforms(&cluster, Some(&["linux/riscv64".to_string()])).is_err());
+    assert_eq!(pack_platforms(&[], Some(&["linux/arm64".to_string()])).unwrap(), ["linux/arm64"]);
+    assert_eq!(node_arch("linux/arm/v7"), "arm");
+
+    assert_eq!(Builder::Docker.for_platforms(&cluster, false).unwrap(), Builder::Buildx);
+    assert_eq!(Builder::Docker.for_platforms(&cluster[..1], true).unwrap(), Builder::Docker);
+    assert!(Builder::Podman.for_platforms(&cluster, false).is_err());
+  }
+
+  #[test]
+  fn finds_the_platforms_the_builder_needs_emulation_for() {
+    let inspect = "Name:   nya\nDriver: docker-container\n\nNodes:\nName:      nya0\nStatus:    running\nPlatforms: linux/amd64*, linux/amd64/v2, linux/386\n";
+    let platforms = vec!["linux/amd64".to_string(), "linux/arm64".to_string(), "linux/arm/v7".to_string()];
+    let missing = missing_platforms(inspect, &platforms);
+    assert_eq!(missing, ["linux/arm64", "linux/arm/v7"]);
+    assert_eq!(binfmt_archs(&missing), "arm64,arm");
+  }
+}
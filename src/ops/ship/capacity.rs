@@ -0,0 +1,116 @@
+use std::{fs, path::PathBuf};
+use colored::*;
+use serde_json::Value;
+
+use crate::{core::{payload::Payload, runtime::Nya}, defaults::inventory_default_location, ops::base::inventory::{load_inventory, parse_cpu_millis, parse_memory_bytes}};
+
+/// What the capsule's packs ask the scheduler for.
+#[derive(Debug, Default, PartialEq)]
+pub struct Requests {
+  pub cpu_millis: u64,
+  pub memory_bytes: u64,
+}
+
+/// Sums `replicaCount` times `resources.requests` over the components in a pack's values.yaml.
+pub fn pack_requests(values: &Value) -> Requests {
+  let mut requests = Requests::default();
+  let components = values.get("components").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+  for component in components.iter() {
+    let replicas = component.get("replicaCount").and_then(|v| v.as_u64()).unwrap_or(1);
+    let quantity = |name: &str| component.pointer(&format!("/resources/requests/{}", name)).map(|v| match v {
+      Value::String(s) => s.clone(),
+      other => other.to_string(),
+    });
+    requests.cpu_millis += replicas * quantity("cpu").and_then(|q| parse_cpu_millis(&q)).unwrap_or(0);
+    requests.memory_bytes += replicas * quantity("memory").and_then(|q| parse_memory_bytes(&q)).unwrap_or(0);
+  }
+  requests
+}
+
+/// Compares the capsule's requests with what the last `nya base inventory` found.
+/// `nya.capacity_check` in the base config picks whether going over warns
+/// (the default), refuses to ship, or is not checked at all.
+pub async fn check_capacity(nya: Nya, _: Payload) {
+  let mode = nya.get("nya.capacity_check").await.as_str().unwrap_or("warn").to_string();
+  if mode == "off" {
+    return;
+  }
+  let Some(inventory) = load_inventory() else {
+    println!("{}", format!("No inventory at {}, skipping the capacity check. Run `nya base inventory` to enable it.", inventory_default_location().display()).yellow());
+    return;
+  };
+
+  let capsule = nya.get("capsule").await;
+  let packs = capsule.get("packs").and_then(|p| p.as_array()).cloned().unwrap_or_default();
+  let mut capsule_dir = nya.capsule_path().map(PathBuf::from).unwrap_or_default();
+  capsule_dir.pop();
+  capsule_dir.pop();
+
+  let mut total = Requests::default();
+  for pack in packs.iter() {
+    let Some(location) = pack.get("location").and_then(|v| v.as_str()) else {
+      continue;
+    };
+    let values_path = capsule_dir.join(location).join("values.yaml");
+    let values: Value = match fs::read_to_string(&values_path).map_err(|e| e.to_string()).and_then(|content| serde_yaml_ng::from_str(&content).map_err(|e| e.to_string())) {
+      Ok(values) => values,
+      Err(e) => {
+        eprintln!("Skipping {} in the capacity check: {}", values_path.display(), e);
+        continue;
+      },
+    };
+    let requests = pack_requests(&values);
+    total.cpu_millis += requests.cpu_millis;
+    total.memory_bytes += requests.memory_bytes;
+  }
+
+  let Some((cpu, memory)) = inventory.allocatable() else {
+    println!("{}", format!("The inventory at {} has no allocatable resources for any node, skipping the capacity check. Run `nya base inventory` again once the cluster is up.", inventory_default_location().display()).yellow());
+    return;
+  };
+  let mut over: Vec<String> = Vec::new();
+  if total.cpu_millis > cpu {
+    over.push(format!("CPU: {}m requested, {}m allocatable", total.cpu_millis, cpu));
+  }
+  if total.memory_bytes > memory {
+    over.push(format!("memory: {}Mi requested, {}Mi allocatable", total.memory_bytes / 1048576, memory / 1048576));
+  }
+  if over.is_empty() {
+    return;
+  }
+
+  let message = format!("The capsule requests more than the cluster can schedule ({}).", over.join("; "));
+  if mode == "refuse" {
+    eprintln!("{}", message.red());
+    eprintln!("Lower the requests or replicaCount in the packs' values.yaml, or add nodes and run `nya base inventory` again.");
+    nya.stop();
+  } else {
+    println!("{}", message.yellow());
+    println!("Some pods may stay Pending. The inventory was collected at {}.", inventory.collected_at);
+  }
+}
+
+#[cfg(test)]
+mod capacity_tests {
+  use crate::ops::ship::capacity::{Requests, pack_requests};
+
+  #[test]
+  fn multiplies_requests_by_replicas() {
+    let values: serde_json::Value = serde_yaml_ng::from_str("
+components:
+  - name: api
+    replicaCount: 3
+    resources:
+      requests:
+        cpu: 250m
+        memory: 128Mi
+  - name: web
+    resources:
+      requests:
+        cpu: 1
+  - name: worker
+    replicaCount: 2
+").unwrap();
+    assert_eq!(pack_requests(&values), Requests { cpu_millis: 1750, memory_bytes: 3 * 134217728 });
+  }
+}
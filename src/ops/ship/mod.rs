@@ -1,8 +1,20 @@
-use std::{env, path::PathBuf, process::Stdio};
+pub(crate) mod builder;
+mod capacity;
+pub(crate) mod remote;
+mod report;
+mod state;
+
+use std::{collections::HashMap, env, fs, path::PathBuf, process::Stdio, time::Instant};
+use colored::*;
 use futures::future::join_all;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tokio::{io::{AsyncBufReadExt, BufReader}, process::Command};
-use crate::{core::{payload::Payload, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::utils::prepare_base_context};
+use crate::{core::{payload::Payload, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::{base::{build::cluster::upload_chart, inventory::load_inventory}, capsule::{capsule_namespace, move_out_of_default_message, prepare_namespace_command, releases_left_in_default}, preview::{Preview, check_preview_namespace, current_branch}, release::{LiveTag, live_tags, record_live_tags, ship_description}, retry::{RetryPolicy, is_transient}, utils::{KUBECONFIG, get_control_plane_config, prepare_base_context, try_create_ssh_session}}, utils::generate_sha};
+use builder::{BuildConfig, Builder, build_config, cluster_platforms, ensure_buildx_builder, local_builder, node_arch, pack_platforms};
+use remote::{build_node, build_remote};
+use report::{PackReport, Phase, ingress_hosts, ingress_urls, print_summary};
+use state::{PackState, ShipState, pack_hash};
 
 pub struct NyaShip;
 
@@ -10,17 +22,68 @@ impl Service for NyaShip {
   fn name(&self) -> String {"NyaShip".to_string()}
   fn register(&self) -> ServiceActions {
     vec![
+      ("onCheckCapacity".to_string(), handle_action(capacity::check_capacity)),
       ("onBuildPacks".to_string(), handle_action(build_packs)),
       ("onDeployCapsule".to_string(), handle_action(deploy_capsule)),
     ]
   }
 }
 
+/// Where the ship state of the capsule at `capsule_path` is kept.
+pub(crate) fn ship_state_path(capsule_path: &std::path::Path) -> PathBuf {
+  ShipState::path(capsule_path)
+}
+
+pub(crate) fn preview_state_path(capsule_path: &std::path::Path, namespace: &str) -> PathBuf {
+  ShipState::preview_path(capsule_path, namespace)
+}
+
+fn state_path(capsule_path: &std::path::Path, preview: Option<&Preview>) -> PathBuf {
+  match preview {
+    Some(preview) => preview_state_path(capsule_path, &preview.namespace),
+    None => ship_state_path(capsule_path),
+  }
+}
+
+/// `--preview` ships into a namespace of the current git branch.
+async fn is_preview(nya: &Nya) -> bool {
+  nya.get("ship_preview").await.as_bool().unwrap_or(false)
+}
+
+/// `--force` ships packs even when nothing changed since their last ship.
+async fn is_forced(nya: &Nya) -> bool {
+  nya.get("ship_force").await.as_bool().unwrap_or(false)
+}
+
+/// The packs passed with `--pack` or to `nya pack deploy`. Empty ships every pack.
+pub(crate) async fn selected_packs(nya: &Nya) -> Vec<String> {
+  serde_json::from_value(nya.get("ship_packs").await).unwrap_or_default()
+}
+
+pub(crate) fn is_selected(selection: &[String], pack_name: &str) -> bool {
+  selection.is_empty() || selection.iter().any(|name| name == pack_name)
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct PackContext {
   pack_name: String,
   pack_location: String,
   pack_image_name: String,
+  pack_image_tag: String,
+  pack_hash: String,
+  /// The capsule's namespace, which holds the pack's release.
+  namespace: String,
+  /// Remote and buildx builds push as they build, so there's nothing left to push.
+  #[serde(default)]
+  pushed: bool,
+  /// What built the image locally, `None` for remote builds.
+  #[serde(default)]
+  builder: Option<Builder>,
+  /// Empty when the nodes' architectures aren't known.
+  #[serde(default)]
+  platforms: Vec<String>,
+  #[serde(default)]
+  preview: Option<Preview>,
 }
 
 async fn build_packs(nya: Nya, _: Payload) {
@@ -35,125 +98,330 @@ async fn build_packs(nya: Nya, _: Payload) {
   full_capsule_path_buf.pop();
   let capsule_path = full_capsule_path_buf.iter().as_path();
 
+  let preview = if is_preview(&nya).await {
+    match current_branch(capsule_path) {
+      Ok(branch) => match Preview::new(&capsule_namespace(&capsule), &branch) {
+        Ok(preview) => Some(preview),
+        Err(e) => {
+          eprintln!("{}", e.red());
+          nya.stop();
+          return;
+        },
+      },
+      Err(e) => {
+        eprintln!("{}", e.red());
+        nya.stop();
+        return;
+      },
+    }
+  } else {
+    None
+  };
+  let namespace = match &preview {
+    Some(preview) => {
+      println!("Shipping a preview of {} into {}", preview.branch, preview.namespace);
+      preview.namespace.clone()
+    },
+    None => capsule_namespace(&capsule),
+  };
+
+  let selection = selected_packs(&nya).await;
+  let candidates: Vec<(String, PathBuf, Option<Vec<String>>)> = packs.iter()
+    .map(|pack| (
+      pack["name"].as_str().unwrap().to_string(),
+      capsule_path.join(pack["location"].as_str().unwrap()),
+      serde_json::from_value(pack["platforms"].clone()).ok(),
+    ))
+    .filter(|(pack_name, _, _)| is_selected(&selection, pack_name))
+    .collect();
+
+  let ship_state = ShipState::load(&state_path(full_capsule_path, preview.as_ref()));
+  let forced = is_forced(&nya).await;
+  let control_plane_config = get_control_plane_config(nya.clone()).await;
+  let session = match try_create_ssh_session(&control_plane_config).await {
+    Ok(session) => session,
+    Err(e) => {
+      eprintln!("{}", format!("Could not connect to the control plane {}: {}", control_plane_config.host, e).red());
+      nya.stop();
+      return;
+    },
+  };
+  // Bases built before image tags were recorded still have a chart that ignores imageTag
+  if let Err(e) = upload_chart(&session).await {
+    eprintln!("{}", format!("Failed to upload the Nya chart: {}", e).red());
+    let _ = session.close().await;
+    nya.stop();
+    return;
+  }
+  let names: Vec<String> = candidates.iter().map(|(name, _, _)| name.clone()).collect();
+  if let Some(preview) = preview.as_ref()
+    && let Err(e) = check_preview_namespace(&nya, &control_plane_config.host, &session, preview, &capsule_namespace(&capsule)).await {
+    eprintln!("{}", e.red());
+    let _ = session.close().await;
+    nya.stop();
+    return;
+  }
+  if preview.is_none() && namespace != "default" {
+    let left_in_default = releases_left_in_default(&nya, &control_plane_config.host, &session, &names).await;
+    let refusal = match left_in_default {
+      Ok(packs) if packs.is_empty() => None,
+      Ok(packs) => Some(move_out_of_default_message(&packs, &namespace, &control_plane_config.host)),
+      Err(e) => Some(e),
+    };
+    if let Some(refusal) = refusal {
+      eprintln!("{}", refusal.red());
+      let _ = session.close().await;
+      nya.stop();
+      return;
+    }
+  }
+  let live = if forced {
+    HashMap::new()
+  } else {
+    live_tags(&nya, &control_plane_config.host, &session, &namespace, &names).await
+  };
+  let _ = session.close().await;
+
+  let build = build_config(&nya).await;
+  let (build_node, builder) = if build.is_remote() {
+    match build_node(&nya, &build).await {
+      Ok(node) => (Some(node), None),
+      Err(e) => {
+        eprintln!("{}", e.red());
+        nya.stop();
+        return;
+      },
+    }
+  } else {
+    match local_builder(&build).await {
+      Ok(builder) => (None, Some(builder)),
+      Err(e) => {
+        eprintln!("{}", e.red());
+        nya.stop();
+        return;
+      },
+    }
+  };
+
+  let cluster = build.platforms.clone()
+    .or_else(|| load_inventory().map(|inventory| cluster_platforms(&inventory)))
+    .unwrap_or_default();
+  let mut buildx_platforms: Vec<String> = Vec::new();
+
   let mut pack_ctx: Vec<PackContext> = vec![];
+  let mut reports: Vec<PackReport> = vec![];
   let mut build_tasks = vec![];
 
-  for pack in packs.iter() {
-    let pack_name = pack["name"].as_str().unwrap().to_string();
-    let pack_location = pack["location"].as_str().unwrap();
-    let full_path = capsule_path.join(pack_location);
+  for (pack_name, full_path, restriction) in candidates {
+    let pack_hash = pack_hash(&full_path);
+    if !forced && ship_state.is_unchanged(&pack_name, &pack_hash, live.get(&pack_name).map(|t| t.as_str())) {
+      println!("{}", format!("Skipping {}, unchanged since {} (use --force to ship it anyway)", pack_name, live[&pack_name]).dimmed());
+      continue;
+    }
+    let platforms = match pack_platforms(&cluster, restriction.as_deref()) {
+      Ok(platforms) => platforms,
+      Err(e) => {
+        reports.push(PackReport::failed(&pack_name, Phase::Build, e));
+        continue;
+      },
+    };
+    let builder = match builder.map(|builder| builder.for_platforms(&platforms, build.builder.is_some())).transpose() {
+      Ok(builder) => builder,
+      Err(e) => {
+        reports.push(PackReport::failed(&pack_name, Phase::Build, e));
+        continue;
+      },
+    };
+    if builder == Some(Builder::Buildx) && platforms.len() > 1 && !platforms.iter().all(|platform| buildx_platforms.contains(platform)) {
+      if let Err(e) = ensure_buildx_builder(&platforms).await {
+        reports.push(PackReport::failed(&pack_name, Phase::Build, e));
+        continue;
+      }
+      buildx_platforms.extend(platforms.iter().cloned());
+    }
     let path_str = full_path.display().to_string();
-    let image_name = format!("{}/{}:latest", registry_host, pack_name);
+    let image_tag = generate_sha(&path_str);
+    let image_name = format!("{}/{}:{}", registry_host, pack_name, image_tag);
 
     let pack_context = PackContext {
       pack_name: pack_name.clone(),
       pack_location: path_str.clone(),
       pack_image_name: image_name.clone(),
+      pack_image_tag: image_tag,
+      pack_hash,
+      namespace: namespace.clone(),
+      pushed: build_node.is_some() || builder.is_some_and(|builder| builder.pushes_on_build()),
+      builder,
+      platforms: platforms.clone(),
+      preview: preview.clone(),
     };
     pack_ctx.push(pack_context);
 
     let nya = nya.clone();
+    let build_node = build_node.clone();
+    let build = build.clone();
     let build_task = tokio::spawn({
       async move {
-        build_cmd(&path_str, &image_name, nya).await
+        let started = Instant::now();
+        let result = match build_node {
+          Some(node) => {
+            let _ = nya.trigger("log", Payload::new(format!("Building {} on {}...", image_name, node.host))).await;
+            build_remote(&nya, &node, &pack_name, &full_path, &image_name, &platforms).await
+          },
+          None => {
+            let _ = nya.trigger("log", Payload::new(format!("Building {}...", image_name))).await;
+            let builder = builder.unwrap();
+            let mut cmd = Command::new(builder.program());
+            cmd.args(builder.build_args(&image_name, &path_str, &platforms, &build))
+              .stdin(Stdio::null())
+              .stdout(Stdio::piped())
+              .stderr(Stdio::piped());
+            run_command(cmd, nya, &RetryPolicy::INSTALL).await
+          },
+        };
+        let mut report = PackReport::new(&pack_name);
+        let _ = report.record(Phase::Build, started, result);
+        report
       }
     });
     build_tasks.push(build_task);
   }
 
-  let _ = nya.set("pack_contexts", pack_ctx).await;
-  join_all(build_tasks).await;
-
-}
-
-async fn build_cmd(file_path: &str, image_name: &str, nya: Nya) {
-  let cmd_args = vec!["build", "-t", image_name, file_path];
-  let mut cmd = Command::new("docker");
-  cmd.args(cmd_args)
-    .stdin(Stdio::null())
-    .stdout(Stdio::piped())
-    .stderr(Stdio::piped());
-
-  let mut child = cmd.spawn().unwrap();
-
-  let stdout = child.stdout.take().unwrap();
-  let stderr = child.stderr.take().unwrap();
-
-  let mut out_reader = BufReader::new(stdout).lines();
-  let mut err_reader = BufReader::new(stderr).lines();
-
-  // Pump stdout
-  let out_task = tokio::spawn({
-      let nya = nya.clone();
-      async move {
-          while let Ok(Some(line)) = out_reader.next_line().await {
-            let _ = nya.trigger("log", Payload::new(line.clone())).await;
-          }
-      }
-  });
-
-  // Pump stderr
-  let err_task = tokio::spawn({
-      let nya = nya.clone();
-      async move {
-          while let Ok(Some(line)) = err_reader.next_line().await {
-              let _ = nya.trigger("log", Payload::new(line.clone())).await;
-          }
-      }
-  });
-
-  let status = child.wait().await.unwrap();
-
-  let _ = out_task.await;
-  let _ = err_task.await;
-
-  if !status.success() {
-      println!("nya:ship failed with {}", status);
+  if pack_ctx.is_empty() && reports.is_empty() {
+    println!("{}", "Every pack is up to date, nothing to ship.".green());
+  }
+  // A pack whose build failed has no image in the registry to deploy
+  for report in join_all(build_tasks).await.into_iter().flatten() {
+    if report.failure().is_some() {
+      pack_ctx.retain(|ctx| ctx.pack_name != report.pack);
+    }
+    reports.push(report);
   }
+  let _ = nya.set("pack_contexts", pack_ctx).await;
+  let _ = nya.set("ship_reports", reports).await;
 }
 
 async fn deploy_capsule(nya: Nya, _: Payload) {
-    let pack_contexts_val = nya.get("pack_contexts").await;
-    let pack_contexts: Vec<PackContext> = pack_contexts_val
-        .as_array()
-        .unwrap()
-        .iter()
-        .map(|v| serde_json::from_value(v.clone()).unwrap())
-        .collect();
+    let pack_contexts: Vec<PackContext> = serde_json::from_value(nya.get("pack_contexts").await).unwrap_or_default();
+    let mut reports: Vec<PackReport> = serde_json::from_value(nya.get("ship_reports").await).unwrap_or_default();
     
+    let build = build_config(&nya).await;
+    let domain = nya.get("nya.control_plane.vars").await["domain_name"].as_str().unwrap_or("").to_string();
     let mut deploy_tasks = vec![];
     
     for ctx in pack_contexts {
         let nya = nya.clone();
+        let build = build.clone();
+        let domain = domain.clone();
+        let mut report = match reports.iter().position(|report| report.pack == ctx.pack_name) {
+            Some(i) => reports.remove(i),
+            None => PackReport::new(&ctx.pack_name),
+        };
         let deploy_task = tokio::spawn(async move {
-            // Push image
-            push_image(&ctx.pack_image_name, nya.clone()).await;
-            
-            // Copy values
-            copy_values(&ctx, nya.clone()).await;
-            
-            // Helm deploy
-            helm_deploy(&ctx, nya.clone()).await;
+            let result = deploy_pack(&ctx, &build, &domain, &mut report, nya).await;
+            (ctx, report, result.is_ok())
         });
         deploy_tasks.push(deploy_task);
     }
     
-    join_all(deploy_tasks).await;
+    let mut shipped: Vec<PackContext> = Vec::new();
+    for (ctx, report, ok) in join_all(deploy_tasks).await.into_iter().flatten() {
+        if ok {
+            shipped.push(ctx);
+        }
+        reports.push(report);
+    }
+    reports.sort_by(|a, b| a.pack.cmp(&b.pack));
+    if !reports.is_empty() {
+        print_summary(&reports);
+    }
+    // Fails the run, so `nya ship` exits non-zero
+    if reports.iter().any(|report| report.failure().is_some()) {
+        nya.stop();
+    }
+
+    let Some(capsule_path) = nya.capsule_path() else {
+        return;
+    };
+    let shipped_at = chrono::Utc::now().to_rfc3339();
+    let preview = shipped.first().and_then(|ctx| ctx.preview.clone());
+    let ship_state_path = state_path(capsule_path, preview.as_ref());
+    let mut ship_state = ShipState::load(&ship_state_path);
+    for ctx in shipped.iter() {
+        ship_state.packs.insert(ctx.pack_name.clone(), PackState { hash: ctx.pack_hash.clone(), tag: ctx.pack_image_tag.clone(), shipped_at: shipped_at.clone() });
+    }
+    if !shipped.is_empty()
+        && let Err(e) = ship_state.save(&ship_state_path) {
+        eprintln!("Failed to save the ship state to {}: {}", ship_state_path.display(), e);
+    }
+    // The capsule's live tags are for its own namespace, not for previews
+    if preview.is_some() {
+        return;
+    }
+
+    let live: Vec<LiveTag> = shipped.into_iter()
+        .map(|ctx| LiveTag { pack: ctx.pack_name, tag: ctx.pack_image_tag, image: ctx.pack_image_name })
+        .collect();
+    if let Err(e) = record_live_tags(capsule_path, &live) {
+        eprintln!("Failed to record the shipped image tags in {}: {}", capsule_path.display(), e);
+    }
+}
+
+/// Pushes, copies the values and deploys one pack, stopping at the first
+/// phase that fails.
+async fn deploy_pack(ctx: &PackContext, build: &BuildConfig, domain: &str, report: &mut PackReport, nya: Nya) -> Result<(), String> {
+    let values: Value = fs::read_to_string(format!("{}/values.yaml", ctx.pack_location))
+        .ok()
+        .and_then(|content| serde_yaml_ng::from_str(&content).ok())
+        .unwrap_or_default();
+    let host_suffix = match ctx.preview.as_ref().map(|preview| preview.host_suffix(&ingress_hosts(&values))) {
+        Some(Ok(suffix)) => suffix,
+        Some(Err(e)) => return report.record(Phase::Helm, Instant::now(), Err(e)),
+        None => String::new(),
+    };
+    report.urls = ingress_urls(&values, &host_suffix, domain);
+
+    match ctx.builder.filter(|_| !ctx.pushed) {
+        Some(builder) => {
+            let started = Instant::now();
+            report.record(Phase::Push, started, push_image(builder, &ctx.pack_image_name, build, nya.clone()).await)?;
+        },
+        None => report.included(Phase::Push),
+    }
+    let started = Instant::now();
+    report.record(Phase::Values, started, copy_values(ctx, nya.clone()).await)?;
+    let started = Instant::now();
+    report.record(Phase::Helm, started, helm_deploy(ctx, &host_suffix, nya.clone()).await)?;
+    let started = Instant::now();
+    report.record(Phase::Rollout, started, wait_for_rollout(ctx, &deployments(&values), nya).await)
 }
 
-async fn push_image(image_name: &str, nya: Nya) {
+/// The Deployments the chart renders for a pack's components.
+fn deployments(values: &Value) -> Vec<String> {
+    let components = values.get("components").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+    components.iter().filter_map(|component| {
+        let name = component.get("name")?.as_str()?;
+        match component.get("type")?.as_str()? {
+            "frontend" => Some(name.to_string()),
+            "backend" => Some(format!("{}-api", name)),
+            _ => None,
+        }
+    }).collect()
+}
+
+async fn push_image(builder: Builder, image_name: &str, build: &BuildConfig, nya: Nya) -> Result<(), String> {
     let _ = nya.trigger("log", Payload::new(format!("Pushing {}...", image_name))).await;
     
-    let mut cmd = Command::new("docker");
-    cmd.args(["push", image_name])
+    let mut cmd = Command::new(builder.program());
+    cmd.args(builder.push_args(image_name, build))
         .stdin(Stdio::null())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
     
-    run_command(cmd, nya).await;
+    run_command(cmd, nya, &RetryPolicy::INSTALL).await
 }
 
-async fn copy_values(ctx: &PackContext, nya: Nya) {
+async fn copy_values(ctx: &PackContext, nya: Nya) -> Result<(), String> {
     let control_plane = nya.get("nya.control_plane").await;
     let host = control_plane["host"].as_str().unwrap();
     let user = control_plane["user"].as_str().unwrap();
@@ -170,18 +438,33 @@ async fn copy_values(ctx: &PackContext, nya: Nya) {
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
     
-    run_command(cmd, nya).await;
+    run_command(cmd, nya, &RetryPolicy::COMMAND).await
 }
 
-async fn helm_deploy(ctx: &PackContext, nya: Nya) {
-    let base_vars = nya.get("nya.control_plane.vars").await;
+/// Runs a command on the control plane with the local `ssh`.
+async fn control_plane_command(nya: &Nya, remote_cmd: &str) -> Command {
     let control_plane = nya.get("nya.control_plane").await;
-    let registry_host_value = nya.get("nya.registry_host").await;
-    
     let host = control_plane["host"].as_str().unwrap();
     let user = control_plane["user"].as_str().unwrap();
     let ssh_key = control_plane["ssh_private_key_file"].as_str().unwrap();
     let ssh_key = shellexpand::tilde(ssh_key).to_string();
+
+    let mut cmd = Command::new("ssh");
+    cmd.args([
+        "-i", &ssh_key,
+        "-o", "StrictHostKeyChecking=no",
+        &format!("{}@{}", user, host),
+        remote_cmd
+    ])
+    .stdin(Stdio::null())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+    cmd
+}
+
+async fn helm_deploy(ctx: &PackContext, host_suffix: &str, nya: Nya) -> Result<(), String> {
+    let base_vars = nya.get("nya.control_plane.vars").await;
+    let registry_host_value = nya.get("nya.registry_host").await;
     
     let registry_host = registry_host_value.as_str().unwrap();
     let domain = base_vars["domain_name"].as_str().unwrap();
@@ -189,46 +472,93 @@ async fn helm_deploy(ctx: &PackContext, nya: Nya) {
 
     
     let values_path = format!("/tmp/{}-values.yaml", ctx.pack_name);
+    // A new tag rolls the pods by itself. A -dirty tag can be rebuilt under
+    // the same name, so those still force a restart.
+    let mut extra_sets = if ctx.pack_image_tag.ends_with("-dirty") {
+        format!("--set podAnnotations.deployedAt='{}' ", chrono::Utc::now().timestamp())
+    } else {
+        String::new()
+    };
+    let mut prepare = prepare_namespace_command(&ctx.namespace, secret_name);
+    if let Some(preview) = ctx.preview.as_ref() {
+        let capsule_namespace = capsule_namespace(&nya.get("capsule").await);
+        prepare = format!("{} && {}", prepare, preview.label_command(&capsule_namespace));
+        extra_sets.push_str(&format!("--set hostSuffix={} ", host_suffix));
+    }
+    // Keep the pods off nodes the image wasn't built for
+    if !ctx.platforms.is_empty() {
+        let mut archs: Vec<&str> = ctx.platforms.iter().map(|platform| node_arch(platform)).collect();
+        archs.dedup();
+        extra_sets.push_str(&format!("--set 'architectures={{{}}}' ", archs.join(",")));
+    }
     let helm_cmd = format!(
-        "helm upgrade --install {} /opt/nya/charts -f {} \
+        "{} && helm upgrade --install {} /opt/nya/charts -f {} \
+        --namespace {} \
         --set image.name={} \
+        --set image.tag={} \
         --set registry_host={} \
         --set domain={} \
         --set secret_name={} \
-        --set podAnnotations.deployedAt='{}' \
-        --kubeconfig=/etc/rancher/k3s/k3s.yaml&& \
-        kubectl rollout status deployment/{} --timeout=120s --kubeconfig=/etc/rancher/k3s/k3s.yaml",
+        --description '{}' \
+        {}{}",
+        prepare,
         ctx.pack_name, values_path,
+        ctx.namespace,
         ctx.pack_name,
+        ctx.pack_image_tag,
         registry_host, domain, secret_name,
-        chrono::Utc::now().timestamp(),  // ← Forces pod recreation
-        ctx.pack_name
+        ship_description(&ctx.pack_image_tag),
+        extra_sets, KUBECONFIG,
     );
     
     let _ = nya.trigger("log", Payload::new(format!("Deploying {}...", ctx.pack_name))).await;
-    
-    let mut cmd = Command::new("ssh");
-    cmd.args([
-        "-i", &ssh_key,
-        "-o", "StrictHostKeyChecking=no",
-        &format!("{}@{}", user, host),
-        &helm_cmd
-    ])
-    .stdin(Stdio::null())
-    .stdout(Stdio::piped())
-    .stderr(Stdio::piped());
-    
-    run_command(cmd, nya).await;
+    let cmd = control_plane_command(&nya, &helm_cmd).await;
+    run_command(cmd, nya, &RetryPolicy::COMMAND).await
+}
+
+/// Waits for the pack's Deployments to finish rolling out.
+async fn wait_for_rollout(ctx: &PackContext, deployments: &[String], nya: Nya) -> Result<(), String> {
+    if deployments.is_empty() {
+        return Ok(());
+    }
+    let rollout_cmd = deployments.iter()
+        .map(|deployment| format!("kubectl rollout status deployment/{} -n {} --timeout=120s {}", deployment, ctx.namespace, KUBECONFIG))
+        .collect::<Vec<_>>()
+        .join(" && ");
+
+    let _ = nya.trigger("log", Payload::new(format!("Waiting for {} to roll out...", ctx.pack_name))).await;
+    let cmd = control_plane_command(&nya, &rollout_cmd).await;
+    run_command(cmd, nya, &RetryPolicy::WAIT).await
 }
 
 // Reuse this for all commands
-async fn run_command(mut cmd: Command, nya: Nya) {
+async fn run_command(mut cmd: Command, nya: Nya, policy: &RetryPolicy) -> Result<(), String> {
     // CRITICAL: Pass through SSH agent socket
     if let Ok(ssh_auth_sock) = env::var("SSH_AUTH_SOCK") {
       cmd.env("SSH_AUTH_SOCK", ssh_auth_sock);
     }
+    cmd.kill_on_drop(true);
+
+    let mut attempt = 1;
+    loop {
+        match run_command_once(&mut cmd, nya.clone(), policy).await {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt < policy.attempts && is_transient(&err) => {
+                let delay = policy.delay(attempt);
+                let _ = nya.trigger("log", Payload::new(format!("Transient failure, retrying in {}s ({}/{})", delay.as_secs(), attempt, policy.attempts))).await;
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            },
+            Err(err) => {
+                let _ = nya.trigger("log", Payload::new(format!("Command failed: {}", err))).await;
+                return Err(err);
+            },
+        }
+    }
+}
 
-    let mut child = cmd.spawn().unwrap();
+async fn run_command_once(cmd: &mut Command, nya: Nya, policy: &RetryPolicy) -> Result<(), String> {
+    let mut child = cmd.spawn().map_err(|e| e.to_string())?;
     
     let stdout = child.stdout.take().unwrap();
     let stderr = child.stderr.take().unwrap();
@@ -248,13 +578,42 @@ async fn run_command(mut cmd: Command, nya: Nya) {
     let err_task = tokio::spawn({
         let nya = nya.clone();
         async move {
+            let mut err_lines: Vec<String> = Vec::new();
             while let Ok(Some(line)) = err_reader.next_line().await {
+                err_lines.push(line.clone());
                 let _ = nya.trigger("log", Payload::new(line)).await;
             }
+            err_lines
         }
     });
     
-    let _ = child.wait().await;
+    let status = match tokio::time::timeout(policy.timeout, child.wait()).await {
+        Ok(status) => status.map_err(|e| e.to_string())?,
+        Err(_) => {
+            let _ = child.kill().await;
+            return Err(format!("timed out after {}s", policy.timeout.as_secs()));
+        },
+    };
     let _ = out_task.await;
-    let _ = err_task.await;
-}
\ No newline at end of file
+    let err_lines = err_task.await.unwrap_or_default();
+    if !status.success() {
+        if err_lines.is_empty() {
+            return Err(format!("exited with {}", status));
+        }
+        return Err(err_lines.join("\n"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod ship_tests {
+  use crate::ops::ship::is_selected;
+
+  #[test]
+  fn an_empty_selection_ships_every_pack() {
+    assert!(is_selected(&[], "web"));
+    let selection = vec!["api".to_string(), "web".to_string()];
+    assert!(is_selected(&selection, "web"));
+    assert!(!is_selected(&selection, "worker"));
+  }
+}
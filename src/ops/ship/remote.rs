@@ -0,0 +1,115 @@
+use std::path::Path;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{core::runtime::Nya, ops::{retry::RetryPolicy, types::BaseNodeConfig, upload::{UploadOptions, upload_file}, utils::{get_base_nodes, get_control_plane_config, get_from_node, stream_from_node, try_create_ssh_session}}};
+use super::{builder::{BINFMT_IMAGE, BUILDX_BUILDER, BuildConfig, node_arch}, state::context_files};
+
+/// The node remote builds run on.
+pub async fn build_node(nya: &Nya, config: &BuildConfig) -> Result<BaseNodeConfig, String> {
+  match &config.host {
+    None => Ok(get_control_plane_config(nya.clone()).await),
+    Some(host) => get_base_nodes(nya.clone()).await
+      .into_iter()
+      .find(|node| node.host == *host)
+      .ok_or_else(|| format!("`nya.build.host` is {}, which is not a node in the base config", host)),
+  }
+}
+
+/// Packs the build context into a gzipped tarball, leaving out what
+/// `.dockerignore` excludes so it isn't uploaded only to be ignored.
+async fn context_tarball(pack_dir: &Path) -> Result<Vec<u8>, String> {
+  let files = context_files(pack_dir);
+  let archive = tempfile::NamedTempFile::new().map_err(|e| e.to_string())?;
+  let mut tar = Command::new("tar")
+    .arg("-czf").arg(archive.path())
+    .arg("-C").arg(pack_dir)
+    .args(["-T", "-"])
+    .stdin(std::process::Stdio::piped())
+    .stderr(std::process::Stdio::piped())
+    .spawn()
+    .map_err(|e| format!("could not run tar: {}", e))?;
+  let mut stdin = tar.stdin.take().unwrap();
+  stdin.write_all(files.join("\n").as_bytes()).await.map_err(|e| e.to_string())?;
+  drop(stdin);
+  let output = tar.wait_with_output().await.map_err(|e| e.to_string())?;
+  if !output.status.success() {
+    return Err(format!("could not pack the build context: {}", String::from_utf8_lossy(&output.stderr).trim()));
+  }
+  std::fs::read(archive.path()).map_err(|e| e.to_string())
+}
+
+/// The commands that unpack, build and push a pack on the build node, then
+/// clean up the unpacked context. The archive is kept so a retry can unpack
+/// it again. More than one platform builds with buildx, which pushes the
+/// manifest list itself.
+fn remote_build_command(build_dir: &str, archive: &str, image_name: &str, platforms: &[String]) -> String {
+  let build = match platforms {
+    [] => format!("sudo docker build -t {0} {1} && sudo docker push {0}", image_name, build_dir),
+    [platform] => format!("sudo docker build --platform {2} -t {0} {1} && sudo docker push {0}", image_name, build_dir, platform),
+    _ => format!(
+      "(sudo docker buildx inspect {3} > /dev/null 2>&1 || sudo docker buildx create --name {3} --driver docker-container --driver-opt network=host) && \
+      {4} && \
+      sudo docker buildx build --builder {3} --platform {2} -t {0} --output type=image,name={0},push=true,registry.insecure=true {1}",
+      image_name, build_dir, platforms.join(","), BUILDX_BUILDER, emulation_command(platforms),
+    ),
+  };
+  format!(
+    "rm -rf {0} && mkdir -p {0} && tar -xzf {1} -C {0} && {2}; \
+    status=$?; rm -rf {0}; exit $status",
+    build_dir, archive, build,
+  )
+}
+
+/// Installs QEMU emulators on the build node for the platforms the buildx
+/// builder doesn't list, then restarts the builder so it picks them up.
+fn emulation_command(platforms: &[String]) -> String {
+  let checks: String = platforms.iter()
+    .map(|platform| format!("case \",$supported,\" in *\",{},\"*) ;; *) missing=\"$missing,{}\" ;; esac; ", platform, node_arch(platform)))
+    .collect();
+  format!(
+    "supported=$(sudo docker buildx inspect --bootstrap {0} | sed -n 's/^Platforms: *//p' | tr -d ' *' | tr '\\n' ','); missing=''; {1}\
+    if [ -n \"$missing\" ]; then sudo docker run --privileged --rm {2} --install \"${{missing#,}}\" && sudo docker buildx stop {0}; fi",
+    BUILDX_BUILDER, checks, BINFMT_IMAGE,
+  )
+}
+
+/// Uploads a pack's context to the build node, builds the image there and
+/// pushes it to the registry from inside the cluster network.
+pub async fn build_remote(nya: &Nya, node: &BaseNodeConfig, pack_name: &str, pack_dir: &Path, image_name: &str, platforms: &[String]) -> Result<(), String> {
+  let tarball = context_tarball(pack_dir).await?;
+  let session = try_create_ssh_session(node).await?;
+  let build_dir = format!("/tmp/nya-build/{}", pack_name);
+  let archive = format!("/tmp/nya-build/{}.tar.gz", pack_name);
+  let options = UploadOptions { sudo: false, ..Default::default() };
+
+  let result = match upload_file(&session, &tarball, &archive, &options).await {
+    Ok(_) => stream_from_node(nya, &node.host, &session, &remote_build_command(&build_dir, &archive, image_name, platforms), &RetryPolicy::INSTALL).await.map(|_| ()),
+    Err(e) => Err(format!("could not upload the build context: {}", e)),
+  };
+  // Only once every attempt is done, since each one unpacks the archive again
+  let _ = get_from_node(nya, &node.host, &session, &format!("rm -f {}", archive)).await;
+  let _ = session.close().await;
+  result
+}
+
+#[cfg(test)]
+mod remote_tests {
+  use crate::ops::ship::remote::remote_build_command;
+
+  #[test]
+  fn builds_and_pushes_then_cleans_up() {
+    let command = remote_build_command("/tmp/nya-build/web", "/tmp/nya-build/web.tar.gz", "10.0.0.7:5000/web:1a2b3c4", &[]);
+    assert!(command.contains("tar -xzf /tmp/nya-build/web.tar.gz -C /tmp/nya-build/web"));
+    assert!(command.contains("sudo docker push 10.0.0.7:5000/web:1a2b3c4"));
+    assert!(command.ends_with("rm -rf /tmp/nya-build/web; exit $status"));
+    assert!(!command.contains("rm -rf /tmp/nya-build/web /tmp/nya-build/web.tar.gz"));
+
+    let platforms = ["linux/amd64".to_string(), "linux/arm64".to_string()];
+    let command = remote_build_command("/tmp/nya-build/web", "/tmp/nya-build/web.tar.gz", "10.0.0.7:5000/web:1a2b3c4", &platforms);
+    assert!(command.contains("--platform linux/amd64,linux/arm64"));
+    assert!(command.contains("push=true"));
+    assert!(!command.contains("sudo docker push"));
+    assert!(command.contains(r#"*",linux/arm64,"*) ;; *) missing="$missing,arm64" ;; esac;"#));
+    assert!(command.contains(r#"sudo docker run --privileged --rm tonistiigi/binfmt --install "${missing#,}" && sudo docker buildx stop nya; fi"#));
+  }
+}
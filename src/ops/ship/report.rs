@@ -0,0 +1,200 @@
+use std::time::Instant;
+use colored::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The steps a pack goes through on every ship, in order.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+  Build,
+  Push,
+  Values,
+  Helm,
+  Rollout,
+}
+
+pub const PHASES: [Phase; 5] = [Phase::Build, Phase::Push, Phase::Values, Phase::Helm, Phase::Rollout];
+
+impl Phase {
+  pub fn label(&self) -> &'static str {
+    match self {
+      Phase::Build => "build",
+      Phase::Push => "push",
+      Phase::Values => "values copy",
+      Phase::Helm => "helm",
+      Phase::Rollout => "rollout",
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PhaseStatus {
+  Ok,
+  Failed,
+  /// An earlier phase failed.
+  Skipped,
+  /// Done as part of another phase, like a buildx push.
+  Included,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PhaseResult {
+  pub phase: Phase,
+  pub status: PhaseStatus,
+  pub seconds: f32,
+  #[serde(default)]
+  pub error: Option<String>,
+}
+
+/// How far a pack got in this ship.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PackReport {
+  pub pack: String,
+  pub phases: Vec<PhaseResult>,
+  #[serde(default)]
+  pub urls: Vec<String>,
+}
+
+impl PackReport {
+  pub fn new(pack: &str) -> Self {
+    Self { pack: pack.to_string(), phases: Vec::new(), urls: Vec::new() }
+  }
+
+  /// A pack that failed before its phase could start.
+  pub fn failed(pack: &str, phase: Phase, error: String) -> Self {
+    let mut report = Self::new(pack);
+    report.phases.push(PhaseResult { phase, status: PhaseStatus::Failed, seconds: 0.0, error: Some(error) });
+    report
+  }
+
+  /// Records a phase that ran, passing its result on so callers can `?` it.
+  pub fn record(&mut self, phase: Phase, started: Instant, result: Result<(), String>) -> Result<(), String> {
+    let (status, error) = match &result {
+      Ok(()) => (PhaseStatus::Ok, None),
+      Err(e) => (PhaseStatus::Failed, Some(e.clone())),
+    };
+    self.phases.push(PhaseResult { phase, status, seconds: started.elapsed().as_secs_f32(), error });
+    result
+  }
+
+  pub fn included(&mut self, phase: Phase) {
+    self.phases.push(PhaseResult { phase, status: PhaseStatus::Included, seconds: 0.0, error: None });
+  }
+
+  /// Phases that never ran count as skipped.
+  pub fn status(&self, phase: Phase) -> PhaseStatus {
+    self.phases.iter().find(|result| result.phase == phase).map(|result| result.status).unwrap_or(PhaseStatus::Skipped)
+  }
+
+  pub fn failure(&self) -> Option<&PhaseResult> {
+    self.phases.iter().find(|result| result.status == PhaseStatus::Failed)
+  }
+
+  pub fn seconds(&self) -> f32 {
+    self.phases.iter().map(|result| result.seconds).sum()
+  }
+}
+
+/// The addresses of the components in a pack's `values.yaml` that have an
+/// ingress. The chart serves backends under `<host>-api`, and previews add
+/// their branch after that.
+pub fn ingress_urls(values: &Value, host_suffix: &str, domain: &str) -> Vec<String> {
+  let components = values.get("components").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+  let mut urls: Vec<String> = components.iter().filter_map(|component| {
+    let host = ingress_host(component)?;
+    let scheme = match component.pointer("/tls/enabled").and_then(|v| v.as_bool()) {
+      Some(true) => "https",
+      _ => "http",
+    };
+    Some(format!("{}://{}{}.{}", scheme, host, host_suffix, domain))
+  }).collect();
+  urls.dedup();
+  urls
+}
+
+/// The first labels of the ingress hosts in a pack's `values.yaml`, before
+/// a preview's suffix.
+pub fn ingress_hosts(values: &Value) -> Vec<String> {
+  let components = values.get("components").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+  components.iter().filter_map(ingress_host).collect()
+}
+
+fn ingress_host(component: &Value) -> Option<String> {
+  let host = component.pointer("/ingress/host")?.as_str()?;
+  match component.get("type").and_then(|v| v.as_str()) {
+    Some("backend") => Some(format!("{}-api", host)),
+    _ => Some(host.to_string()),
+  }
+}
+
+fn status_label(status: PhaseStatus) -> ColoredString {
+  match status {
+    PhaseStatus::Ok => "ok".green(),
+    PhaseStatus::Failed => "failed".red(),
+    PhaseStatus::Skipped => "skipped".dimmed(),
+    PhaseStatus::Included => "-".dimmed(),
+  }
+}
+
+pub fn print_summary(reports: &[PackReport]) {
+  println!("{}", "Ship summary".bold());
+  println!("  {:<20} {:<8} {:<8} {:<8} {:<8} {:<8} {:<8} URL", "PACK", "BUILD", "PUSH", "VALUES", "HELM", "ROLLOUT", "TIME");
+  for report in reports.iter() {
+    let statuses: Vec<ColoredString> = PHASES.iter().map(|phase| status_label(report.status(*phase))).collect();
+    let url = match report.failure() {
+      Some(_) => "-".to_string(),
+      None => report.urls.first().cloned().unwrap_or_else(|| "-".to_string()),
+    };
+    println!(
+      "  {:<20} {:<8} {:<8} {:<8} {:<8} {:<8} {:<8} {}",
+      report.pack, statuses[0], statuses[1], statuses[2], statuses[3], statuses[4], format!("{:.1}s", report.seconds()), url,
+    );
+    if report.failure().is_none() {
+      for url in report.urls.iter().skip(1) {
+        println!("  {:<20} {:<8} {:<8} {:<8} {:<8} {:<8} {:<8} {}", "", "", "", "", "", "", "", url);
+      }
+    }
+  }
+
+  let failed: Vec<&PackReport> = reports.iter().filter(|report| report.failure().is_some()).collect();
+  if failed.is_empty() {
+    println!("{}", format!("Shipped {} pack(s).", reports.len()).green());
+    return;
+  }
+  eprintln!("{}", format!("{} of {} pack(s) failed:", failed.len(), reports.len()).red());
+  for report in failed {
+    let failure = report.failure().unwrap();
+    let error = failure.error.as_deref().unwrap_or("").lines().last().unwrap_or("").trim();
+    eprintln!("  - {} failed at {}: {}", report.pack, failure.phase.label(), error);
+  }
+}
+
+#[cfg(test)]
+mod report_tests {
+  use std::time::Instant;
+  use serde_json::json;
+  use crate::ops::ship::report::{PackReport, Phase, PhaseStatus, ingress_urls};
+
+  #[test]
+  fn later_phases_count_as_skipped_after_a_failure() {
+    let mut report = PackReport::new("web");
+    assert!(report.record(Phase::Build, Instant::now(), Ok(())).is_ok());
+    report.included(Phase::Push);
+    assert!(report.record(Phase::Values, Instant::now(), Err("scp: connection refused".to_string())).is_err());
+    assert_eq!(report.status(Phase::Push), PhaseStatus::Included);
+    assert_eq!(report.status(Phase::Values), PhaseStatus::Failed);
+    assert_eq!(report.status(Phase::Helm), PhaseStatus::Skipped);
+    assert_eq!(report.failure().unwrap().phase, Phase::Values);
+  }
+
+  #[test]
+  fn lists_an_url_per_ingress() {
+    let values = json!({ "components": [
+      { "name": "web", "type": "frontend", "ingress": { "host": "web" }, "tls": { "enabled": true } },
+      { "name": "web", "type": "backend", "ingress": { "host": "web" } },
+      { "name": "jobs", "type": "worker" },
+    ]});
+    assert_eq!(ingress_urls(&values, "", "nya.local"), ["https://web.nya.local", "http://web-api.nya.local"]);
+    assert_eq!(ingress_urls(&values, "-feature-x", "nya.local")[1], "http://web-api-feature-x.nya.local");
+  }
+}
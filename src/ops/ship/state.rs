@@ -0,0 +1,214 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Docker sends these with the build context whatever `.dockerignore` says.
+const ALWAYS_INCLUDED: [&str; 2] = ["Dockerfile", ".dockerignore"];
+
+/// What the last successful ship of a pack was built from.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PackState {
+  pub hash: String,
+  pub tag: String,
+  pub shipped_at: String,
+}
+
+/// `.nya/ship_state.json` next to the capsule file, keyed by pack name.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ShipState {
+  pub packs: HashMap<String, PackState>,
+}
+
+impl ShipState {
+  pub fn path(capsule_path: &Path) -> PathBuf {
+    capsule_path.with_file_name("ship_state.json")
+  }
+
+  /// Previews keep their own state in `.nya/previews/<namespace>.json`.
+  pub fn preview_path(capsule_path: &Path, namespace: &str) -> PathBuf {
+    capsule_path.with_file_name("previews").join(format!("{}.json", namespace))
+  }
+
+  pub fn load(path: &Path) -> Self {
+    fs::read_to_string(path)
+      .ok()
+      .and_then(|content| serde_json::from_str(&content).ok())
+      .unwrap_or_default()
+  }
+
+  pub fn save(&self, path: &Path) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    fs::write(path, serde_json::to_string_pretty(self).unwrap()).map_err(|e| e.to_string())
+  }
+
+  /// A pack can be skipped when it hashes the same as its last ship and that
+  /// ship's tag is still what the cluster runs.
+  pub fn is_unchanged(&self, pack: &str, hash: &str, live_tag: Option<&str>) -> bool {
+    self.packs.get(pack).is_some_and(|state| state.hash == hash && live_tag == Some(state.tag.as_str()))
+  }
+}
+
+/// The patterns in a `.dockerignore`, matched the way Docker matches them:
+/// relative to the context root, `**` spans directories, a leading `!`
+/// re-includes, and the last matching pattern wins.
+pub struct DockerIgnore {
+  patterns: Vec<(Regex, bool)>,
+}
+
+impl DockerIgnore {
+  pub fn parse(content: &str) -> Self {
+    let patterns = content
+      .lines()
+      .map(|line| line.trim())
+      .filter(|line| !line.is_empty() && !line.starts_with('#'))
+      .filter_map(|line| {
+        let (pattern, include) = match line.strip_prefix('!') {
+          Some(rest) => (rest.trim(), true),
+          None => (line, false),
+        };
+        let pattern = pattern.trim_start_matches("./").trim_start_matches('/').trim_end_matches('/');
+        Regex::new(&pattern_to_regex(pattern)).ok().map(|regex| (regex, include))
+      })
+      .collect();
+    Self { patterns }
+  }
+
+  /// A path is excluded when it or one of its parent directories matches.
+  pub fn is_excluded(&self, relative_path: &str) -> bool {
+    let mut excluded = false;
+    for (regex, include) in self.patterns.iter() {
+      let matches = regex.is_match(relative_path) || parents(relative_path).any(|parent| regex.is_match(parent));
+      if matches {
+        excluded = !include;
+      }
+    }
+    excluded
+  }
+}
+
+fn parents(path: &str) -> impl Iterator<Item = &str> {
+  path.match_indices('/').map(move |(i, _)| &path[..i])
+}
+
+fn pattern_to_regex(pattern: &str) -> String {
+  let mut regex = String::from("^");
+  let chars: Vec<char> = pattern.chars().collect();
+  let mut i = 0;
+  while i < chars.len() {
+    match chars[i] {
+      '*' if chars.get(i + 1) == Some(&'*') => {
+        // `**/` matches zero or more directories, a trailing `**` everything below
+        if chars.get(i + 2) == Some(&'/') {
+          regex.push_str("(?:.*/)?");
+          i += 3;
+        } else {
+          regex.push_str(".*");
+          i += 2;
+        }
+        continue;
+      },
+      '*' => regex.push_str("[^/]*"),
+      '?' => regex.push_str("[^/]"),
+      c => regex.push_str(&regex::escape(&c.to_string())),
+    }
+    i += 1;
+  }
+  regex.push('$');
+  regex
+}
+
+fn collect_files(root: &Path, dir: &Path, ignore: &DockerIgnore, files: &mut Vec<String>) {
+  let Ok(entries) = fs::read_dir(dir) else {
+    return;
+  };
+  for entry in entries.flatten() {
+    let path = entry.path();
+    let relative = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+    if ignore.is_excluded(&relative) && !ALWAYS_INCLUDED.contains(&relative.as_str()) {
+      continue;
+    }
+    if path.is_dir() {
+      collect_files(root, &path, ignore, files);
+    } else {
+      files.push(relative);
+    }
+  }
+}
+
+/// The files Docker sends as the pack's build context, relative to the pack.
+pub fn context_files(pack_dir: &Path) -> Vec<String> {
+  let ignore = DockerIgnore::parse(&fs::read_to_string(pack_dir.join(".dockerignore")).unwrap_or_default());
+  let mut files = Vec::new();
+  collect_files(pack_dir, pack_dir, &ignore, &mut files);
+  files.sort();
+  files
+}
+
+/// A SHA-256 over the pack's build context plus its `values.yaml`, which
+/// Helm reads even when Docker ignores it.
+pub fn pack_hash(pack_dir: &Path) -> String {
+  let mut files = context_files(pack_dir);
+  if !files.iter().any(|file| file == "values.yaml") {
+    files.push("values.yaml".to_string());
+  }
+  files.sort();
+
+  let mut hasher = Sha256::new();
+  for file in files.iter() {
+    hasher.update(file.as_bytes());
+    hasher.update([0]);
+    hasher.update(fs::read(pack_dir.join(file)).unwrap_or_default());
+    hasher.update([0]);
+  }
+  format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod state_tests {
+  use std::fs;
+  use crate::ops::ship::state::{DockerIgnore, PackState, ShipState, pack_hash};
+
+  #[test]
+  fn matches_dockerignore_patterns_like_docker() {
+    let ignore = DockerIgnore::parse("# deps\nnode_modules\n**/*.log\n*.md\n!README.md\n/tmp/\n");
+    assert!(ignore.is_excluded("node_modules"));
+    assert!(ignore.is_excluded("node_modules/react/index.js"));
+    assert!(ignore.is_excluded("logs/app/debug.log"));
+    assert!(ignore.is_excluded("debug.log"));
+    assert!(ignore.is_excluded("CHANGELOG.md"));
+    assert!(!ignore.is_excluded("README.md"));
+    assert!(!ignore.is_excluded("docs/guide.md"));
+    assert!(ignore.is_excluded("tmp/cache"));
+    assert!(!ignore.is_excluded("src/main.rs"));
+  }
+
+  #[test]
+  fn ignored_files_do_not_change_the_hash() {
+    let dir = std::env::temp_dir().join(format!("nya-pack-hash-{}", std::process::id()));
+    fs::create_dir_all(dir.join("node_modules")).unwrap();
+    fs::write(dir.join("Dockerfile"), "FROM nginx").unwrap();
+    fs::write(dir.join("values.yaml"), "components: []").unwrap();
+    fs::write(dir.join(".dockerignore"), "node_modules\nvalues.yaml\n").unwrap();
+    let first = pack_hash(&dir);
+
+    fs::write(dir.join("node_modules/dep.js"), "changed").unwrap();
+    assert_eq!(first, pack_hash(&dir));
+
+    fs::write(dir.join("values.yaml"), "components: [{name: web}]").unwrap();
+    assert_ne!(first, pack_hash(&dir));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn skips_only_when_the_hash_and_live_tag_match() {
+    let mut state = ShipState::default();
+    state.packs.insert("web".to_string(), PackState { hash: "abc".to_string(), tag: "1a2b3c4".to_string(), shipped_at: String::new() });
+    assert!(state.is_unchanged("web", "abc", Some("1a2b3c4")));
+    assert!(!state.is_unchanged("web", "abc", Some("9f8e7d6")));
+    assert!(!state.is_unchanged("web", "def", Some("1a2b3c4")));
+    assert!(!state.is_unchanged("api", "abc", None));
+  }
+}
@@ -1,6 +1,6 @@
 use serde_json::Value;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BaseNodeConfig {
   pub host: String,
   pub user: String,
@@ -26,4 +26,72 @@ pub struct ClusterBind9Context {
   pub control_plane_ip: String,
   pub network_cidr: String,
   pub domain_name: String,
-}
\ No newline at end of file
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct AddonConfig {
+  pub name: String,
+  pub chart: Option<String>,
+  pub repo: Option<String>,
+  pub path: Option<String>,
+  pub version: Option<String>,
+  #[serde(default = "default_addon_namespace")]
+  pub namespace: String,
+  pub values_file: Option<String>,
+  pub values: Option<Value>,
+}
+
+fn default_addon_namespace() -> String {
+  "default".to_string()
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct DestroyOptions {
+  pub only: Vec<String>,
+  pub keep: Vec<String>,
+  pub node: Option<String>,
+  pub yes: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct RemovalResult {
+  pub component: String,
+  pub status: String,
+  pub detail: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct CloudInitOptions {
+  pub host: String,
+  pub output: Option<std::path::PathBuf>,
+  pub hostname: Option<String>,
+  pub docker: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct ExecOptions {
+  pub node: Option<String>,
+  pub role: Option<String>,
+  pub all: bool,
+  pub command: Vec<String>,
+  pub script: Option<std::path::PathBuf>,
+  pub sudo: bool,
+  pub concurrency: usize,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct CapsuleDeleteOptions {
+  pub yes: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct PreviewOptions {
+  pub yes: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct ReleaseOptions {
+  pub pack: String,
+  /// Revision to roll back to, the one before the live revision when unset.
+  pub to: Option<u32>,
+}
@@ -0,0 +1,229 @@
+use std::{collections::HashMap, fs, path::Path};
+use include_dir::Dir;
+use openssh::{Session, Stdio};
+use openssh_sftp_client::{Sftp, SftpOptions};
+use sha2::{Digest, Sha256};
+
+/// How an uploaded file ends up on the node.
+#[derive(Clone, Debug)]
+pub struct UploadOptions {
+  pub mode: String,
+  /// `user` or `user:group`, only applied with `sudo`.
+  pub owner: Option<String>,
+  /// Move the file into place as root, for paths the SSH user can't write.
+  pub sudo: bool,
+  /// For directory uploads, delete remote files that aren't in the upload.
+  pub prune: bool,
+}
+
+impl Default for UploadOptions {
+  fn default() -> Self {
+    Self { mode: "0644".to_string(), owner: None, sudo: true, prune: false }
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum UploadResult {
+  Uploaded,
+  Unchanged,
+}
+
+/// Counts for a directory upload.
+#[derive(Debug, Default, PartialEq)]
+pub struct TreeUploadResult {
+  pub uploaded: usize,
+  pub unchanged: usize,
+  pub removed: usize,
+}
+
+pub fn sha256_hex(content: &[u8]) -> String {
+  format!("{:x}", Sha256::digest(content))
+}
+
+/// Single-quotes a value for the remote shell.
+fn quote(value: &str) -> String {
+  format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Uploads `content` to `remote_path`, skipping it when the remote file already has the same checksum.
+pub async fn upload_file(session: &Session, content: &[u8], remote_path: &str, options: &UploadOptions) -> Result<UploadResult, String> {
+  let sudo = if options.sudo { "sudo " } else { "" };
+  let remote_sum = run(session, &format!("{}sha256sum {} 2>/dev/null || true", sudo, quote(remote_path))).await?;
+  if remote_sum.split_whitespace().next() == Some(sha256_hex(content).as_str()) {
+    return Ok(UploadResult::Unchanged);
+  }
+  put(session, content, remote_path, options).await?;
+  Ok(UploadResult::Uploaded)
+}
+
+/// Uploads every file under `local_dir` into `remote_dir`, keeping the layout.
+pub async fn upload_dir(session: &Session, local_dir: &Path, remote_dir: &str, options: &UploadOptions) -> Result<TreeUploadResult, String> {
+  let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+  read_local_tree(local_dir, "", &mut files)?;
+  upload_files(session, files, remote_dir, options).await
+}
+
+/// Uploads a directory embedded in the binary with `include_dir!`.
+pub async fn upload_embedded_dir(session: &Session, dir: &Dir<'_>, remote_dir: &str, options: &UploadOptions) -> Result<TreeUploadResult, String> {
+  let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+  read_embedded_tree(dir, &mut files);
+  upload_files(session, files, remote_dir, options).await
+}
+
+fn read_local_tree(dir: &Path, prefix: &str, files: &mut Vec<(String, Vec<u8>)>) -> Result<(), String> {
+  let entries = fs::read_dir(dir).map_err(|e| format!("failed to read directory '{}': {}", dir.display(), e))?;
+  for entry in entries {
+    let entry = entry.map_err(|e| e.to_string())?;
+    let path = entry.path();
+    let relative = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+    if path.is_dir() {
+      read_local_tree(&path, &format!("{}/", relative), files)?;
+    } else {
+      let content = fs::read(&path).map_err(|e| format!("failed to read '{}': {}", path.display(), e))?;
+      files.push((relative, content));
+    }
+  }
+  Ok(())
+}
+
+fn read_embedded_tree(dir: &Dir<'_>, files: &mut Vec<(String, Vec<u8>)>) {
+  for file in dir.files() {
+    files.push((file.path().display().to_string(), file.contents().to_vec()));
+  }
+  for subdir in dir.dirs() {
+    read_embedded_tree(subdir, files);
+  }
+}
+
+/// Uploads files given as (path relative to `remote_dir`, content). The remote
+/// tree is checksummed in one command and only files that differ are sent.
+pub async fn upload_files(session: &Session, files: Vec<(String, Vec<u8>)>, remote_dir: &str, options: &UploadOptions) -> Result<TreeUploadResult, String> {
+  let sudo = if options.sudo { "sudo " } else { "" };
+  let remote_dir = remote_dir.trim_end_matches('/');
+  let listing = run(session, &format!(
+    "{}find {} -type f -exec sha256sum {{}} + 2>/dev/null || true",
+    sudo, quote(remote_dir)
+  )).await?;
+  let remote_sums: HashMap<String, String> = parse_sha256sums(&listing, remote_dir);
+
+  let mut result = TreeUploadResult::default();
+  for (relative, content) in files.iter() {
+    if remote_sums.get(relative).is_some_and(|sum| *sum == sha256_hex(content)) {
+      result.unchanged += 1;
+      continue;
+    }
+    let remote_path = format!("{}/{}", remote_dir, relative);
+    put(session, content, &remote_path, options).await?;
+    result.uploaded += 1;
+  }
+
+  if options.prune {
+    let stale: Vec<String> = remote_sums.keys()
+      .filter(|path| !files.iter().any(|(relative, _)| relative == *path))
+      .map(|path| quote(&format!("{}/{}", remote_dir, path)))
+      .collect();
+    if !stale.is_empty() {
+      run(session, &format!("{}rm -f {}", sudo, stale.join(" "))).await?;
+      result.removed = stale.len();
+    }
+  }
+  Ok(result)
+}
+
+/// Parses `sha256sum` output for files under `remote_dir` into relative path -> checksum.
+fn parse_sha256sums(listing: &str, remote_dir: &str) -> HashMap<String, String> {
+  let prefix = format!("{}/", remote_dir);
+  listing
+    .lines()
+    .filter_map(|line| {
+      let (sum, path) = line.split_once(char::is_whitespace)?;
+      let path = path.trim_start_matches([' ', '*']).strip_prefix(&prefix)?;
+      Some((path.to_string(), sum.to_string()))
+    })
+    .collect()
+}
+
+/// Writes the content into a private temp directory over SFTP, then installs it with the requested owner and mode.
+async fn put(session: &Session, content: &[u8], remote_path: &str, options: &UploadOptions) -> Result<(), String> {
+  let temp_dir = run(session, "mktemp -d /tmp/nya-upload-XXXXXX").await
+    .map_err(|e| format!("failed to prepare upload of {}: {}", remote_path, e))?
+    .trim()
+    .to_string();
+  let temp_path = format!("{}/content", temp_dir);
+  if let Err(e) = sftp_write(session, content, &temp_path).await {
+    let _ = run(session, &format!("rm -rf {}", quote(&temp_dir))).await;
+    return Err(format!("failed to upload {}: {}", remote_path, e));
+  }
+
+  let dir = Path::new(remote_path).parent().map(|p| p.display().to_string()).unwrap_or_else(|| "/".to_string());
+  let install = if options.sudo {
+    let owner = match &options.owner {
+      Some(owner) => {
+        let (user, group) = owner.split_once(':').unwrap_or((owner, owner));
+        format!(" -o {} -g {}", quote(user), quote(group))
+      },
+      None => " -o root -g root".to_string(),
+    };
+    format!(
+      "sudo mkdir -p {dir} && sudo install{owner} -m {mode} {temp} {dest}; status=$?; rm -rf {temp_dir}; exit $status",
+      dir = quote(&dir), owner = owner, mode = quote(&options.mode), temp = quote(&temp_path), dest = quote(remote_path), temp_dir = quote(&temp_dir)
+    )
+  } else {
+    format!(
+      "mkdir -p {dir} && install -m {mode} {temp} {dest}; status=$?; rm -rf {temp_dir}; exit $status",
+      dir = quote(&dir), mode = quote(&options.mode), temp = quote(&temp_path), dest = quote(remote_path), temp_dir = quote(&temp_dir)
+    )
+  };
+  run(session, &install).await.map(|_| ())
+}
+
+/// Writes `content` to `path` through the node's SFTP subsystem, on a channel of the existing session.
+async fn sftp_write(session: &Session, content: &[u8], path: &str) -> Result<(), String> {
+  let mut child = session.subsystem("sftp")
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::null())
+    .spawn()
+    .await
+    .map_err(|e| format!("failed to start sftp: {}", e))?;
+  let stdin = child.stdin().take().ok_or("no stdin for sftp")?;
+  let stdout = child.stdout().take().ok_or("no stdout for sftp")?;
+  let sftp = Sftp::new(stdin, stdout, SftpOptions::default()).await.map_err(|e| e.to_string())?;
+
+  let written = async {
+    let mut file = sftp.create(path).await?;
+    file.write_all(content).await?;
+    file.close().await
+  }.await;
+  let closed = sftp.close().await;
+  let _ = child.wait().await;
+  written.and(closed).map_err(|e| e.to_string())
+}
+
+async fn run(session: &Session, command: &str) -> Result<String, String> {
+  let output = session.command("sh")
+    .arg("-c")
+    .arg(command)
+    .output()
+    .await
+    .map_err(|e| e.to_string())?;
+  if !output.status.success() {
+    return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod upload_tests {
+  use crate::ops::upload::{parse_sha256sums, quote, sha256_hex};
+
+  #[test]
+  fn parses_remote_checksums_and_quotes_paths() {
+    let sum = sha256_hex(b"nya");
+    let listing = format!("{sum}  /opt/nya/charts/Chart.yaml\n{sum}  /opt/nya/charts/templates/it's.yaml\n");
+    let sums = parse_sha256sums(&listing, "/opt/nya/charts");
+    assert_eq!(sums.get("Chart.yaml"), Some(&sum));
+    assert_eq!(sums.get("templates/it's.yaml"), Some(&sum));
+    assert_eq!(quote("it's"), r"'it'\''s'");
+  }
+}
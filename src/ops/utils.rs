@@ -1,6 +1,14 @@
-use openssh::{Session, SessionBuilder};
+use colored::*;
+use openssh::{Session, SessionBuilder, Stdio};
 use serde_json::Value;
-use crate::{core::runtime::Nya, ops::types::{BaseNodeConfig, NodeCommandResult}};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use crate::{core::{log::{HostLine, Stream, host_prefix}, payload::Payload, runtime::Nya}, ops::{retry::{RetryPolicy, is_transient}, types::{AddonConfig, BaseNodeConfig, NodeCommandResult}}};
+
+/// How much of a failed command's stderr is kept for the error message.
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Points kubectl and helm on the control plane at the k3s cluster.
+pub const KUBECONFIG: &str = "--kubeconfig=/etc/rancher/k3s/k3s.yaml";
 
 pub async fn get_base_nodes(nya: Nya) -> Vec<BaseNodeConfig> {
   let control_plane_value: Value = nya.get("nya.control_plane").await;
@@ -13,7 +21,7 @@ pub async fn get_base_nodes(nya: Nya) -> Vec<BaseNodeConfig> {
     .unwrap_or(&vec![])
     .iter()
     .map(|node| BaseNodeConfig::new(node.clone()))
-      .filter(|config| config.host.as_str() != &control_plane_host)
+      .filter(|config| config.host.as_str() != control_plane_host)
     .collect();
 
   let mut all_nodes = vec![control_plane.clone()]; 
@@ -34,66 +42,166 @@ pub async fn get_node_configs(nya: Nya) -> Vec<BaseNodeConfig> {
     .unwrap_or(&vec![])
     .iter()
     .map(|node| BaseNodeConfig::new(node.clone()))
-      .filter(|config| config.host.as_str() != &control_plane_host)
+      .filter(|config| config.host.as_str() != control_plane_host)
     .collect();
   nodes
 }
 
-pub async fn create_ssh_session(node: &BaseNodeConfig) -> Session {
+pub async fn get_addons(nya: Nya) -> Vec<AddonConfig> {
+  let addons_value: Value = nya.get("nya.addons").await;
+  addons_value
+    .as_array()
+    .unwrap_or(&vec![])
+    .iter()
+    .filter_map(|addon| match serde_json::from_value::<AddonConfig>(addon.clone()) {
+      Ok(config) => Some(config),
+      Err(e) => {
+        eprintln!("Skipping invalid addon entry {}: {}", addon, e);
+        None
+      },
+    })
+    .collect()
+}
+
+/// True when `nya base build --bundle` was used and installs should come from the uploaded bundle.
+pub async fn is_offline(nya: Nya) -> bool {
+  nya.get("bundle_path").await.as_str().is_some_and(|path| !path.is_empty())
+}
+
+/// Connects to a node, retrying with backoff. When the node stays unreachable
+/// the error is reported and the run is stopped.
+pub async fn create_ssh_session(nya: &Nya, node: &BaseNodeConfig) -> Option<Session> {
+  match try_create_ssh_session(node).await {
+    Ok(session) => Some(session),
+    Err(e) => {
+      eprintln!("{}", format!("Failed to connect to node at {}: {}", node.host, e).red());
+      nya.stop();
+      None
+    },
+  }
+}
+
+pub async fn try_create_ssh_session(node: &BaseNodeConfig) -> Result<Session, String> {
+  connect_with(node, &RetryPolicy::CONNECT).await
+}
+
+pub async fn connect_with(node: &BaseNodeConfig, policy: &RetryPolicy) -> Result<Session, String> {
+  let mut attempt = 1;
+  loop {
     let mut session_builder = SessionBuilder::default();
     session_builder.user(node.user.clone());
     session_builder.keyfile(node.ssh_key_path.clone());
+    session_builder.connect_timeout(policy.timeout);
 
     match session_builder.connect(node.host.clone()).await {
-        Ok(session) => session,
-        Err(e) =>  { 
-          println!("Failed to connect to node at {}: {:?}", node.host, e);
-          panic!("Failed to connect to node");
-        },
+      Ok(session) => return Ok(session),
+      Err(e) if attempt < policy.attempts => {
+        let delay = policy.delay(attempt);
+        eprintln!("{} Connection failed ({:?}), retrying in {}s ({}/{})", host_prefix(&node.host), e, delay.as_secs(), attempt, policy.attempts);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+      },
+      Err(e) => return Err(format!("{:?}", e)),
     }
+  }
 }
 
-pub async fn run_on_node(session: &Session, command: &str) -> NodeCommandResult {
-    match session.command("bash")
-        .arg("-c")
-        .arg(command)
-        .output()
-        .await
-    {
-        Ok(output) => {
-            if !output.status.success() {
-                eprintln!("Command error: {}", String::from_utf8_lossy(&output.stderr));
-                return NodeCommandResult::Failure(String::from_utf8_lossy(&output.stderr).to_string());
-            }
-            NodeCommandResult::Success
-        },
-        Err(e) => {
-          eprintln!("Command error: {}", e.to_string());
-          return NodeCommandResult::Failure(e.to_string());
-
-        },
+/// Runs a command on a node, streaming its output line by line through the `log` event.
+pub async fn run_on_node(nya: &Nya, host: &str, session: &Session, command: &str) -> NodeCommandResult {
+  run_on_node_with(nya, host, session, command, &RetryPolicy::COMMAND).await
+}
+
+pub async fn run_on_node_with(nya: &Nya, host: &str, session: &Session, command: &str, policy: &RetryPolicy) -> NodeCommandResult {
+  match exec_on_node(nya, host, session, command, Stream::Stdout, policy).await {
+    Ok(_) => NodeCommandResult::Success,
+    Err(err) => NodeCommandResult::Failure(err),
+  }
+}
+
+/// Runs a command on a node and returns its stdout. Only stderr is shown while it runs,
+/// stdout goes to the host's log file.
+pub async fn get_from_node(nya: &Nya, host: &str, session: &Session, command: &str) -> Result<String, String> {
+  exec_on_node(nya, host, session, command, Stream::Captured, &RetryPolicy::COMMAND).await
+}
+
+/// Like `get_from_node`, but also shows stdout while it runs. For long scripts whose output is parsed afterwards.
+pub async fn stream_from_node(nya: &Nya, host: &str, session: &Session, command: &str, policy: &RetryPolicy) -> Result<String, String> {
+  exec_on_node(nya, host, session, command, Stream::Stdout, policy).await
+}
+
+/// Retries failures that look transient. A command that times out is not
+/// retried, since it may still be running on the node.
+async fn exec_on_node(nya: &Nya, host: &str, session: &Session, command: &str, stdout_stream: Stream, policy: &RetryPolicy) -> Result<String, String> {
+  let mut attempt = 1;
+  loop {
+    let result = match tokio::time::timeout(policy.timeout, exec_once(nya, host, session, command, stdout_stream)).await {
+      Ok(result) => result,
+      Err(_) => return Err(format!("timed out after {}s", policy.timeout.as_secs())),
+    };
+    match result {
+      Err(err) if attempt < policy.attempts && is_transient(&err) => {
+        let delay = policy.delay(attempt);
+        eprintln!("{} Transient failure, retrying in {}s ({}/{})", host_prefix(host), delay.as_secs(), attempt, policy.attempts);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+      },
+      result => return result,
     }
+  }
 }
 
-pub async fn get_from_node(session: &Session, command: &str) -> Result<String, String> {
-    match session.command("bash")
-        .arg("-c")
-        .arg(command)
-        .output()
-        .await
-    {
-        Ok(output) => {
-            if !output.status.success() {
-                eprintln!("Command error: {}", String::from_utf8_lossy(&output.stderr));
-                return Err(String::from_utf8_lossy(&output.stderr).to_string());
-            }
-            Ok(String::from_utf8_lossy(&output.stdout).to_string())
-        },
-        Err(e) => {
-          eprintln!("Command error: {}", e.to_string());
-          return Err(e.to_string());
-        },
+/// On failure the error holds the last lines of stderr, which have already been streamed.
+async fn exec_once(nya: &Nya, host: &str, session: &Session, command: &str, stdout_stream: Stream) -> Result<String, String> {
+  let mut child = session.command("bash")
+    .arg("-c")
+    .arg(command)
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .await
+    .map_err(|e| {
+      eprintln!("{} Command error: {}", host_prefix(host), e);
+      e.to_string()
+    })?;
+
+  let stdout = child.stdout().take();
+  let stderr = child.stderr().take();
+  let read_stdout = async {
+    let mut captured = String::new();
+    if let Some(stdout) = stdout {
+      let mut lines = BufReader::new(stdout).lines();
+      while let Ok(Some(line)) = lines.next_line().await {
+        captured.push_str(&line);
+        captured.push('\n');
+        nya.emit("log", Payload::new(HostLine { host: host.to_string(), line, stream: stdout_stream })).await;
+      }
     }
+    captured
+  };
+  let read_stderr = async {
+    let mut tail: Vec<String> = Vec::new();
+    if let Some(stderr) = stderr {
+      let mut lines = BufReader::new(stderr).lines();
+      while let Ok(Some(line)) = lines.next_line().await {
+        if tail.len() == STDERR_TAIL_LINES {
+          tail.remove(0);
+        }
+        tail.push(line.clone());
+        nya.emit("log", Payload::new(HostLine { host: host.to_string(), line, stream: Stream::Stderr })).await;
+      }
+    }
+    tail
+  };
+  let (captured, tail) = tokio::join!(read_stdout, read_stderr);
+
+  match child.wait().await {
+    Ok(status) if status.success() => Ok(captured),
+    Ok(_) => Err(tail.join("\n")),
+    Err(e) => {
+      eprintln!("{} Command error: {}", host_prefix(host), e);
+      Err(e.to_string())
+    },
+  }
 }
 
 pub async fn prepare_base_context(nya: Nya) {
@@ -101,11 +209,11 @@ pub async fn prepare_base_context(nya: Nya) {
   if control_plane_value == Value::Null || control_plane_value.as_str().unwrap_or("").is_empty() {
     let control_plane_value: Value = nya.get("nya.control_plane").await;
     let host = control_plane_value.get("host").and_then(|v| v.as_str()).unwrap_or("");
-    let _ = nya.set("nya.registry_host", format!("{}:{}", host.to_string(), "5000".to_string())).await;
+    let _ = nya.set("nya.registry_host", format!("{}:{}", host, "5000")).await;
   }
 
   let control_plane_vars = nya.get("nya.control_plane.vars").await;
-  let k3s_token = control_plane_vars.get("k3s_token").unwrap().to_string();
+  let k3s_token = control_plane_vars.get("k3s_token").and_then(|v| v.as_str()).unwrap_or("").to_string();
   let _ = nya.set("nya.k3s_token", k3s_token).await;
     let control_plane = nya.get("nya.control_plane").await;
     let control_plane_host = control_plane.get("host").and_then(|v| v.as_str()).unwrap_or("");
@@ -0,0 +1,388 @@
+use std::{collections::HashSet, path::PathBuf};
+use colored::*;
+use regex::Regex;
+use serde_json::Value;
+use tera::{Context, Tera};
+
+use crate::{core::{payload::Payload, runtime::Nya, service::{Service, ServiceActions, handle_action}}, ops::{base::{build::{cluster::{BIND9_DB_TEMPLATE, INSTALL_MKCERT_SCRIPT, NAMED_CONF_LOCAL_TEMPLATE, NAMED_CONF_OPTIONS_TEMPLATE}, control_plane::{INSTALL_HELM_SCRIPT, INSTALL_K3S_SCRIPT, K3S_REGISTRIES_TEMPLATE}, ingress::SETUP_INGRESS_SCRIPT, wireguard::{overlay_peers, wireguard_config}}, inventory::load_inventory}, capsule::to_dns_label, ship::{builder::{Builder, build_config, cluster_platforms, pack_platforms}, is_selected, remote::build_node, selected_packs}, utils::prepare_base_context}};
+
+const INITIAL_CONFIG: &str = include_str!("init/initial_config.json");
+
+/// Every template rendered during `base:build`.
+const BASE_TEMPLATES: &[(&str, &str)] = &[
+  ("install_k3s.sh", INSTALL_K3S_SCRIPT),
+  ("registries.yaml", K3S_REGISTRIES_TEMPLATE),
+  ("install_helm.sh", INSTALL_HELM_SCRIPT),
+  ("named.conf.local", NAMED_CONF_LOCAL_TEMPLATE),
+  ("named.conf.options", NAMED_CONF_OPTIONS_TEMPLATE),
+  ("bind9.db", BIND9_DB_TEMPLATE),
+  ("install_mkcert.sh", INSTALL_MKCERT_SCRIPT),
+  ("setup_ingress.sh", SETUP_INGRESS_SCRIPT),
+];
+
+/// Where each template variable comes from in the base config.
+const TEMPLATE_VARS: &[(&str, &str)] = &[
+  ("control_plane_ip", "nya.control_plane.host"),
+  ("k3s_token", "nya.control_plane.vars.k3s_token"),
+  ("registry_host", "nya.registry_host"),
+  ("domain_name", "nya.control_plane.vars.domain_name"),
+  ("domain", "nya.control_plane.vars.domain_name"),
+  ("metallb_ip_range", "nya.control_plane.vars.metallb_ip_range"),
+  ("secret_name", "nya.control_plane.vars.secret_name"),
+];
+
+/// Variables that are detected from the nodes during the build, not read from the config.
+const RUNTIME_VARS: &[(&str, &str)] = &[
+  ("network_cidr", "10.0.0.0/24"),
+  ("node_ip", "10.0.0.1"),
+  ("flannel_iface", ""),
+];
+
+const SHIP_REQUIRED: &[&str] = &[
+  "nya.control_plane.host",
+  "nya.control_plane.user",
+  "nya.control_plane.ssh_private_key_file",
+  "nya.registry_host",
+  "nya.control_plane.vars.domain_name",
+  "nya.control_plane.vars.secret_name",
+];
+
+pub struct NyaValidate;
+
+impl Service for NyaValidate {
+  fn name(&self) -> String {"NyaValidate".to_string()}
+  fn register(&self) -> ServiceActions {
+    vec![
+      ("onValidateBase".to_string(), handle_action(validate_base)),
+      ("onValidateShip".to_string(), handle_action(validate_ship)),
+    ]
+  }
+}
+
+async fn validate_base(nya: Nya, _: Payload) {
+  prepare_base_context(nya.clone()).await;
+  let mut errors: Vec<String> = Vec::new();
+  errors.extend(check_placeholders(&nya));
+  errors.extend(check_nodes(&nya).await);
+  errors.extend(check_templates(&nya, BASE_TEMPLATES).await);
+  errors.extend(check_wireguard(&nya).await);
+  report(&nya, "base config", errors);
+}
+
+async fn validate_ship(nya: Nya, _: Payload) {
+  prepare_base_context(nya.clone()).await;
+  let mut errors: Vec<String> = Vec::new();
+  errors.extend(check_placeholders(&nya));
+  for path in SHIP_REQUIRED.iter() {
+    if as_present(&config_value(&nya, path).await).is_none() {
+      errors.push(format!("`{}` is missing", path));
+    }
+  }
+  errors.extend(check_packs(&nya).await);
+  errors.extend(check_build(&nya).await);
+  report(&nya, "capsule", errors);
+}
+
+fn report(nya: &Nya, subject: &str, errors: Vec<String>) {
+  if errors.is_empty() {
+    return;
+  }
+  eprintln!("{}", format!("Found {} problem(s) with the {}:", errors.len(), subject).red());
+  for error in errors.iter() {
+    eprintln!("  - {}", error);
+  }
+  eprintln!("Fix the values above and run the command again. No nodes were changed.");
+  nya.stop();
+}
+
+/// Looks a dotted path up in the context, falling back to nested fields
+/// for keys that aren't stored flat (e.g. `nya.control_plane.vars.domain_name`).
+async fn config_value(nya: &Nya, path: &str) -> Value {
+  let value = nya.get(path).await;
+  if !value.is_null() {
+    return value;
+  }
+  match path.rsplit_once('.') {
+    Some((parent, field)) => Box::pin(config_value(nya, parent)).await
+      .get(field)
+      .cloned()
+      .unwrap_or(Value::Null),
+    None => Value::Null,
+  }
+}
+
+fn as_present(value: &Value) -> Option<String> {
+  match value {
+    Value::String(s) if !s.trim().is_empty() => Some(s.clone()),
+    Value::Number(n) => Some(n.to_string()),
+    _ => None,
+  }
+}
+
+fn placeholders() -> HashSet<String> {
+  fn collect(value: &Value, found: &mut HashSet<String>) {
+    match value {
+      Value::String(s) if s.starts_with("provide ") => { found.insert(s.clone()); },
+      Value::Array(items) => items.iter().for_each(|item| collect(item, found)),
+      Value::Object(map) => map.values().for_each(|item| collect(item, found)),
+      _ => {},
+    }
+  }
+  let mut found = HashSet::new();
+  if let Ok(initial) = serde_json::from_str::<Value>(INITIAL_CONFIG) {
+    collect(&initial, &mut found);
+  }
+  found
+}
+
+fn find_placeholders(path: &str, value: &Value, known: &HashSet<String>, errors: &mut Vec<String>) {
+  match value {
+    Value::String(s) if known.contains(s) => errors.push(format!("`{}` still has the placeholder value \"{}\"", path, s)),
+    Value::Array(items) => {
+      for (i, item) in items.iter().enumerate() {
+        find_placeholders(&format!("{}[{}]", path, i), item, known, errors);
+      }
+    },
+    Value::Object(map) => {
+      for (key, item) in map.iter() {
+        find_placeholders(&format!("{}.{}", path, key), item, known, errors);
+      }
+    },
+    _ => {},
+  }
+}
+
+fn check_placeholders(nya: &Nya) -> Vec<String> {
+  let known = placeholders();
+  let mut errors = Vec::new();
+  let config: Value = serde_json::from_str(&std::fs::read_to_string(nya.base_config_path()).unwrap_or_default())
+    .unwrap_or(Value::Null);
+  if let Value::Object(map) = config {
+    for (key, value) in map.iter() {
+      find_placeholders(key, value, &known, &mut errors);
+    }
+  }
+  errors
+}
+
+async fn check_nodes(nya: &Nya) -> Vec<String> {
+  let mut errors = Vec::new();
+  let mut nodes: Vec<(String, Value)> = vec![("nya.control_plane".to_string(), nya.get("nya.control_plane").await)];
+  if let Value::Array(items) = nya.get("nya.nodes").await {
+    for (i, node) in items.into_iter().enumerate() {
+      nodes.push((format!("nya.nodes[{}]", i), node));
+    }
+  }
+
+  for (path, node) in nodes.iter() {
+    for field in ["host", "user", "ssh_private_key_file"] {
+      if as_present(node.get(field).unwrap_or(&Value::Null)).is_none() {
+        errors.push(format!("`{}.{}` is missing", path, field));
+      }
+    }
+    if let Some(key) = node.get("ssh_private_key_file").and_then(|v| v.as_str())
+      && !key.starts_with("provide ")
+      && !PathBuf::from(shellexpand::tilde(key).to_string()).exists() {
+      errors.push(format!("`{}.ssh_private_key_file` points to {}, which does not exist", path, key));
+    }
+  }
+  errors
+}
+
+async fn check_wireguard(nya: &Nya) -> Vec<String> {
+  let Some(config) = wireguard_config(nya).await else {
+    return Vec::new();
+  };
+  match overlay_peers(nya, &config).await {
+    Ok(_) => Vec::new(),
+    Err(err) => vec![err],
+  }
+}
+
+async fn check_templates(nya: &Nya, templates: &[(&str, &str)]) -> Vec<String> {
+  let mut context = Context::new();
+  for (name, source) in TEMPLATE_VARS.iter() {
+    if let Some(value) = as_present(&config_value(nya, source).await) {
+      context.insert(*name, &value);
+    }
+  }
+  for (name, stand_in) in RUNTIME_VARS.iter() {
+    context.insert(*name, stand_in);
+  }
+
+  // Missing variable -> templates that need it, in first-seen order
+  let mut missing: Vec<(String, Vec<String>)> = Vec::new();
+  let mut errors = Vec::new();
+  for (template_name, template) in templates.iter() {
+    match missing_template_vars(template, &context) {
+      Ok(vars) => {
+        for var in vars {
+          let source = TEMPLATE_VARS.iter()
+            .find(|(name, _)| *name == var)
+            .map(|(_, source)| source.to_string())
+            .unwrap_or(var);
+          match missing.iter_mut().find(|(s, _)| *s == source) {
+            Some((_, users)) => users.push(template_name.to_string()),
+            None => missing.push((source, vec![template_name.to_string()])),
+          }
+        }
+      },
+      Err(e) => errors.push(format!("{} failed to render: {}", template_name, e)),
+    }
+  }
+
+  for (source, users) in missing {
+    errors.push(format!("`{}` is missing (needed by {})", source, users.join(", ")));
+  }
+  errors
+}
+
+/// Renders a template, filling in each undefined variable as it's found
+/// so every missing variable is reported rather than just the first.
+fn missing_template_vars(template: &str, context: &Context) -> Result<Vec<String>, String> {
+  let undefined = Regex::new(r"Variable `([^`]+)` not found").unwrap();
+  let mut context = context.clone();
+  let mut missing: Vec<String> = Vec::new();
+  loop {
+    match Tera::one_off(template, &context, false) {
+      Ok(_) => return Ok(missing),
+      Err(e) => {
+        let message = error_chain(&e);
+        match undefined.captures(&message).map(|c| c[1].to_string()) {
+          Some(var) if !missing.contains(&var) => {
+            context.insert(var.as_str(), "");
+            missing.push(var);
+          },
+          _ => return Err(message),
+        }
+      },
+    }
+  }
+}
+
+fn error_chain(e: &tera::Error) -> String {
+  let mut messages = vec![e.to_string()];
+  let mut source = std::error::Error::source(e);
+  while let Some(inner) = source {
+    messages.push(inner.to_string());
+    source = inner.source();
+  }
+  messages.join(": ")
+}
+
+async fn check_build(nya: &Nya) -> Vec<String> {
+  let config = build_config(nya).await;
+  let mut errors = Vec::new();
+  if let Some(mode) = config.mode.as_deref().filter(|mode| !["local", "remote"].contains(mode)) {
+    errors.push(format!("`nya.build.mode` is {}, expected local or remote", mode));
+  }
+  let builder = config_value(nya, "nya.build.builder").await;
+  if !builder.is_null() && serde_json::from_value::<Builder>(builder.clone()).is_err() {
+    errors.push(format!("`nya.build.builder` is {}, expected docker, buildx, podman or nerdctl", builder));
+  }
+  if config.is_remote()
+    && let Err(err) = build_node(nya, &config).await {
+    errors.push(err);
+  }
+  errors
+}
+
+async fn check_packs(nya: &Nya) -> Vec<String> {
+  let mut errors = Vec::new();
+  let capsule = nya.get("capsule").await;
+  let Some(packs) = capsule.get("packs").and_then(|p| p.as_array()) else {
+    errors.push("`capsule.packs` is missing".to_string());
+    return errors;
+  };
+  if packs.is_empty() {
+    errors.push("the capsule has no packs, create one with `nya pack new`".to_string());
+  }
+  if let Some(namespace) = capsule.get("namespace").and_then(|v| v.as_str())
+    && to_dns_label(namespace) != namespace {
+    errors.push(format!("`capsule.namespace` is {}, which is not a valid namespace, try {}", namespace, to_dns_label(namespace)));
+  }
+
+  let mut capsule_dir = nya.capsule_path().map(PathBuf::from).unwrap_or_default();
+  capsule_dir.pop();
+  capsule_dir.pop();
+
+  let cluster = build_config(nya).await.platforms
+    .or_else(|| load_inventory().map(|inventory| cluster_platforms(&inventory)))
+    .unwrap_or_default();
+
+  let selection = selected_packs(nya).await;
+  for name in selection.iter() {
+    if !packs.iter().any(|pack| pack.get("name").and_then(|v| v.as_str()) == Some(name.as_str())) {
+      errors.push(format!("the capsule has no pack named {}", name));
+    }
+  }
+
+  for (i, pack) in packs.iter().enumerate() {
+    let name = pack.get("name").and_then(|v| v.as_str()).unwrap_or("");
+    if !name.is_empty() && !is_selected(&selection, name) {
+      continue;
+    }
+    let label = if name.is_empty() { format!("capsule.packs[{}]", i) } else { format!("pack {}", name) };
+    if name.is_empty() {
+      errors.push(format!("`capsule.packs[{}].name` is missing", i));
+    }
+    let Some(location) = pack.get("location").and_then(|v| v.as_str()) else {
+      errors.push(format!("`capsule.packs[{}].location` is missing", i));
+      continue;
+    };
+    let pack_dir = capsule_dir.join(location);
+    for file in ["Dockerfile", "values.yaml"] {
+      if !pack_dir.join(file).exists() {
+        errors.push(format!("{} has no {} at {}", label, file, pack_dir.display()));
+      }
+    }
+
+    let restriction: Option<Vec<String>> = pack.get("platforms").and_then(|v| serde_json::from_value(v.clone()).ok());
+    match pack_platforms(&cluster, restriction.as_deref()) {
+      Ok(platforms) => {
+        let dockerfile = std::fs::read_to_string(pack_dir.join("Dockerfile")).unwrap_or_default();
+        for pinned in pinned_platforms(&dockerfile) {
+          if let Some(other) = platforms.iter().find(|platform| **platform != pinned) {
+            errors.push(format!("{}'s Dockerfile pins FROM to {}, remove `--platform` so it can also build for {}", label, pinned, other));
+          }
+        }
+      },
+      Err(err) => errors.push(format!("{} can't run on any node, {}", label, err)),
+    }
+  }
+  errors
+}
+
+/// Platforms hard-coded with `FROM --platform=...`. `$BUILDPLATFORM` and
+/// other build args are left alone.
+fn pinned_platforms(dockerfile: &str) -> Vec<String> {
+  let re = Regex::new(r"(?im)^\s*FROM\s+--platform=([^\s$]+)\s").unwrap();
+  re.captures_iter(dockerfile).map(|caps| caps[1].to_string()).collect()
+}
+
+#[cfg(test)]
+mod validate_tests {
+  use tera::Context;
+  use crate::ops::validate::{missing_template_vars, pinned_platforms, placeholders};
+
+  #[test]
+  fn reports_every_missing_template_variable() {
+    let mut context = Context::new();
+    context.insert("domain", "nya.local");
+    let missing = missing_template_vars("{{ domain }} {{ k3s_token }} {{ registry_host }}", &context).unwrap();
+    assert_eq!(missing, vec!["k3s_token", "registry_host"]);
+  }
+
+  #[test]
+  fn collects_placeholders_from_initial_config() {
+    let found = placeholders();
+    assert!(found.contains("provide any value"));
+    assert!(!found.contains("v1.4.4"));
+  }
+
+  #[test]
+  fn finds_platforms_pinned_in_from() {
+    let dockerfile = "FROM --platform=linux/amd64 node:20 AS build\nFROM --platform=$BUILDPLATFORM golang:1.22\nFROM nginx\n";
+    assert_eq!(pinned_platforms(dockerfile), ["linux/amd64"]);
+  }
+}
@@ -1,4 +1,5 @@
 use std::{env, path::PathBuf, process::Stdio};
+use sha2::{Digest, Sha256};
 use tokio::process::Command;
 
 use crate::defaults;
@@ -54,26 +55,54 @@ pub fn verify_capsule(user_input: Option<PathBuf>) -> ConfigStatus {
     ConfigStatus::Exists(full_path)
 }
 
+/// An immutable image tag for the pack at `location`: the git short SHA, with
+/// `-dirty` appended when the pack has uncommitted changes, or a hash of the
+/// pack's files when it isn't in a git repository.
 pub fn generate_sha(location: &str) -> String {
-    // Try git sha first
-    let output = std::process::Command::new("git")
-        .args(["rev-parse", "--short", "HEAD"])
+    let git = |args: &[&str]| std::process::Command::new("git")
+        .args(args)
         .current_dir(location)
-        .output();
-        
-    if let Ok(out) = output {
-        if out.status.success() {
-            return String::from_utf8_lossy(&out.stdout).trim().to_string();
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string());
+
+    if let Some(sha) = git(&["rev-parse", "--short", "HEAD"]) {
+        let dirty = git(&["status", "--porcelain", "--", "."]).is_some_and(|status| !status.is_empty());
+        return if dirty { format!("{}-dirty", sha) } else { sha };
+    }
+    content_hash(std::path::Path::new(location))[..12].to_string()
+}
+
+/// A SHA-256 over every file under `dir`, by relative path and content.
+pub fn content_hash(dir: &std::path::Path) -> String {
+    fn collect(dir: &std::path::Path, files: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                if entry.file_name() != ".git" {
+                    collect(&path, files);
+                }
+            } else {
+                files.push(path);
+            }
         }
     }
-    
-    // Fallback to timestamp-based sha
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let ts = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    format!("{:x}", ts)[..7].to_string()
+
+    let mut files = Vec::new();
+    collect(dir, &mut files);
+    files.sort();
+    let mut hasher = Sha256::new();
+    for file in files.iter() {
+        hasher.update(file.strip_prefix(dir).unwrap_or(file).to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(std::fs::read(file).unwrap_or_default());
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
 }
 
 pub async fn run_ssh(host: &str, user: &str, key: &str, cmd: &str) -> Result<(), String> {
@@ -98,4 +127,23 @@ pub async fn run_ssh(host: &str, user: &str, key: &str, cmd: &str) -> Result<(),
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod utils_tests {
+    use crate::utils::content_hash;
+
+    #[test]
+    fn content_hash_follows_file_contents() {
+        let dir = std::env::temp_dir().join(format!("nya-content-hash-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("Dockerfile"), "FROM nginx").unwrap();
+        std::fs::write(dir.join("src/index.html"), "hello").unwrap();
+        let first = content_hash(&dir);
+        assert_eq!(first, content_hash(&dir));
+
+        std::fs::write(dir.join("src/index.html"), "hello again").unwrap();
+        assert_ne!(first, content_hash(&dir));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}